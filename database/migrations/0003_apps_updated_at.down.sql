@@ -0,0 +1,2 @@
+DROP TRIGGER IF EXISTS apps_set_updated_at ON apps;
+DROP FUNCTION IF EXISTS set_updated_at();
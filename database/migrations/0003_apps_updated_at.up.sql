@@ -0,0 +1,11 @@
+CREATE OR REPLACE FUNCTION set_updated_at() RETURNS TRIGGER AS $$
+BEGIN
+  NEW.updated_at = CURRENT_TIMESTAMP;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER apps_set_updated_at
+  BEFORE UPDATE ON apps
+  FOR EACH ROW
+  EXECUTE FUNCTION set_updated_at();
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS apps_user_id_created_at_id_idx;
+DROP INDEX IF EXISTS apps_name_id_idx;
+DROP INDEX IF EXISTS apps_updated_at_id_idx;
+DROP INDEX IF EXISTS apps_created_at_id_idx;
@@ -0,0 +1,5 @@
+-- Keyset pagination orders by the sort column with the id as tie breaker
+CREATE INDEX IF NOT EXISTS apps_created_at_id_idx ON apps (created_at, id);
+CREATE INDEX IF NOT EXISTS apps_updated_at_id_idx ON apps (updated_at, id);
+CREATE INDEX IF NOT EXISTS apps_name_id_idx ON apps (name, id);
+CREATE INDEX IF NOT EXISTS apps_user_id_created_at_id_idx ON apps (user_id, created_at, id);
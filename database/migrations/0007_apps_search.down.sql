@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS apps_name_trgm_idx;
+DROP INDEX IF EXISTS apps_search_vector_idx;
+ALTER TABLE apps DROP COLUMN IF EXISTS search_vector;
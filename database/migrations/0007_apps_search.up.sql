@@ -0,0 +1,13 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Name matches rank above description matches
+ALTER TABLE apps ADD COLUMN IF NOT EXISTS search_vector tsvector
+  GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', coalesce(name, '')), 'A') ||
+    setweight(to_tsvector('english', coalesce(description, '')), 'B')
+  ) STORED;
+
+CREATE INDEX IF NOT EXISTS apps_search_vector_idx ON apps USING GIN (search_vector);
+
+-- Trigram index for typo tolerant name matching
+CREATE INDEX IF NOT EXISTS apps_name_trgm_idx ON apps USING GIN (name gin_trgm_ops);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS app_image_variants (
+  app_id UUID NOT NULL REFERENCES apps(id) ON DELETE CASCADE,
+  variant VARCHAR(20) NOT NULL CHECK (variant IN ('thumbnail', 'card', 'full')),
+  file_name VARCHAR(255) NOT NULL,
+  width INTEGER NOT NULL,
+  height INTEGER NOT NULL,
+  content_type VARCHAR(50) NOT NULL,
+  PRIMARY KEY (app_id, variant)
+);
@@ -0,0 +1,13 @@
+-- Failed logins per account and per client IP address. Accounts are keyed by user id, or by the
+-- lowercased username or email for names that do not exist, so unknown names behave the same.
+CREATE TABLE IF NOT EXISTS login_failures (
+  kind VARCHAR(16) NOT NULL CHECK (kind IN ('account', 'ip')),
+  subject VARCHAR(255) NOT NULL,
+  failures INTEGER NOT NULL DEFAULT 1,
+  last_failure_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  locked_until TIMESTAMP WITH TIME ZONE,
+  PRIMARY KEY (kind, subject)
+);
+
+CREATE INDEX IF NOT EXISTS login_failures_last_failure_at_idx
+  ON login_failures (last_failure_at);
@@ -0,0 +1,8 @@
+-- Theoretical arrival time of the next request per rate limited key, in milliseconds since the
+-- epoch. Rows in the past are back to their full limit and can be deleted.
+CREATE TABLE IF NOT EXISTS rate_limits (
+  key VARCHAR(255) PRIMARY KEY,
+  tat_ms BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS rate_limits_tat_ms_idx ON rate_limits (tat_ms);
@@ -0,0 +1,15 @@
+-- Login attempts against existing accounts. `session_id` is the refresh token family a
+-- successful login started.
+CREATE TABLE IF NOT EXISTS login_events (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  outcome VARCHAR(20) NOT NULL
+    CHECK (outcome IN ('success', 'wrong_password', 'locked', 'unverified')),
+  ip VARCHAR(255) NOT NULL,
+  user_agent VARCHAR(512),
+  session_id UUID,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS login_events_user_id_idx ON login_events (user_id, created_at);
+CREATE INDEX IF NOT EXISTS login_events_session_id_idx ON login_events (session_id);
@@ -0,0 +1,190 @@
+use actix_web::middleware::from_fn;
+use actix_web::web::{Data, Json, Path};
+use actix_web::{HttpResponse, delete, get, post, put, web};
+use uuid::Uuid;
+
+use crate::apierror::{ApiError, ErrorBody};
+use crate::auth::auth_user::AuthUser;
+use crate::auth::login_guard::failures_since;
+use crate::auth::middleware::{RequirePermission, require_auth};
+use crate::auth::role::Permission;
+use crate::config::Config;
+use crate::log;
+use crate::repository::Repositories;
+use crate::requests::set_role_request::SetRoleRequest;
+use crate::tables::apps::Apps;
+use crate::tables::login_failure::{LoginFailure, LoginFailureKind};
+use crate::tables::user::User;
+
+/// Every user. Requires `ViewUsers`.
+#[utoipa::path(
+  tag = "admin",
+  security(("bearer_auth" = [])),
+  responses(
+    (status = 200, description = "All users", body = [User]),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    (status = 403, description = "Missing permission", body = ErrorBody),
+  )
+)]
+#[get("/users", wrap = "RequirePermission(Permission::ViewUsers)")]
+async fn list_users(repo: Data<Repositories>) -> Result<HttpResponse, ApiError> {
+  let users = repo.user.list_users().await?;
+  Ok(HttpResponse::Ok().json(users.iter().map(|u| u.to_json()).collect::<Vec<_>>()))
+}
+
+/// Changes the role of another user. Requires `ManageUsers`.
+#[utoipa::path(
+  tag = "admin",
+  security(("bearer_auth" = [])),
+  request_body = SetRoleRequest,
+  responses(
+    (status = 200, description = "The updated user", body = User),
+    (status = 400, description = "Tried to change the caller's own role", body = ErrorBody),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    (status = 403, description = "Missing permission", body = ErrorBody),
+    (status = 404, description = "User not found", body = ErrorBody),
+  )
+)]
+#[put(
+  "/users/{id}/role",
+  wrap = "RequirePermission(Permission::ManageUsers)"
+)]
+async fn set_user_role(
+  user: AuthUser,
+  id: Path<Uuid>,
+  repo: Data<Repositories>,
+  payload: Json<SetRoleRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let id = id.into_inner();
+  if id == user.0.id {
+    return Err(ApiError::BadRequest(
+      "You cannot change your own role".to_string(),
+    ));
+  }
+
+  let updated = repo
+    .user
+    .set_user_role(id, payload.role)
+    .await
+    .map_err(|e| ApiError::or_not_found(e, "User not found"))?;
+  log::info(&format!(
+    "User {} set the role of {} to {}",
+    user.0.id, updated.id, updated.role
+  ));
+  Ok(HttpResponse::Ok().json(updated.to_json()))
+}
+
+/// Deactivates any app. Requires `ManageApps`.
+#[utoipa::path(
+  tag = "admin",
+  security(("bearer_auth" = [])),
+  responses(
+    (status = 200, description = "The updated app", body = Apps),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    (status = 403, description = "Missing permission", body = ErrorBody),
+    (status = 404, description = "App not found", body = ErrorBody),
+  )
+)]
+#[post(
+  "/apps/{id}/deactivate",
+  wrap = "RequirePermission(Permission::ManageApps)"
+)]
+async fn deactivate_app(
+  user: AuthUser,
+  id: Path<Uuid>,
+  repo: Data<Repositories>,
+) -> Result<HttpResponse, ApiError> {
+  let app = repo
+    .apps
+    .set_app_active(id.into_inner(), false)
+    .await
+    .map_err(|e| ApiError::or_not_found(e, "App not found"))?;
+  log::info(&format!("User {} deactivated app {}", user.0.id, app.id));
+  Ok(HttpResponse::Ok().json(app.to_json()))
+}
+
+/// Accounts and IP addresses with recent failed logins, locked ones first. Requires `ViewUsers`.
+#[utoipa::path(
+  tag = "admin",
+  security(("bearer_auth" = [])),
+  responses(
+    (status = 200, description = "Recent login failures", body = [LoginFailure]),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    (status = 403, description = "Missing permission", body = ErrorBody),
+  )
+)]
+#[get("/lockouts", wrap = "RequirePermission(Permission::ViewUsers)")]
+async fn list_lockouts(
+  repo: Data<Repositories>,
+  config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+  let failures = repo
+    .login_failures
+    .list(failures_since(&config.login_protection))
+    .await?;
+  Ok(HttpResponse::Ok().json(failures))
+}
+
+/// Forgets the failed logins of an account or IP address and lifts its lockout. Requires
+/// `ManageUsers`.
+#[utoipa::path(
+  tag = "admin",
+  security(("bearer_auth" = [])),
+  params(
+    ("kind" = LoginFailureKind, Path, description = "`account` or `ip`"),
+    ("subject" = String, Path, description = "User id, name tried or IP address, as listed"),
+  ),
+  responses(
+    (status = 204, description = "Failures cleared"),
+    (status = 400, description = "Unknown kind", body = ErrorBody),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    (status = 403, description = "Missing permission", body = ErrorBody),
+    (status = 404, description = "No failed logins recorded", body = ErrorBody),
+  )
+)]
+#[delete(
+  "/lockouts/{kind}/{subject}",
+  wrap = "RequirePermission(Permission::ManageUsers)"
+)]
+async fn clear_lockout(
+  user: AuthUser,
+  path: Path<(LoginFailureKind, String)>,
+  repo: Data<Repositories>,
+) -> Result<HttpResponse, ApiError> {
+  let (kind, subject) = path.into_inner();
+  repo
+    .login_failures
+    .clear(kind, &subject)
+    .await
+    .map_err(|e| ApiError::or_not_found(e, "No failed logins recorded"))?;
+  log::info(&format!(
+    "User {} cleared the login failures of {} {}",
+    user.0.id,
+    kind.as_str(),
+    subject
+  ));
+  Ok(HttpResponse::NoContent().finish())
+}
+
+routes!(
+  AdminApi,
+  [
+    list_users,
+    set_user_role,
+    deactivate_app,
+    list_lockouts,
+    clear_lockout
+  ]
+);
+
+pub fn scope() -> actix_web::Scope<
+  impl actix_web::dev::ServiceFactory<
+    actix_web::dev::ServiceRequest,
+    Config = (),
+    Response = actix_web::dev::ServiceResponse<impl actix_web::body::MessageBody>,
+    Error = actix_web::Error,
+    InitError = (),
+  >,
+> {
+  routes(web::scope("/api/admin")).wrap(from_fn(require_auth))
+}
@@ -1,140 +1,737 @@
+use std::time::Duration;
+
 use actix_multipart::Multipart;
-use actix_web::web::{Data, Query};
-use actix_web::{HttpRequest, HttpResponse, get, post, web};
+use actix_web::http::header;
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{HttpResponse, delete, get, patch, post, put, web};
+use chrono::DateTime;
 use uuid::Uuid;
 
-use crate::jwt::jwt::JwtManager;
+use crate::apierror::{ApiError, ErrorBody};
+use crate::auth::auth_user::{AuthUser, OptionalAuthUser, VerifiedUser};
+use crate::auth::role::Permission;
+use crate::config::Config;
+use crate::jwt::claims::Claims;
+use crate::log;
+use crate::media::{self, ImageVariant};
+use crate::middleware::rate_limit::RateLimit;
+use crate::pagination::{Cursor, DEFAULT_LIMIT, MAX_LIMIT, Page};
+use crate::rate_limit::RateLimitGroup;
 use crate::repository::Repositories;
-use crate::requests::create_app_request::CreateAppRequest;
-use crate::{log, tools};
+use crate::requests::create_app_request::{CreateAppRequest, ImageUpload};
+use crate::requests::image_request::ImageRequest;
+use crate::requests::list_apps_request::{AppSort, ListAppsRequest};
+use crate::requests::search_apps_request::SearchAppsRequest;
+use crate::requests::update_app_request::{PatchAppRequest, UpdateAppRequest};
+use crate::storage::{self, Storage};
+use crate::tables::app_image::AppImage;
+use crate::tables::apps::{AppSearchResult, Apps};
+use crate::upload;
 
-#[get("")]
-async fn get_own_apps(
-  req: HttpRequest,
-  repo: Data<Repositories>,
-  jwt: Data<JwtManager>,
-) -> HttpResponse {
-  let auth = req
-    .headers()
-    .get("Authorization")
-    .and_then(|h| h.to_str().ok());
-
-  let token = match auth.and_then(|h| h.strip_prefix("Bearer ")) {
-    Some(t) => t,
-    None => {
-      return {
-        log::debug("Missing or invalid token format");
-        HttpResponse::Unauthorized().body("Missing or invalid token format")
-      };
+/// The requested page size, or the default
+fn page_limit(limit: Option<i64>) -> Result<i64, ApiError> {
+  let limit = limit.unwrap_or(DEFAULT_LIMIT);
+  if !(1..=MAX_LIMIT).contains(&limit) {
+    return Err(ApiError::BadRequest(format!(
+      "limit must be between 1 and {}",
+      MAX_LIMIT
+    )));
+  }
+  Ok(limit)
+}
+
+/// Decodes the request's cursor and checks that it was issued for the same sort
+fn request_cursor(request: &ListAppsRequest) -> Result<Option<Cursor>, ApiError> {
+  match request.cursor.as_deref() {
+    Some(_) if request.offset.is_some() => Err(ApiError::BadRequest(
+      "Use either cursor or offset, not both".to_string(),
+    )),
+    Some(value) => {
+      let invalid = || ApiError::BadRequest("Invalid cursor".to_string());
+      let cursor = Cursor::decode(value).ok_or_else(invalid)?;
+      if cursor.sort != request.sort.as_str() || cursor.order != request.order {
+        return Err(ApiError::BadRequest(
+          "The cursor belongs to a different sort".to_string(),
+        ));
+      }
+      if request.sort != AppSort::Name && DateTime::parse_from_rfc3339(&cursor.value).is_err() {
+        return Err(invalid());
+      }
+      Ok(Some(cursor))
     }
-  };
+    None => Ok(None),
+  }
+}
+
+/// Validates the page size and cursor, then runs the listing. Only the owner of the listed apps
+/// and callers with `ManageApps` see inactive apps; everyone else gets active apps only.
+/// Inactive apps are only visible to their owner and to moderators
+fn sees_inactive(viewer: Option<&Claims>, owner: Option<Uuid>) -> bool {
+  viewer.is_some_and(|claims| owner == Some(claims.id) || claims.role.has(Permission::ManageApps))
+}
 
-  let claims = match jwt.validate_token(token) {
-    Ok(c) => c,
-    Err(_) => {
-      return {
-        log::debug("Invalid token");
-        HttpResponse::Unauthorized().body("Invalid token")
-      };
+/// The app if the viewer may see it. Inactive apps are reported as not found to everyone else,
+/// as if they did not exist.
+async fn visible_app(
+  repo: &Repositories,
+  id: Uuid,
+  viewer: Option<&Claims>,
+) -> Result<Apps, ApiError> {
+  let not_found = |e| ApiError::or_not_found(e, "App not found");
+  let app = repo.apps.get_app_by_id(id).await.map_err(not_found)?;
+  if !app.is_active {
+    let owner = repo.apps.get_app_owner(id).await.map_err(not_found)?;
+    if !sees_inactive(viewer, Some(owner)) {
+      return Err(ApiError::NotFound("App not found".to_string()));
     }
-  };
+  }
+  Ok(app)
+}
+
+async fn list_apps(
+  repo: &Repositories,
+  user_id: Option<Uuid>,
+  viewer: Option<&Claims>,
+  mut request: ListAppsRequest,
+) -> Result<Page<Apps>, ApiError> {
+  if !sees_inactive(viewer, user_id) {
+    if request.is_active == Some(false) {
+      return Err(ApiError::Forbidden(
+        "Only the owner or a moderator can list inactive apps".to_string(),
+      ));
+    }
+    request.is_active = Some(true);
+  }
+
+  let limit = page_limit(request.limit)?;
+  if request.offset.is_some_and(|o| o < 0) {
+    return Err(ApiError::BadRequest(
+      "offset cannot be negative".to_string(),
+    ));
+  }
+
+  let cursor = request_cursor(&request)?;
+
+  Ok(
+    repo
+      .apps
+      .list_apps(user_id, &request, limit, cursor.as_ref())
+      .await?,
+  )
+}
+
+/// Every app
+#[utoipa::path(
+  tag = "apps",
+  params(ListAppsRequest),
+  responses(
+    (status = 200, description = "A page of apps", body = Page<Apps>),
+    (status = 400, description = "Invalid filter or cursor", body = ErrorBody),
+    (status = 403, description = "Inactive apps requested without `ManageApps`", body = ErrorBody),
+  )
+)]
+#[get("/all")]
+async fn get_all_apps(
+  user: OptionalAuthUser,
+  repo: Data<Repositories>,
+  query: Query<ListAppsRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let page = list_apps(&repo, None, user.0.as_ref(), query.into_inner()).await?;
+  Ok(HttpResponse::Ok().json(page))
+}
 
-  let user_id = claims.id;
-  match repo.apps.get_apps_by_user_id(user_id).await {
-    Ok(apps) => HttpResponse::Ok().json(apps),
-    Err(e) => {
-      log::debug(&format!("Failed to retrieve apps: {}", e));
-      HttpResponse::InternalServerError().body(format!("Failed to retrieve apps: {}", e))
-    },
+/// Longest accepted search query
+const MAX_QUERY_LENGTH: usize = 200;
+
+/// Searches the names and descriptions of active apps
+#[utoipa::path(
+  tag = "apps",
+  params(SearchAppsRequest),
+  responses(
+    (status = 200, description = "Matches, best first", body = Page<AppSearchResult>),
+    (status = 400, description = "Missing or invalid query", body = ErrorBody),
+  )
+)]
+#[get("/search")]
+async fn search_apps(
+  repo: Data<Repositories>,
+  query: Query<SearchAppsRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let q = query.q.trim();
+  if q.is_empty() || q.chars().count() > MAX_QUERY_LENGTH {
+    return Err(ApiError::BadRequest(format!(
+      "q must be between 1 and {} characters",
+      MAX_QUERY_LENGTH
+    )));
   }
+  let limit = page_limit(query.limit)?;
+  let offset = query.offset.unwrap_or(0);
+  if offset < 0 {
+    return Err(ApiError::BadRequest(
+      "offset cannot be negative".to_string(),
+    ));
+  }
+
+  let page = repo.apps.search_apps(q, limit, offset).await?;
+  Ok(HttpResponse::Ok().json(page))
 }
 
+/// Apps owned by the authenticated user
+#[utoipa::path(
+  tag = "apps",
+  security(("bearer_auth" = [])),
+  params(ListAppsRequest),
+  responses(
+    (status = 200, description = "A page of the caller's apps", body = Page<Apps>),
+    (status = 400, description = "Invalid filter or cursor", body = ErrorBody),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+  )
+)]
+#[get("")]
+async fn get_own_apps(
+  user: AuthUser,
+  repo: Data<Repositories>,
+  query: Query<ListAppsRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let page = list_apps(&repo, Some(user.0.id), Some(&user.0), query.into_inner()).await?;
+  Ok(HttpResponse::Ok().json(page))
+}
+
+/// A single app. Inactive apps are only shown to their owner and moderators.
+#[utoipa::path(
+  tag = "apps",
+  responses(
+    (status = 200, description = "The app", body = Apps),
+    (status = 404, description = "App not found, or inactive and not the caller's", body = ErrorBody),
+  )
+)]
 #[get("/{id}")]
-async fn get_app_by_id(req: HttpRequest, repo: Data<Repositories>) -> HttpResponse {
-  let id: Uuid = req
-    .match_info()
-    .get("id")
-    .and_then(|id| id.parse().ok())
-    .unwrap_or(Uuid::new_v4());
-
-  match repo.apps.get_app_by_id(id).await {
-    Ok(app) => HttpResponse::Ok().json(app.to_json()),
-    Err(e) => {
-      HttpResponse::InternalServerError().body(format!("Failed to retrieve app by ID: {}", e))
-    }
+async fn get_app_by_id(
+  id: Path<Uuid>,
+  viewer: OptionalAuthUser,
+  repo: Data<Repositories>,
+) -> Result<HttpResponse, ApiError> {
+  let app = visible_app(&repo, id.into_inner(), viewer.0.as_ref()).await?;
+  Ok(HttpResponse::Ok().json(app.to_json()))
+}
+
+/// The stored sizes of an app's image
+#[utoipa::path(
+  tag = "apps",
+  responses(
+    (status = 200, description = "Every variant of the image", body = Vec<AppImage>),
+    (status = 404, description = "App not found, or inactive and not the caller's", body = ErrorBody),
+  )
+)]
+#[get("/{id}/images")]
+async fn get_app_images(
+  id: Path<Uuid>,
+  viewer: OptionalAuthUser,
+  repo: Data<Repositories>,
+) -> Result<HttpResponse, ApiError> {
+  let app = visible_app(&repo, id.into_inner(), viewer.0.as_ref()).await?;
+  let images = repo.apps.get_app_images(app.id).await?;
+  Ok(HttpResponse::Ok().json(images))
+}
+
+/// Redirects to the app image in the requested size, on `/media` or a signed link to the object
+/// store
+#[utoipa::path(
+  tag = "apps",
+  params(ImageRequest),
+  responses(
+    (status = 302, description = "Redirect to the image file"),
+    (status = 404, description = "App or image not found, or the app is inactive and not the caller's", body = ErrorBody),
+  )
+)]
+#[get("/{id}/image")]
+async fn get_app_image(
+  id: Path<Uuid>,
+  viewer: OptionalAuthUser,
+  repo: Data<Repositories>,
+  config: Data<Config>,
+  storage: Data<dyn Storage>,
+  query: Query<ImageRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let app = visible_app(&repo, id.into_inner(), viewer.0.as_ref()).await?;
+  let images = repo.apps.get_app_images(app.id).await?;
+
+  // Apps created before variants existed only have the uploaded file
+  let file_name = match images.into_iter().find(|i| i.variant == query.variant) {
+    Some(image) => image.file_name,
+    None => app.image_name,
+  };
+  if file_name.is_empty() {
+    return Err(ApiError::NotFound("Image not found".to_string()));
   }
+
+  let url = storage.presigned_url(
+    &storage::image_key("app", &file_name),
+    Duration::from_secs(config.storage.presign_expiry_secs),
+  )?;
+  Ok(
+    HttpResponse::Found()
+      .insert_header((header::LOCATION, url))
+      .insert_header((header::CACHE_CONTROL, "no-store"))
+      .finish(),
+  )
 }
 
+/// Apps owned by a user
+#[utoipa::path(
+  tag = "apps",
+  params(("id" = Uuid, Path, description = "User id"), ListAppsRequest),
+  responses(
+    (status = 200, description = "A page of the user's apps", body = Page<Apps>),
+    (status = 400, description = "Invalid filter or cursor", body = ErrorBody),
+    (status = 403, description = "Inactive apps requested by someone else", body = ErrorBody),
+  )
+)]
 #[get("/user/{id}")]
-async fn get_apps_by_user_id(req: HttpRequest, repo: Data<Repositories>) -> HttpResponse {
-  let id: Uuid = req
-    .match_info()
-    .get("id")
-    .and_then(|id| id.parse().ok())
-    .unwrap_or(Uuid::new_v4());
-
-  match repo.apps.get_apps_by_user_id(id).await {
-    Ok(app) => HttpResponse::Ok().json(app),
-    Err(e) => {
-      log::debug(&format!("Failed to retrieve apps by user_id: {}", e));
-      HttpResponse::InternalServerError().body(format!("Failed to retrieve apps by user_id: {}", e))
-    }
-  }
+async fn get_apps_by_user_id(
+  id: Path<Uuid>,
+  user: OptionalAuthUser,
+  repo: Data<Repositories>,
+  query: Query<ListAppsRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let page = list_apps(
+    &repo,
+    Some(id.into_inner()),
+    user.0.as_ref(),
+    query.into_inner(),
+  )
+  .await?;
+  Ok(HttpResponse::Ok().json(page))
 }
 
-#[post("")]
+/// Creates an app with an image. The details are sent in the query string.
+#[utoipa::path(
+  tag = "apps",
+  security(("bearer_auth" = [])),
+  request_body(content = ImageUpload, content_type = "multipart/form-data"),
+  responses(
+    (status = 200, description = "App created", body = String),
+    (status = 400, description = "Invalid input", body = ErrorBody),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    (status = 403, description = "Email address not verified", body = ErrorBody),
+    (status = 413, description = "Image is too large", body = ErrorBody),
+    (status = 415, description = "Image is not a PNG, JPEG, WebP or GIF", body = ErrorBody),
+    (status = 429, description = "Too many uploads, see `Retry-After`", body = ErrorBody),
+  )
+)]
+#[post("", wrap = "RateLimit(RateLimitGroup::Upload)")]
 async fn create_app(
-  req: HttpRequest,
+  user: VerifiedUser,
   repo: Data<Repositories>,
-  jwt: Data<JwtManager>,
+  config: Data<Config>,
+  storage: Data<dyn Storage>,
   mut payload: Multipart,
   query: Query<CreateAppRequest>,
-) -> HttpResponse {
-  let auth = req
-    .headers()
-    .get("Authorization")
-    .and_then(|h| h.to_str().ok());
-
-  let token = match auth.and_then(|h| h.strip_prefix("Bearer ")) {
-    Some(t) => t,
-    None => return HttpResponse::Unauthorized().body("Missing or invalid token format"),
-  };
-
-  let claims = match jwt.validate_token(token) {
-    Ok(c) => c,
-    Err(_) => return HttpResponse::Unauthorized().body("Invalid token"),
-  };
+) -> Result<HttpResponse, ApiError> {
+  let claims = user.0;
+  if query.name.trim().is_empty() {
+    return Err(ApiError::BadRequest("Name cannot be empty".to_string()));
+  }
 
-  let user_id = claims.id;
-  let name = query.name.clone();
-  let description = query.description.clone();
-  let github_url = query.github_url.clone();
-  let image_name = tools::save_image(&mut payload, "app")
-    .await
-    .map_err(|e| HttpResponse::InternalServerError().body(format!("Failed to save image: {}", e)));
+  let upload_name = upload::save_image(&mut payload, &config.media, "app").await?;
+  let images = media::create_variants(storage.as_ref(), &config.media, "app", &upload_name).await?;
+  let image_name = images
+    .iter()
+    .find(|image| image.variant == ImageVariant::Full)
+    .map(|image| image.file_name.clone())
+    .unwrap_or_default();
 
-  let _ = repo
+  let github_url = Some(query.github_url.as_str()).filter(|u| !u.is_empty());
+  if let Err(e) = repo
     .apps
     .add_app(
-      &name,
-      &description,
-      Some(&github_url),
-      &image_name.unwrap(),
-      user_id,
+      &query.name,
+      &query.description,
+      github_url,
+      &image_name,
+      claims.id,
+      &images,
     )
     .await
-    .map_err(|e| HttpResponse::InternalServerError().body(format!("Failed to add app: {}", e)));
+  {
+    media::delete_variants(storage.as_ref(), &images, "app").await;
+    return Err(e.into());
+  }
 
-  HttpResponse::Ok().json(format!(
+  Ok(HttpResponse::Ok().json(format!(
     "'{}' created successfully for user {}",
-    name, claims.sub
-  ))
+    query.name, claims.sub
+  )))
+}
+
+/// Makes sure the app exists and belongs to the user
+async fn check_owner(repo: &Repositories, id: Uuid, user_id: Uuid) -> Result<(), ApiError> {
+  let owner = repo
+    .apps
+    .get_app_owner(id)
+    .await
+    .map_err(|e| ApiError::or_not_found(e, "App not found"))?;
+
+  if owner != user_id {
+    return Err(ApiError::Forbidden("You do not own this app".to_string()));
+  }
+  Ok(())
+}
+
+/// Replaces every editable field of an app
+#[utoipa::path(
+  tag = "apps",
+  security(("bearer_auth" = [])),
+  request_body = UpdateAppRequest,
+  responses(
+    (status = 200, description = "The updated app", body = Apps),
+    (status = 400, description = "Invalid input", body = ErrorBody),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    (status = 403, description = "The app belongs to another user or the email address is not verified", body = ErrorBody),
+    (status = 404, description = "App not found", body = ErrorBody),
+  )
+)]
+#[put("/{id}")]
+async fn update_app(
+  user: VerifiedUser,
+  id: Path<Uuid>,
+  repo: Data<Repositories>,
+  payload: Json<UpdateAppRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let id = id.into_inner();
+  check_owner(&repo, id, user.0.id).await?;
+
+  if payload.name.trim().is_empty() {
+    return Err(ApiError::BadRequest("Name cannot be empty".to_string()));
+  }
+
+  let github_url = payload.github_url.as_deref().filter(|u| !u.is_empty());
+  let app = repo
+    .apps
+    .update_app(
+      id,
+      &payload.name,
+      &payload.description,
+      github_url,
+      payload.is_active,
+    )
+    .await
+    .map_err(|e| ApiError::or_not_found(e, "App not found"))?;
+  Ok(HttpResponse::Ok().json(app.to_json()))
+}
+
+/// Updates only the given fields of an app
+#[utoipa::path(
+  tag = "apps",
+  security(("bearer_auth" = [])),
+  request_body = PatchAppRequest,
+  responses(
+    (status = 200, description = "The updated app", body = Apps),
+    (status = 400, description = "Invalid input", body = ErrorBody),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    (status = 403, description = "The app belongs to another user or the email address is not verified", body = ErrorBody),
+    (status = 404, description = "App not found", body = ErrorBody),
+  )
+)]
+#[patch("/{id}")]
+async fn patch_app(
+  user: VerifiedUser,
+  id: Path<Uuid>,
+  repo: Data<Repositories>,
+  payload: Json<PatchAppRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let id = id.into_inner();
+  check_owner(&repo, id, user.0.id).await?;
+
+  if payload.name.as_deref().is_some_and(|n| n.trim().is_empty()) {
+    return Err(ApiError::BadRequest("Name cannot be empty".to_string()));
+  }
+
+  let github_url = payload
+    .github_url
+    .as_deref()
+    .map(|u| if u.is_empty() { None } else { Some(u) });
+  let app = repo
+    .apps
+    .patch_app(
+      id,
+      payload.name.as_deref(),
+      payload.description.as_deref(),
+      github_url,
+      payload.is_active,
+    )
+    .await
+    .map_err(|e| ApiError::or_not_found(e, "App not found"))?;
+  Ok(HttpResponse::Ok().json(app.to_json()))
 }
 
+/// Marks an app as inactive
+#[utoipa::path(
+  tag = "apps",
+  security(("bearer_auth" = [])),
+  responses(
+    (status = 200, description = "The updated app", body = Apps),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    (status = 403, description = "The app belongs to another user or the email address is not verified", body = ErrorBody),
+    (status = 404, description = "App not found", body = ErrorBody),
+  )
+)]
+#[post("/{id}/deactivate")]
+async fn deactivate_app(
+  user: VerifiedUser,
+  id: Path<Uuid>,
+  repo: Data<Repositories>,
+) -> Result<HttpResponse, ApiError> {
+  let id = id.into_inner();
+  check_owner(&repo, id, user.0.id).await?;
+
+  let app = repo
+    .apps
+    .set_app_active(id, false)
+    .await
+    .map_err(|e| ApiError::or_not_found(e, "App not found"))?;
+  Ok(HttpResponse::Ok().json(app.to_json()))
+}
+
+/// Deletes an app and its image
+#[utoipa::path(
+  tag = "apps",
+  security(("bearer_auth" = [])),
+  responses(
+    (status = 204, description = "App deleted"),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    (status = 403, description = "The app belongs to another user or the email address is not verified", body = ErrorBody),
+    (status = 404, description = "App not found", body = ErrorBody),
+  )
+)]
+#[delete("/{id}")]
+async fn delete_app(
+  user: VerifiedUser,
+  id: Path<Uuid>,
+  repo: Data<Repositories>,
+  storage: Data<dyn Storage>,
+) -> Result<HttpResponse, ApiError> {
+  let id = id.into_inner();
+  check_owner(&repo, id, user.0.id).await?;
+
+  let files = repo
+    .apps
+    .delete_app(id)
+    .await
+    .map_err(|e| ApiError::or_not_found(e, "App not found"))?;
+  for file in files {
+    let key = storage::image_key("app", &file);
+    if let Err(e) = storage.delete(&key).await {
+      log::warn(&format!("Failed to delete {}: {}", key, e));
+    }
+  }
+  Ok(HttpResponse::NoContent().finish())
+}
+
+routes!(
+  AppsApi,
+  [
+    get_all_apps,
+    search_apps,
+    get_app_by_id,
+    get_app_images,
+    get_app_image,
+    get_apps_by_user_id,
+    create_app,
+    get_own_apps,
+    update_app,
+    patch_app,
+    deactivate_app,
+    delete_app,
+  ]
+);
+
 pub fn scope() -> actix_web::Scope {
-  web::scope("/api/apps")
-    .service(get_app_by_id)
-    .service(get_apps_by_user_id)
-    .service(create_app)
-    .service(get_own_apps)
+  routes(web::scope("/api/apps"))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use actix_web::App;
+  use actix_web::test::{TestRequest, call_service, init_service};
+  use actix_web::web::Query;
+
+  use super::*;
+  use crate::auth::auth_user::SessionRevocations;
+  use crate::auth::role::Role;
+  use crate::config::AuthConfig;
+  use crate::jwt::jwt::JwtManager;
+  use crate::pagination::SortOrder;
+  use crate::testing::TestDatabase;
+
+  fn request(query: &str) -> ListAppsRequest {
+    Query::<ListAppsRequest>::from_query(query)
+      .expect("valid query")
+      .into_inner()
+  }
+
+  fn encoded(sort: &str, order: SortOrder, value: &str) -> String {
+    Cursor {
+      sort: sort.to_string(),
+      order,
+      value: value.to_string(),
+      id: Uuid::nil(),
+    }
+    .encode()
+  }
+
+  #[test]
+  fn cursor_is_optional() {
+    assert!(request_cursor(&request("")).unwrap().is_none());
+  }
+
+  #[test]
+  fn cursor_for_the_same_sort_is_accepted() {
+    let value = encoded("created_at", SortOrder::Desc, "2024-05-01T12:00:00Z");
+    let cursor = request_cursor(&request(&format!("cursor={}", value)))
+      .unwrap()
+      .expect("cursor");
+    assert_eq!(cursor.value, "2024-05-01T12:00:00Z");
+
+    let value = encoded("name", SortOrder::Asc, "any name");
+    let query = format!("sort=name&order=asc&cursor={}", value);
+    assert!(request_cursor(&request(&query)).unwrap().is_some());
+  }
+
+  #[test]
+  fn cursor_for_another_sort_is_rejected() {
+    let value = encoded("created_at", SortOrder::Desc, "2024-05-01T12:00:00Z");
+    for query in [
+      format!("sort=updated_at&cursor={}", value),
+      format!("order=asc&cursor={}", value),
+    ] {
+      assert!(matches!(
+        request_cursor(&request(&query)),
+        Err(ApiError::BadRequest(_))
+      ));
+    }
+  }
+
+  #[test]
+  fn tampered_cursor_is_rejected() {
+    let value = encoded("created_at", SortOrder::Desc, "'; DROP TABLE apps; --");
+    for query in [
+      format!("cursor={}", value),
+      "cursor=garbage".to_string(),
+      format!("cursor={}&offset=0", value),
+    ] {
+      assert!(matches!(
+        request_cursor(&request(&query)),
+        Err(ApiError::BadRequest(_))
+      ));
+    }
+  }
+
+  fn claims(id: Uuid, role: Role) -> Claims {
+    Claims {
+      sub: "viewer".to_string(),
+      id,
+      role,
+      email_verified: true,
+      sid: Uuid::nil(),
+      exp: 0,
+    }
+  }
+
+  #[test]
+  fn inactive_apps_are_visible_to_owner_and_moderators() {
+    let owner = Uuid::new_v4();
+    assert!(sees_inactive(Some(&claims(owner, Role::User)), Some(owner)));
+    assert!(sees_inactive(
+      Some(&claims(Uuid::new_v4(), Role::Moderator)),
+      Some(owner)
+    ));
+    assert!(sees_inactive(
+      Some(&claims(Uuid::new_v4(), Role::Admin)),
+      None
+    ));
+    assert!(!sees_inactive(
+      Some(&claims(Uuid::new_v4(), Role::User)),
+      Some(owner)
+    ));
+    assert!(!sees_inactive(Some(&claims(owner, Role::User)), None));
+    assert!(!sees_inactive(None, Some(owner)));
+  }
+
+  #[actix_web::test]
+  #[ignore = "needs PostgreSQL"]
+  async fn inactive_app_is_hidden_by_id() {
+    let db = TestDatabase::create().await;
+    let repos = db.repositories();
+    let owner = repos
+      .user
+      .register_user(
+        "owner".to_string(),
+        "owner@example.com".to_string(),
+        "hash".to_string(),
+        true,
+      )
+      .await
+      .unwrap();
+    repos
+      .apps
+      .add_app("Hidden", "An app", None, "hidden.png", owner, &[])
+      .await
+      .unwrap();
+    let id: Uuid = db
+      .pool
+      .get()
+      .await
+      .unwrap()
+      .query_one("SELECT id FROM apps", &[])
+      .await
+      .unwrap()
+      .get("id");
+    repos.apps.set_app_active(id, false).await.unwrap();
+
+    let jwt = Data::new(JwtManager::new(&AuthConfig {
+      jwt_secret: "secret".to_string(),
+      ..AuthConfig::default()
+    }));
+    let token = |id: Uuid, role: Role| {
+      let token = jwt
+        .generate_token("viewer", id, role, true, Uuid::new_v4())
+        .unwrap();
+      (header::AUTHORIZATION, format!("Bearer {}", token))
+    };
+    let sessions: Arc<dyn SessionRevocations> = repos.tokens.clone();
+    let app = init_service(
+      App::new()
+        .app_data(Data::new(repos))
+        .app_data(Data::from(sessions))
+        .app_data(jwt.clone())
+        .app_data(Data::new(Config::default()))
+        .service(scope()),
+    )
+    .await;
+
+    for uri in [
+      format!("/api/apps/{}", id),
+      format!("/api/apps/{}/images", id),
+    ] {
+      let get = || TestRequest::get().uri(&uri);
+      assert_eq!(call_service(&app, get().to_request()).await.status(), 404);
+      let stranger = get().insert_header(token(Uuid::new_v4(), Role::User));
+      assert_eq!(
+        call_service(&app, stranger.to_request()).await.status(),
+        404
+      );
+      let moderator = get().insert_header(token(Uuid::new_v4(), Role::Moderator));
+      assert_eq!(
+        call_service(&app, moderator.to_request()).await.status(),
+        200
+      );
+      let owned = get().insert_header(token(owner, Role::User));
+      assert_eq!(call_service(&app, owned.to_request()).await.status(), 200);
+    }
+
+    db.remove().await;
+  }
 }
@@ -0,0 +1,257 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use actix_web::web::Data;
+use actix_web::{HttpResponse, get, web};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::storage::{Storage, StorageError};
+
+/// How long a single readiness check may take before it counts as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Whether the server should receive traffic. Set once the server is listening and cleared as
+/// soon as shutdown begins, so load balancers stop routing new requests here.
+#[derive(Default)]
+pub struct Readiness(AtomicBool);
+
+impl Readiness {
+  pub fn is_ready(&self) -> bool {
+    self.0.load(Ordering::SeqCst)
+  }
+
+  pub fn set_ready(&self, ready: bool) {
+    self.0.store(ready, Ordering::SeqCst);
+  }
+}
+
+#[derive(Serialize)]
+struct Check {
+  status: &'static str,
+  latency_ms: f64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+impl Check {
+  fn is_ok(&self) -> bool {
+    self.status == "ok"
+  }
+}
+
+#[derive(Serialize)]
+struct Checks {
+  database: Check,
+  media: Check,
+  storage: Check,
+}
+
+/// Runs a check with a timeout and records how long it took
+async fn run_check<F, E>(check: F) -> Check
+where
+  F: Future<Output = Result<(), E>>,
+  E: std::fmt::Display,
+{
+  let started = Instant::now();
+  let error = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+    Ok(Ok(())) => None,
+    Ok(Err(e)) => Some(e.to_string()),
+    Err(_) => Some(format!("timed out after {}ms", CHECK_TIMEOUT.as_millis())),
+  };
+
+  Check {
+    status: if error.is_none() { "ok" } else { "fail" },
+    latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+    error,
+  }
+}
+
+/// Writes and removes a small file to make sure uploads can be stored
+async fn check_media_dir(config: &Config) -> Result<(), std::io::Error> {
+  let path = config.media.dir.join(format!(".health-{}", Uuid::new_v4()));
+  tokio::fs::write(&path, b"ok").await?;
+  tokio::fs::remove_file(&path).await
+}
+
+/// Lists a prefix that holds no objects, which proves the store is reachable and accepts the
+/// credentials without transferring any data
+async fn check_storage(storage: &dyn Storage) -> Result<(), StorageError> {
+  storage.list("health-check/").await.map(|_| ())
+}
+
+/// A server that is shutting down reports so even while its dependencies are fine
+fn overall_status(ready: bool, checks: &Checks) -> &'static str {
+  if !ready {
+    "shutting_down"
+  } else if checks.database.is_ok() && checks.media.is_ok() && checks.storage.is_ok() {
+    "ok"
+  } else {
+    "unavailable"
+  }
+}
+
+/// Liveness: the process is running and able to answer requests
+#[get("/live")]
+async fn get_live() -> HttpResponse {
+  HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Readiness: the database answers, the media directory is writable and storage is reachable
+#[get("/ready")]
+async fn get_ready(
+  readiness: Data<Readiness>,
+  pool: Data<DbPool>,
+  config: Data<Config>,
+  storage: Data<dyn Storage>,
+) -> HttpResponse {
+  let (database, media, storage) = tokio::join!(
+    run_check(pool.ping()),
+    run_check(check_media_dir(&config)),
+    run_check(check_storage(storage.as_ref()))
+  );
+  let checks = Checks {
+    database,
+    media,
+    storage,
+  };
+
+  let status = overall_status(readiness.is_ready(), &checks);
+  let mut response = if status == "ok" {
+    HttpResponse::Ok()
+  } else {
+    HttpResponse::ServiceUnavailable()
+  };
+  response
+    .insert_header(("Cache-Control", "no-store"))
+    .json(serde_json::json!({ "status": status, "checks": checks }))
+}
+
+pub fn scope() -> actix_web::Scope {
+  web::scope("/health").service(get_live).service(get_ready)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+  use std::sync::Arc;
+
+  use actix_web::App;
+  use actix_web::test::{TestRequest, call_service, init_service, read_body_json};
+
+  use super::*;
+  use crate::storage::LocalStorage;
+  use crate::testing::TestDatabase;
+
+  fn check(ok: bool) -> Check {
+    Check {
+      status: if ok { "ok" } else { "fail" },
+      latency_ms: 0.0,
+      error: None,
+    }
+  }
+
+  fn checks(database: bool, media: bool, storage: bool) -> Checks {
+    Checks {
+      database: check(database),
+      media: check(media),
+      storage: check(storage),
+    }
+  }
+
+  fn temp_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("health-test-{}", Uuid::new_v4()))
+  }
+
+  #[test]
+  fn not_ready_wins_over_the_checks() {
+    assert_eq!(overall_status(true, &checks(true, true, true)), "ok");
+    assert_eq!(
+      overall_status(false, &checks(true, true, true)),
+      "shutting_down"
+    );
+    assert_eq!(
+      overall_status(false, &checks(false, true, true)),
+      "shutting_down"
+    );
+    assert_eq!(
+      overall_status(true, &checks(true, false, true)),
+      "unavailable"
+    );
+  }
+
+  #[actix_web::test]
+  async fn media_check_needs_a_writable_directory() {
+    let dir = temp_dir();
+    let mut config = Config::default();
+    config.media.dir = dir.clone();
+
+    let missing = run_check(check_media_dir(&config)).await;
+    assert_eq!(missing.status, "fail");
+    assert!(missing.error.is_some());
+
+    std::fs::create_dir_all(&dir).unwrap();
+    let check = run_check(check_media_dir(&config)).await;
+    assert!(check.is_ok(), "{:?}", check.error);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[actix_web::test]
+  async fn live_answers_ok() {
+    let app = init_service(App::new().service(scope())).await;
+    let res = call_service(&app, TestRequest::get().uri("/health/live").to_request()).await;
+    assert_eq!(res.status(), 200);
+  }
+
+  #[actix_web::test]
+  #[ignore = "needs PostgreSQL"]
+  async fn ready_follows_the_flag_and_the_checks() {
+    let db = TestDatabase::create().await;
+    let dir = temp_dir();
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut config = Config::default();
+    config.media.dir = dir.clone();
+    let storage: Arc<dyn Storage> = Arc::new(LocalStorage::new(dir.join("storage")));
+    let readiness = Data::new(Readiness::default());
+    let app = init_service(
+      App::new()
+        .app_data(readiness.clone())
+        .app_data(Data::new(db.pool.clone()))
+        .app_data(Data::new(config))
+        .app_data(Data::from(storage))
+        .service(scope()),
+    )
+    .await;
+    let ready = || TestRequest::get().uri("/health/ready").to_request();
+
+    // Not ready until the server is listening
+    let res = call_service(&app, ready()).await;
+    assert_eq!(res.status(), 503);
+    let body: serde_json::Value = read_body_json(res).await;
+    assert_eq!(body["status"], "shutting_down");
+
+    readiness.set_ready(true);
+    let res = call_service(&app, ready()).await;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers().get("cache-control").unwrap(), "no-store");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    let res = call_service(&app, ready()).await;
+    assert_eq!(res.status(), 503);
+    let body: serde_json::Value = read_body_json(res).await;
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["checks"]["media"]["status"], "fail");
+    assert_eq!(body["checks"]["database"]["status"], "ok");
+
+    readiness.set_ready(false);
+    let res = call_service(&app, ready()).await;
+    let body: serde_json::Value = read_body_json(res).await;
+    assert_eq!(body["status"], "shutting_down");
+
+    db.remove().await;
+  }
+}
@@ -0,0 +1,363 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::body::SizedStream;
+use actix_web::http::header::{
+  self, CacheControl, CacheDirective, ContentRange, ContentRangeSpec, ETag, EntityTag, Header,
+  HttpDate, IfModifiedSince, IfNoneMatch, IfRange, LastModified, Range,
+};
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse, route, web};
+use futures_util::{StreamExt, stream};
+
+use crate::apierror::ApiError;
+use crate::storage::Storage;
+use crate::upload::ImageFormat;
+
+/// Stored files never change because their names are random, so clients may keep them for a year
+const MAX_AGE_SECS: u32 = 365 * 24 * 60 * 60;
+
+/// Serves stored files, e.g. `/media/images/app/{image_name}`. Supports conditional requests and
+/// a single byte range.
+#[route("/media/{path:.*}", method = "GET", method = "HEAD")]
+pub async fn get_media(
+  req: HttpRequest,
+  path: web::Path<String>,
+  storage: Data<dyn Storage>,
+) -> Result<HttpResponse, ApiError> {
+  let key = path.into_inner();
+  let meta = storage.head(&key).await?;
+
+  let length = meta.size;
+  // HTTP dates have second precision, so compare and send the truncated time
+  let modified_secs = meta
+    .last_modified
+    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+    .map(|d| d.as_secs());
+  let modified = modified_secs.map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+  let etag = EntityTag::new_strong(format!("{:x}-{:x}", length, modified_secs.unwrap_or(0)));
+
+  let mut res = HttpResponse::Ok();
+  res
+    .insert_header(ETag(etag.clone()))
+    .insert_header(CacheControl(vec![
+      CacheDirective::Public,
+      CacheDirective::MaxAge(MAX_AGE_SECS),
+      CacheDirective::Extension("immutable".to_string(), None),
+    ]))
+    .insert_header((header::ACCEPT_RANGES, "bytes"))
+    .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"));
+  if let Some(modified) = modified {
+    res.insert_header(LastModified(HttpDate::from(modified)));
+  }
+
+  if not_modified(&req, &etag, modified) {
+    return Ok(res.status(StatusCode::NOT_MODIFIED).finish());
+  }
+
+  res.content_type(content_type(&key));
+
+  let range = match requested_range(&req, &etag, modified, length) {
+    RangeRequest::Full => None,
+    RangeRequest::Partial(first, last) => {
+      res
+        .status(StatusCode::PARTIAL_CONTENT)
+        .insert_header(ContentRange(ContentRangeSpec::Bytes {
+          range: Some((first, last)),
+          instance_length: Some(length),
+        }));
+      Some((first, last))
+    }
+    RangeRequest::Unsatisfiable => {
+      return Ok(
+        res
+          .status(StatusCode::RANGE_NOT_SATISFIABLE)
+          .insert_header(ContentRange(ContentRangeSpec::Bytes {
+            range: None,
+            instance_length: Some(length),
+          }))
+          .finish(),
+      );
+    }
+  };
+
+  let size = range.map_or(length, |(first, last)| last - first + 1);
+  // The length of a HEAD response comes from its body, which the server does not send
+  let body = if req.method() == Method::HEAD {
+    stream::empty().boxed()
+  } else {
+    storage.get(&key, range).await?
+  };
+  Ok(res.body(SizedStream::new(size, body)))
+}
+
+fn content_type(key: &str) -> &'static str {
+  let format = match key.rsplit_once('.').map(|(_, extension)| extension) {
+    Some("png") => Some(ImageFormat::Png),
+    Some("jpg" | "jpeg") => Some(ImageFormat::Jpeg),
+    Some("webp") => Some(ImageFormat::WebP),
+    Some("gif") => Some(ImageFormat::Gif),
+    _ => None,
+  };
+  format.map_or("application/octet-stream", |f| f.mime_type())
+}
+
+/// `If-None-Match` takes precedence over `If-Modified-Since`
+fn not_modified(req: &HttpRequest, etag: &EntityTag, modified: Option<SystemTime>) -> bool {
+  if req.headers().contains_key(header::IF_NONE_MATCH) {
+    return match IfNoneMatch::parse(req) {
+      Ok(IfNoneMatch::Any) => true,
+      Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+      Err(_) => false,
+    };
+  }
+  match (IfModifiedSince::parse(req), modified) {
+    (Ok(IfModifiedSince(since)), Some(modified)) => modified <= SystemTime::from(since),
+    _ => false,
+  }
+}
+
+enum RangeRequest {
+  Full,
+  /// First and last byte, inclusive
+  Partial(u64, u64),
+  Unsatisfiable,
+}
+
+/// Only single byte ranges are supported; the whole file is sent for anything else, and when an
+/// `If-Range` validator no longer matches
+fn requested_range(
+  req: &HttpRequest,
+  etag: &EntityTag,
+  modified: Option<SystemTime>,
+  length: u64,
+) -> RangeRequest {
+  let Ok(Range::Bytes(ranges)) = Range::parse(req) else {
+    return RangeRequest::Full;
+  };
+  if req.headers().contains_key(header::IF_RANGE) {
+    let current = match IfRange::parse(req) {
+      Ok(IfRange::EntityTag(tag)) => tag.strong_eq(etag),
+      Ok(IfRange::Date(date)) => modified.is_some_and(|m| m == SystemTime::from(date)),
+      Err(_) => false,
+    };
+    if !current {
+      return RangeRequest::Full;
+    }
+  }
+
+  match ranges.as_slice() {
+    [range] => match range.to_satisfiable_range(length) {
+      Some((first, last)) => RangeRequest::Partial(first, last),
+      None => RangeRequest::Unsatisfiable,
+    },
+    _ => RangeRequest::Full,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::{Path, PathBuf};
+  use std::sync::Arc;
+
+  use actix_web::body::{BodySize, MessageBody};
+  use actix_web::dev::ServiceResponse;
+  use actix_web::web::Bytes;
+  use actix_web::{App, test};
+  use uuid::Uuid;
+
+  use super::*;
+  use crate::storage::LocalStorage;
+
+  const KEY: &str = "images/app/test.png";
+
+  /// 1000 bytes counting up from 0, so every byte tells its offset modulo 256
+  fn content() -> Vec<u8> {
+    (0..1000u32).map(|i| i as u8).collect()
+  }
+
+  /// A storage root holding the test file
+  async fn root() -> PathBuf {
+    let root = std::env::temp_dir().join(format!("media-test-{}", Uuid::new_v4()));
+    LocalStorage::new(root.clone())
+      .put(KEY, Bytes::from(content()), "image/png")
+      .await
+      .unwrap();
+    root
+  }
+
+  async fn call(root: &Path, req: test::TestRequest) -> ServiceResponse {
+    let storage: Arc<dyn Storage> = Arc::new(LocalStorage::new(root.to_path_buf()));
+    let app = test::init_service(App::new().app_data(Data::from(storage)).service(get_media)).await;
+    test::call_service(&app, req.to_request()).await
+  }
+
+  fn header(res: &ServiceResponse, name: header::HeaderName) -> &str {
+    res
+      .headers()
+      .get(name)
+      .and_then(|v| v.to_str().ok())
+      .unwrap_or_default()
+  }
+
+  async fn get(root: &Path, headers: &[(header::HeaderName, String)]) -> ServiceResponse {
+    let mut req = test::TestRequest::get().uri(&format!("/media/{}", KEY));
+    for (name, value) in headers {
+      req = req.insert_header((name.clone(), value.clone()));
+    }
+    call(root, req).await
+  }
+
+  /// The ETag and Last-Modified of the test file
+  async fn validators(root: &Path) -> (String, String) {
+    let res = get(root, &[]).await;
+    (
+      header(&res, header::ETAG).to_string(),
+      header(&res, header::LAST_MODIFIED).to_string(),
+    )
+  }
+
+  #[actix_web::test]
+  async fn serves_the_whole_file() {
+    let root = root().await;
+
+    let res = get(&root, &[]).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(header(&res, header::CONTENT_TYPE), "image/png");
+    assert_eq!(header(&res, header::ACCEPT_RANGES), "bytes");
+    assert_eq!(res.response().body().size(), BodySize::Sized(1000));
+    assert!(header(&res, header::ETAG).starts_with("\"3e8-"));
+    assert_eq!(test::read_body(res).await, content());
+
+    let req = test::TestRequest::default()
+      .method(Method::HEAD)
+      .uri(&format!("/media/{}", KEY));
+    let res = call(&root, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    // The length is announced, but no body is sent
+    assert_eq!(res.response().body().size(), BodySize::Sized(1000));
+    assert!(test::read_body(res).await.is_empty());
+
+    let req = test::TestRequest::get().uri("/media/images/app/missing.png");
+    let res = call(&root, req).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    std::fs::remove_dir_all(&root).ok();
+  }
+
+  #[actix_web::test]
+  async fn serves_single_byte_ranges() {
+    let root = root().await;
+
+    for (range, first, last) in [
+      ("bytes=0-99", 0, 99),
+      ("bytes=900-", 900, 999),
+      ("bytes=-10", 990, 999),
+      ("bytes=500-5000", 500, 999),
+    ] {
+      let res = get(&root, &[(header::RANGE, range.to_string())]).await;
+      assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT, "{}", range);
+      assert_eq!(
+        header(&res, header::CONTENT_RANGE),
+        format!("bytes {}-{}/1000", first, last)
+      );
+      let body = test::read_body(res).await;
+      assert_eq!(body, content()[first..=last], "{}", range);
+    }
+    std::fs::remove_dir_all(&root).ok();
+  }
+
+  #[actix_web::test]
+  async fn rejects_unsatisfiable_ranges() {
+    let root = root().await;
+
+    for range in ["bytes=1000-", "bytes=2000-3000"] {
+      let res = get(&root, &[(header::RANGE, range.to_string())]).await;
+      assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE, "{}", range);
+      assert_eq!(header(&res, header::CONTENT_RANGE), "bytes */1000");
+      assert!(test::read_body(res).await.is_empty());
+    }
+    std::fs::remove_dir_all(&root).ok();
+  }
+
+  #[actix_web::test]
+  async fn sends_the_whole_file_for_other_ranges() {
+    let root = root().await;
+
+    for range in ["bytes=0-1,5-6", "items=0-1", "bytes=abc"] {
+      let res = get(&root, &[(header::RANGE, range.to_string())]).await;
+      assert_eq!(res.status(), StatusCode::OK, "{}", range);
+      assert_eq!(test::read_body(res).await.len(), 1000);
+    }
+    std::fs::remove_dir_all(&root).ok();
+  }
+
+  #[actix_web::test]
+  async fn answers_conditional_requests() {
+    let root = root().await;
+    let (etag, modified) = validators(&root).await;
+
+    let not_modified = [
+      vec![(header::IF_NONE_MATCH, etag.clone())],
+      vec![(header::IF_NONE_MATCH, format!("W/{}", etag))],
+      vec![(header::IF_NONE_MATCH, format!("\"other\", {}", etag))],
+      vec![(header::IF_NONE_MATCH, "*".to_string())],
+      vec![(header::IF_MODIFIED_SINCE, modified.clone())],
+    ];
+    for headers in not_modified {
+      let res = get(&root, &headers).await;
+      assert_eq!(res.status(), StatusCode::NOT_MODIFIED, "{:?}", headers);
+      assert_eq!(header(&res, header::ETAG), etag);
+      assert!(test::read_body(res).await.is_empty());
+    }
+
+    let modified_since = [
+      vec![(header::IF_NONE_MATCH, "\"other\"".to_string())],
+      vec![(
+        header::IF_MODIFIED_SINCE,
+        "Mon, 01 Jan 2001 00:00:00 GMT".to_string(),
+      )],
+      // If-None-Match takes precedence over a matching If-Modified-Since
+      vec![
+        (header::IF_NONE_MATCH, "\"other\"".to_string()),
+        (header::IF_MODIFIED_SINCE, modified.clone()),
+      ],
+    ];
+    for headers in modified_since {
+      let res = get(&root, &headers).await;
+      assert_eq!(res.status(), StatusCode::OK, "{:?}", headers);
+    }
+    std::fs::remove_dir_all(&root).ok();
+  }
+
+  #[actix_web::test]
+  async fn honours_if_range_only_while_the_file_is_unchanged() {
+    let root = root().await;
+    let (etag, modified) = validators(&root).await;
+    let range = (header::RANGE, "bytes=0-9".to_string());
+
+    for if_range in [etag.clone(), modified] {
+      let res = get(
+        &root,
+        &[range.clone(), (header::IF_RANGE, if_range.clone())],
+      )
+      .await;
+      assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT, "{}", if_range);
+    }
+
+    // A changed validator, a weak tag or an older date means the whole file is sent
+    for if_range in [
+      "\"other\"".to_string(),
+      format!("W/{}", etag),
+      "Mon, 01 Jan 2001 00:00:00 GMT".to_string(),
+    ] {
+      let res = get(
+        &root,
+        &[range.clone(), (header::IF_RANGE, if_range.clone())],
+      )
+      .await;
+      assert_eq!(res.status(), StatusCode::OK, "{}", if_range);
+      assert_eq!(test::read_body(res).await.len(), 1000);
+    }
+    std::fs::remove_dir_all(&root).ok();
+  }
+}
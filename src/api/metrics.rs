@@ -0,0 +1,85 @@
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse, get};
+
+use crate::apierror::ApiError;
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::metrics;
+use crate::tools::sha256_hex;
+
+/// Metrics in the Prometheus text format. Requires `Authorization: Bearer <metrics.token>`, and
+/// answers 404 while no token is configured.
+#[get("/metrics")]
+pub async fn get_metrics(
+  req: HttpRequest,
+  pool: Data<DbPool>,
+  config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+  check_token(&req, &config.metrics.token)?;
+  Ok(
+    HttpResponse::Ok()
+      .content_type("text/plain; version=0.0.4; charset=utf-8")
+      .body(metrics::render(&pool)),
+  )
+}
+
+/// Lets the request through only with `Authorization: Bearer <token>`
+fn check_token(req: &HttpRequest, token: &str) -> Result<(), ApiError> {
+  if token.is_empty() {
+    return Err(ApiError::NotFound("Route not found".to_string()));
+  }
+
+  let given = req
+    .headers()
+    .get(AUTHORIZATION)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.strip_prefix("Bearer "));
+  // Comparing digests keeps the time taken independent of how much of the token matched
+  if given.is_none_or(|given| sha256_hex(given) != sha256_hex(token)) {
+    return Err(ApiError::Unauthorized(
+      "A valid metrics token is required".to_string(),
+    ));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use actix_web::test::TestRequest;
+
+  use super::*;
+
+  fn request(authorization: Option<&str>) -> HttpRequest {
+    let mut req = TestRequest::get().uri("/metrics");
+    if let Some(value) = authorization {
+      req = req.insert_header((AUTHORIZATION, value));
+    }
+    req.to_http_request()
+  }
+
+  #[test]
+  fn hidden_without_a_configured_token() {
+    let result = check_token(&request(Some("Bearer ")), "");
+    assert!(matches!(result, Err(ApiError::NotFound(_))));
+  }
+
+  #[test]
+  fn requires_the_configured_bearer_token() {
+    for authorization in [
+      None,
+      Some("Bearer wrong"),
+      Some("Bearer s3cret "),
+      Some("Basic s3cret"),
+      Some("s3cret"),
+    ] {
+      let result = check_token(&request(authorization), "s3cret");
+      assert!(
+        matches!(result, Err(ApiError::Unauthorized(_))),
+        "{:?} was accepted",
+        authorization
+      );
+    }
+    assert!(check_token(&request(Some("Bearer s3cret")), "s3cret").is_ok());
+  }
+}
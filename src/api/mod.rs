@@ -1,3 +1,22 @@
+/// Declares the handlers of an API module once. `routes` registers them on a scope and `$api`
+/// documents the same handlers, so a route cannot be served without being in the OpenAPI spec.
+macro_rules! routes {
+  ($api:ident, [$($handler:ident),* $(,)?]) => {
+    #[derive(utoipa::OpenApi)]
+    #[openapi(paths($($handler),*))]
+    pub struct $api;
+
+    fn routes(scope: actix_web::Scope) -> actix_web::Scope {
+      scope$(.service($handler))*
+    }
+  };
+}
+
+pub mod admin;
 pub mod apps;
 pub mod example;
-pub mod user;
\ No newline at end of file
+pub mod health;
+pub mod media;
+pub mod metrics;
+pub mod openapi;
+pub mod user;
@@ -0,0 +1,192 @@
+use std::sync::LazyLock;
+
+use actix_web::{HttpResponse, get};
+use utoipa::openapi::OpenApi as OpenApiDoc;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::api::{admin, apps, user};
+use crate::apierror::ErrorBody;
+
+#[derive(OpenApi)]
+#[openapi(
+  info(title = "Rust REST API"),
+  nest(
+    (path = "/api/apps", api = apps::AppsApi),
+    (path = "/api/users", api = user::UserApi),
+    (path = "/api/admin", api = admin::AdminApi),
+  ),
+  components(schemas(ErrorBody)),
+  modifiers(&BearerAuth),
+  tags(
+    (name = "apps", description = "Apps and their images"),
+    (name = "users", description = "Registration and authentication"),
+    (name = "admin", description = "Routes that require a role permission"),
+  )
+)]
+pub struct ApiDoc;
+
+/// Registers the `Authorization: Bearer` scheme used by the `security` of each operation
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+  fn modify(&self, openapi: &mut OpenApiDoc) {
+    let components = openapi.components.get_or_insert_with(Default::default);
+    let mut scheme = Http::new(HttpAuthScheme::Bearer);
+    scheme.bearer_format = Some("JWT".to_string());
+    components.add_security_scheme("bearer_auth", SecurityScheme::Http(scheme));
+  }
+}
+
+static SPEC: LazyLock<String> = LazyLock::new(|| {
+  ApiDoc::openapi()
+    .to_pretty_json()
+    .expect("OpenAPI document must serialize")
+});
+
+/// The OpenAPI 3 document of every `/api` route
+#[get("/api/openapi.json")]
+pub async fn get_openapi() -> HttpResponse {
+  HttpResponse::Ok()
+    .content_type("application/json")
+    .body(SPEC.as_str())
+}
+
+/// Handles `openapi` from the command line by printing the document. Returns the process exit
+/// code.
+pub fn run_command(args: &[String]) -> i32 {
+  match args.first().map(String::as_str) {
+    None => {
+      println!("{}", SPEC.as_str());
+      0
+    }
+    Some(other) => {
+      eprintln!("Unknown openapi command: {}", other);
+      2
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use actix_web::http::{Method, StatusCode};
+  use actix_web::{App, HttpResponse, test, web};
+  use async_trait::async_trait;
+  use utoipa::OpenApi;
+  use utoipa::openapi::HttpMethod;
+  use uuid::Uuid;
+
+  use super::ApiDoc;
+  use crate::api::{admin, apps, user};
+  use crate::auth::auth_user::SessionRevocations;
+  use crate::auth::role::Role;
+  use crate::config::{AuthConfig, Config};
+  use crate::dberror::DbError;
+  use crate::jwt::jwt::JwtManager;
+  use crate::rate_limit::{MemoryStore, RateLimitStore};
+
+  /// Keeps the check's session valid without a database
+  struct NoRevocations;
+
+  #[async_trait]
+  impl SessionRevocations for NoRevocations {
+    async fn is_revoked(&self, _: Uuid) -> Result<bool, DbError> {
+      Ok(false)
+    }
+  }
+
+  fn to_method(method: &HttpMethod) -> Method {
+    match method {
+      HttpMethod::Get => Method::GET,
+      HttpMethod::Post => Method::POST,
+      HttpMethod::Put => Method::PUT,
+      HttpMethod::Delete => Method::DELETE,
+      HttpMethod::Options => Method::OPTIONS,
+      HttpMethod::Head => Method::HEAD,
+      HttpMethod::Patch => Method::PATCH,
+      HttpMethod::Trace => Method::TRACE,
+    }
+  }
+
+  /// Sends a request for every documented operation to the real scopes and returns the ones no
+  /// route answers. The document is built from the same handler lists as the scopes, so this
+  /// catches paths and methods that differ between `#[utoipa::path]` and the route macros.
+  async fn check_routes() -> Vec<String> {
+    // Unmatched requests get a status no handler uses
+    let sentinel = StatusCode::IM_A_TEAPOT;
+    let jwt = JwtManager::new(&AuthConfig {
+      jwt_secret: Uuid::new_v4().to_string(),
+      ..Default::default()
+    });
+    // An admin token so scopes behind `require_auth` route the request instead of rejecting it
+    let token = jwt
+      .generate_token("openapi-check", Uuid::nil(), Role::Admin, true, Uuid::nil())
+      .expect("token for the route check");
+
+    let rate_limits: Arc<dyn RateLimitStore> = Arc::new(MemoryStore::default());
+    let sessions: Arc<dyn SessionRevocations> = Arc::new(NoRevocations);
+
+    let app = test::init_service(
+      App::new()
+        .app_data(web::Data::new(jwt))
+        .app_data(web::Data::from(sessions))
+        .app_data(web::Data::new(Config::default()))
+        .app_data(web::Data::from(rate_limits))
+        .service(apps::scope())
+        .service(user::scope())
+        .service(admin::scope())
+        .default_service(web::to(move || async move { HttpResponse::new(sentinel) })),
+    )
+    .await;
+
+    let mut problems = Vec::new();
+    for (path, item) in ApiDoc::openapi().paths.paths {
+      let operations = [
+        (HttpMethod::Get, item.get.is_some()),
+        (HttpMethod::Post, item.post.is_some()),
+        (HttpMethod::Put, item.put.is_some()),
+        (HttpMethod::Patch, item.patch.is_some()),
+        (HttpMethod::Delete, item.delete.is_some()),
+      ];
+      for (method, documented) in operations {
+        if !documented {
+          continue;
+        }
+        let uri = path
+          .split('/')
+          .map(|segment| {
+            if segment.starts_with('{') {
+              Uuid::nil().to_string()
+            } else {
+              segment.to_string()
+            }
+          })
+          .collect::<Vec<_>>()
+          .join("/");
+
+        let req = test::TestRequest::default()
+          .method(to_method(&method))
+          .uri(&uri)
+          .insert_header(("Authorization", format!("Bearer {}", token)))
+          .to_request();
+        let res = test::call_service(&app, req).await;
+        if res.status() == sentinel {
+          problems.push(format!(
+            "{} {} is documented but no route handles it",
+            to_method(&method),
+            path
+          ));
+        }
+      }
+    }
+    problems
+  }
+
+  #[actix_web::test]
+  async fn documented_routes_exist() {
+    let problems = check_routes().await;
+    assert!(problems.is_empty(), "{}", problems.join("\n"));
+  }
+}
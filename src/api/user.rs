@@ -1,132 +1,438 @@
+use std::sync::{Arc, LazyLock};
+
 use actix_web::{
-  HttpRequest, HttpResponse, get, post,
-  web::{self, Data, Json},
+  HttpRequest, HttpResponse, delete, get,
+  http::header,
+  post, put,
+  web::{self, Data, Json, Path, Query},
 };
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-  jwt::jwt::JwtManager,
+  apierror::{ApiError, ErrorBody},
+  auth::{auth_user::AuthUser, login_guard::LoginGuard},
+  config::Config,
+  dberror::DbError,
+  jwt::jwt::{JwtManager, TokenPurpose},
   log,
+  mail::{self, Email, Mailer},
+  metrics,
+  middleware::rate_limit::RateLimit,
+  rate_limit::RateLimitGroup,
   repository::Repositories,
-  requests::{login_request::LoginRequest, register_request::RegisterRequest},
-  tools::{is_valid_email, is_valid_username},
+  requests::{
+    login_request::LoginRequest,
+    password_request::{ChangePasswordRequest, ForgotPasswordRequest, ResetPasswordRequest},
+    refresh_request::RefreshRequest,
+    register_request::RegisterRequest,
+    verify_email_request::{ResendVerificationRequest, VerifyEmailRequest},
+  },
+  tables::{
+    login_event::{LoginEvent, LoginOutcome, Session},
+    user::User,
+  },
+  tools::{client_ip, is_valid_email, is_valid_username, sha256_hex},
 };
 
+/// Login attempts returned with the sessions
+const RECENT_LOGINS: i64 = 20;
+
+/// Longest user agent kept in the login history
+const MAX_USER_AGENT_LEN: usize = 512;
+
+/// Checked instead of a real password hash for unknown users, so that they take as long to reject
+/// as a wrong password
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+  bcrypt::hash("not a real password", bcrypt::DEFAULT_COST).expect("hashing a constant")
+});
+
+/// The authenticated user
+#[utoipa::path(
+  tag = "users",
+  security(("bearer_auth" = [])),
+  responses(
+    (status = 200, description = "The caller", body = User),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+  )
+)]
 #[get("")]
-async fn get_user(
+async fn get_user(user: AuthUser, repo: Data<Repositories>) -> Result<HttpResponse, ApiError> {
+  let user_row = repo
+    .user
+    .get_user_id(user.0.id)
+    .await
+    .map_err(|e| ApiError::or_not_found(e, "User not found"))?;
+
+  Ok(HttpResponse::Ok().json(user_row.to_json()))
+}
+
+/// Exchanges a username and password for an access and refresh token. Unknown users and wrong
+/// passwords get the same response. Repeated failures delay and then lock further attempts for
+/// the account and the client IP address.
+#[utoipa::path(
+  tag = "users",
+  request_body = LoginRequest,
+  responses(
+    (status = 200, description = "Logged in", body = TokenPair),
+    (status = 401, description = "Unknown user or wrong password", body = ErrorBody),
+    (status = 403, description = "Email address not verified and unverified logins are disabled", body = ErrorBody),
+    (status = 429, description = "Too many attempts or failed attempts, see `Retry-After`", body = ErrorBody),
+  )
+)]
+#[post("/login", wrap = "RateLimit(RateLimitGroup::Login)")]
+async fn user_login(
   req: HttpRequest,
   repo: Data<Repositories>,
+  payload: Json<LoginRequest>,
   jwt: Data<JwtManager>,
-) -> HttpResponse {
-  let auth = req
-    .headers()
-    .get("Authorization")
-    .and_then(|h| h.to_str().ok());
-
-  let token = match auth.and_then(|h| h.strip_prefix("Bearer ")) {
-    Some(t) => t,
-    None => {
-      return {
-        log::debug("Missing or invalid token format");
-        HttpResponse::Unauthorized().body("Missing or invalid token format")
-      };
-    }
+  config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+  let user_row = match repo
+    .user
+    .get_user_username_authentication(&payload.username)
+    .await
+  {
+    Ok(user) => Some(user),
+    Err(DbError::NotFound) => None,
+    Err(e) => return Err(e.into()),
   };
 
-  let claims = match jwt.validate_token(token) {
-    Ok(c) => c,
-    Err(_) => {
-      return {
-        log::debug("Invalid token");
-        HttpResponse::Unauthorized().body("Invalid token")
-      };
-    }
+  let attempt = LoginAttempt {
+    user_id: user_row.as_ref().map(|u| u.id),
+    ip: client_ip(&req, config.server.trust_forwarded_headers),
+    user_agent: req
+      .headers()
+      .get(header::USER_AGENT)
+      .and_then(|v| v.to_str().ok())
+      .map(|ua| ua.chars().take(MAX_USER_AGENT_LEN).collect()),
   };
+  let guard = LoginGuard::new(
+    &repo.login_failures,
+    &config.login_protection,
+    user_row.as_ref(),
+    &payload.username,
+    attempt.ip.clone(),
+  );
+  if let Err(e) = guard.check().await {
+    attempt.record(&repo, LoginOutcome::Locked, None);
+    return Err(e);
+  }
 
-  let user_row = match repo.user.get_user_id(claims.id).await {
-    Ok(row) => row,
-    Err(_) => return {
-      log::debug("User not found");
-      HttpResponse::NotFound().body("User not found")
-    },
+  let hash = user_row.as_ref().and_then(|u| u.password.as_deref());
+  let valid = bcrypt::verify(&payload.password, hash.unwrap_or(&DUMMY_HASH))
+    .map_err(|e| ApiError::Internal(format!("Failed to verify password: {}", e)))?
+    && hash.is_some();
+  let Some(user_row) = user_row.filter(|_| valid) else {
+    attempt.record(&repo, LoginOutcome::WrongPassword, None);
+    guard.record_failure().await?;
+    return Err(ApiError::Unauthorized(
+      "Username or password is incorrect".to_string(),
+    ));
   };
+  guard.record_success().await?;
+
+  if !user_row.email_verified() && !config.verification.allow_unverified_login {
+    attempt.record(&repo, LoginOutcome::Unverified, None);
+    return Err(ApiError::Forbidden(
+      "Verify your email address before logging in".to_string(),
+    ));
+  }
 
-  HttpResponse::Ok().json(user_row.to_json())
+  let session_id = Uuid::new_v4();
+  let tokens = issue_tokens(&repo, &jwt, &user_row, session_id).await?;
+  attempt.record(&repo, LoginOutcome::Success, Some(session_id));
+  Ok(HttpResponse::Ok().json(tokens))
 }
 
-#[post("login")]
-async fn user_login(
+/// Where a login attempt came from
+struct LoginAttempt {
+  /// The account the name belongs to, if any
+  user_id: Option<Uuid>,
+  ip: String,
+  user_agent: Option<String>,
+}
+
+impl LoginAttempt {
+  /// Counts the attempt in the metrics and, for existing accounts, adds it to the login history.
+  /// The history is written in the background: names without an account have none, so waiting
+  /// for it would make a wrong password answer slower for existing accounts and reveal them. A
+  /// failure to write the history is logged but does not fail the login.
+  fn record(self, repo: &Repositories, outcome: LoginOutcome, session_id: Option<Uuid>) {
+    metrics::record_login(outcome == LoginOutcome::Success);
+    let Some(user_id) = self.user_id else {
+      return;
+    };
+    let events = repo.login_events.clone();
+    actix_web::rt::spawn(async move {
+      if let Err(e) = events
+        .record(
+          user_id,
+          outcome,
+          &self.ip,
+          self.user_agent.as_deref(),
+          session_id,
+        )
+        .await
+      {
+        log::error(&format!(
+          "Failed to record login of user {}: {}",
+          user_id, e
+        ));
+      }
+    });
+  }
+}
+
+/// Tokens returned by login and refresh
+#[derive(Serialize, ToSchema)]
+struct TokenPair {
+  access_token: String,
+  #[schema(example = "Bearer")]
+  token_type: &'static str,
+  /// Seconds until the access token expires
+  expires_in: i64,
+  refresh_token: String,
+  refresh_expires_at: String,
+}
+
+/// Issues an access token and a new refresh token in the given refresh token family
+async fn issue_tokens(
+  repo: &Repositories,
+  jwt: &JwtManager,
+  user: &User,
+  family_id: Uuid,
+) -> Result<TokenPair, ApiError> {
+  let refresh = jwt.generate_refresh_token();
+  repo
+    .tokens
+    .add_refresh_token(user.id, family_id, &refresh.hash, refresh.expires_at)
+    .await?;
+
+  let access_token = jwt
+    .generate_token(
+      &user.username,
+      user.id,
+      user.role,
+      user.email_verified(),
+      family_id,
+    )
+    .map_err(|e| ApiError::Internal(format!("Failed to generate token: {}", e)))?;
+
+  Ok(TokenPair {
+    access_token,
+    token_type: "Bearer",
+    expires_in: jwt.access_token_ttl().num_seconds(),
+    refresh_token: refresh.token,
+    refresh_expires_at: refresh.expires_at.to_rfc3339(),
+  })
+}
+
+/// Exchanges a refresh token for a new token pair. Each refresh token works once.
+#[utoipa::path(
+  tag = "users",
+  request_body = RefreshRequest,
+  responses(
+    (status = 200, description = "New tokens", body = TokenPair),
+    (status = 401, description = "Invalid, expired, revoked or reused token", body = ErrorBody),
+  )
+)]
+#[post("/refresh")]
+async fn refresh_token(
   repo: Data<Repositories>,
-  payload: Json<LoginRequest>,
+  payload: Json<RefreshRequest>,
   jwt: Data<JwtManager>,
-) -> HttpResponse {
-  let user_row = match repo
+) -> Result<HttpResponse, ApiError> {
+  let expired = || ApiError::Unauthorized("Refresh token expired or revoked".to_string());
+
+  let record = repo
+    .tokens
+    .get_refresh_token(&sha256_hex(&payload.refresh_token))
+    .await
+    .map_err(|e| match e {
+      DbError::NotFound => ApiError::Unauthorized("Invalid refresh token".to_string()),
+      e => e.into(),
+    })?;
+
+  if record.revoked_at.is_some() || record.expires_at <= Utc::now() {
+    return Err(expired());
+  }
+
+  // A used token coming back means it was copied; end the whole session
+  let first_use = record.used_at.is_none() && repo.tokens.mark_used(record.id).await?;
+  if !first_use {
+    log::warn(&format!(
+      "Refresh token reuse detected for user {}, revoking session {}",
+      record.user_id, record.family_id
+    ));
+    repo.tokens.revoke_family(record.family_id).await?;
+    return Err(expired());
+  }
+
+  let user_row = repo
     .user
-    .get_user_username_authentication(&payload.username)
+    .get_user_id(record.user_id)
     .await
-  {
-    Ok(row) => row,
-    Err(_) => return HttpResponse::NotFound().body("User not found"),
-  };
+    .map_err(|e| match e {
+      DbError::NotFound => ApiError::Unauthorized("Invalid refresh token".to_string()),
+      e => e.into(),
+    })?;
 
-  let password: String = user_row.password.unwrap();
+  let tokens = issue_tokens(&repo, &jwt, &user_row, record.family_id).await?;
+  Ok(HttpResponse::Ok().json(tokens))
+}
 
-  if !(bcrypt::verify(&payload.password, &password)).unwrap() {
-    return HttpResponse::Unauthorized().body("Username or password is incorrect");
+/// Revokes the session the refresh token belongs to
+#[utoipa::path(
+  tag = "users",
+  request_body = RefreshRequest,
+  responses((status = 204, description = "Logged out"))
+)]
+#[post("/logout")]
+async fn user_logout(
+  repo: Data<Repositories>,
+  payload: Json<RefreshRequest>,
+) -> Result<HttpResponse, ApiError> {
+  match repo
+    .tokens
+    .get_refresh_token(&sha256_hex(&payload.refresh_token))
+    .await
+  {
+    Ok(record) => {
+      repo.tokens.revoke_family(record.family_id).await?;
+    }
+    Err(DbError::NotFound) => {}
+    Err(e) => return Err(e.into()),
   }
+  Ok(HttpResponse::NoContent().finish())
+}
 
-  let id: Uuid = user_row.id;
+/// The caller's sessions and login history
+#[derive(Serialize, ToSchema)]
+struct SessionsResponse {
+  /// Sessions that can still be refreshed, most recently used first
+  sessions: Vec<Session>,
+  /// Latest login attempts on the account, successful or not, newest first
+  logins: Vec<LoginEvent>,
+}
 
-  let token = match jwt.generate_token(&user_row.username, id) {
-    Ok(t) => t,
-    Err(_) => return HttpResponse::InternalServerError().body("Failed to generate token"),
-  };
+/// The caller's active sessions and latest login attempts
+#[utoipa::path(
+  tag = "users",
+  security(("bearer_auth" = [])),
+  responses(
+    (status = 200, description = "Sessions and login history", body = SessionsResponse),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+  )
+)]
+#[get("/sessions")]
+async fn list_sessions(user: AuthUser, repo: Data<Repositories>) -> Result<HttpResponse, ApiError> {
+  let mut sessions = repo.tokens.active_sessions(user.0.id).await?;
+  for session in &mut sessions {
+    session.current = session.id == user.0.sid;
+  }
+  let logins = repo.login_events.recent(user.0.id, RECENT_LOGINS).await?;
+  Ok(HttpResponse::Ok().json(SessionsResponse { sessions, logins }))
+}
 
-  HttpResponse::Ok().body(token)
+/// Logs out one of the caller's sessions, along with the access tokens issued for it
+#[utoipa::path(
+  tag = "users",
+  security(("bearer_auth" = [])),
+  responses(
+    (status = 204, description = "Session revoked"),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    (status = 404, description = "Session not found or already revoked", body = ErrorBody),
+  )
+)]
+#[delete("/sessions/{id}")]
+async fn revoke_session(
+  user: AuthUser,
+  id: Path<Uuid>,
+  repo: Data<Repositories>,
+) -> Result<HttpResponse, ApiError> {
+  repo
+    .tokens
+    .revoke_session(user.0.id, id.into_inner())
+    .await
+    .map_err(|e| ApiError::or_not_found(e, "Session not found"))?;
+  Ok(HttpResponse::NoContent().finish())
+}
+
+/// Logs out every session of the caller except the one the access token belongs to
+#[utoipa::path(
+  tag = "users",
+  security(("bearer_auth" = [])),
+  responses(
+    (status = 204, description = "Other sessions revoked"),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+  )
+)]
+#[delete("/sessions")]
+async fn revoke_other_sessions(
+  user: AuthUser,
+  repo: Data<Repositories>,
+) -> Result<HttpResponse, ApiError> {
+  let revoked = repo
+    .tokens
+    .revoke_other_sessions(user.0.id, user.0.sid)
+    .await?;
+  log::info(&format!(
+    "User {} revoked {} refresh token(s) of other sessions",
+    user.0.id, revoked
+  ));
+  Ok(HttpResponse::NoContent().finish())
 }
 
-#[post("register")]
-async fn user_register(repo: Data<Repositories>, payload: Json<RegisterRequest>) -> HttpResponse {
+/// Creates a user with an unverified email address and sends them a verification link
+#[utoipa::path(
+  tag = "users",
+  request_body = RegisterRequest,
+  responses(
+    (status = 201, description = "User created", body = String),
+    (status = 400, description = "Invalid input", body = ErrorBody),
+    (status = 409, description = "Username or email already exists", body = ErrorBody),
+    (status = 429, description = "Too many registrations, see `Retry-After`", body = ErrorBody),
+  )
+)]
+#[post("/register", wrap = "RateLimit(RateLimitGroup::Register)")]
+async fn user_register(
+  repo: Data<Repositories>,
+  payload: Json<RegisterRequest>,
+  jwt: Data<JwtManager>,
+  config: Data<Config>,
+  mailer: Data<dyn Mailer>,
+) -> Result<HttpResponse, ApiError> {
   if !is_valid_username(&payload.username) {
-    return HttpResponse::BadRequest().body("Invalid username");
+    return Err(ApiError::BadRequest("Invalid username".to_string()));
   }
 
   if !is_valid_email(&payload.email) {
-    return HttpResponse::BadRequest().body("Invalid email");
+    return Err(ApiError::BadRequest("Invalid email".to_string()));
   }
 
-  if payload.password.len() < 8 {
-    return HttpResponse::BadRequest().body("Password must be at least 8 characters long");
-  }
+  check_password(&payload.password)?;
 
   if !payload.terms {
-    return HttpResponse::BadRequest().body("You must accept the terms and conditions");
+    return Err(ApiError::BadRequest(
+      "You must accept the terms and conditions".to_string(),
+    ));
   }
 
-  let hashed_password = match bcrypt::hash(&payload.password, bcrypt::DEFAULT_COST) {
-    Ok(p) => p,
-    Err(_) => return HttpResponse::InternalServerError().body("Failed to hash password"),
-  };
-
-  if repo
-    .user
-    .user_exists_by_username(&payload.username)
-    .await
-    .unwrap_or(false)
-  {
-    return HttpResponse::Conflict().body("Username already exists");
+  if repo.user.user_exists_by_username(&payload.username).await? {
+    return Err(ApiError::Conflict("Username already exists".to_string()));
   }
 
-  if repo
-    .user
-    .user_exists_by_email(&payload.email)
-    .await
-    .unwrap_or(false)
-  {
-    return HttpResponse::Conflict().body("Email already exists");
+  if repo.user.user_exists_by_email(&payload.email).await? {
+    return Err(ApiError::Conflict("Email already exists".to_string()));
   }
 
-  match repo
+  let hashed_password = hash_password(&payload.password)?;
+
+  // Two registrations racing past the checks above still hit the unique constraints
+  let user_id = repo
     .user
     .register_user(
       payload.username.clone(),
@@ -134,24 +440,645 @@ async fn user_register(repo: Data<Repositories>, payload: Json<RegisterRequest>)
       hashed_password,
       payload.terms,
     )
-    .await
+    .await?;
+
+  // The account exists either way; the user can ask for another email
+  if let Err(e) = send_verification_email(
+    &repo,
+    &jwt,
+    &config,
+    mailer.into_inner(),
+    user_id,
+    &payload.username,
+    &payload.email,
+  )
+  .await
   {
-    Ok(_) => HttpResponse::Created().body("User registered successfully"),
-    Err(e) => {
-      if e.contains("duplicate key value violates unique constraint") {
-        log::error(&format!("User registration failed: {}", e));
-        HttpResponse::Conflict().body("Username or email already exists")
-      } else {
-        log::error(&format!("Failed to register user: {}", e));
-        HttpResponse::InternalServerError().body("Failed to register user")
-      }
-    }
+    log::error(&format!(
+      "Failed to send verification email to user {}: {}",
+      user_id, e
+    ));
+  }
+
+  Ok(HttpResponse::Created().body("User registered successfully"))
+}
+
+/// Rules every new password has to follow
+fn check_password(password: &str) -> Result<(), ApiError> {
+  if password.len() < 8 {
+    return Err(ApiError::BadRequest(
+      "Password must be at least 8 characters long".to_string(),
+    ));
+  }
+  Ok(())
+}
+
+fn hash_password(password: &str) -> Result<String, ApiError> {
+  bcrypt::hash(password, bcrypt::DEFAULT_COST)
+    .map_err(|e| ApiError::Internal(format!("Failed to hash password: {}", e)))
+}
+
+/// Whether another email to an account would exceed the limits, given how many were sent in the
+/// last 24 hours and when the latest one was
+fn email_throttled(
+  sent: i64,
+  latest: Option<DateTime<Utc>>,
+  interval_secs: i64,
+  max_per_day: i64,
+) -> bool {
+  sent >= max_per_day || latest.is_some_and(|at| Utc::now() - at < Duration::seconds(interval_secs))
+}
+
+/// Stores a new verification token for the user and emails them the link
+async fn send_verification_email(
+  repo: &Repositories,
+  jwt: &JwtManager,
+  config: &Config,
+  mailer: Arc<dyn Mailer>,
+  user_id: Uuid,
+  username: &str,
+  email: &str,
+) -> Result<(), DbError> {
+  let ttl_hours = config.verification.token_ttl_hours;
+  let token = jwt.generate_signed_token(
+    TokenPurpose::VerifyEmail,
+    user_id,
+    Duration::hours(ttl_hours),
+  );
+  repo
+    .verification
+    .add_token(user_id, &token.hash, token.expires_at)
+    .await?;
+
+  let link = format!(
+    "{}/api/users/verify?token={}",
+    config.mail.public_url.trim_end_matches('/'),
+    token.token
+  );
+  mail::deliver(
+    mailer,
+    Email {
+      to: email.to_string(),
+      subject: "Verify your email address".to_string(),
+      body: format!(
+        "Hi {},\n\nPlease confirm your email address by opening this link:\n\n{}\n\n\
+         The link expires in {} hours. If you did not create an account, you can ignore this \
+         email.\n",
+        username, link, ttl_hours
+      ),
+    },
+  );
+  Ok(())
+}
+
+/// Marks the email address as verified. The token comes from the link in the verification email
+/// and works once. Access tokens issued before show the new state after the next refresh.
+#[utoipa::path(
+  tag = "users",
+  params(VerifyEmailRequest),
+  responses(
+    (status = 200, description = "Email address verified", body = String),
+    (status = 400, description = "Invalid, expired or already used token", body = ErrorBody),
+  )
+)]
+#[get("/verify")]
+async fn verify_email(
+  repo: Data<Repositories>,
+  jwt: Data<JwtManager>,
+  query: Query<VerifyEmailRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let invalid = || ApiError::BadRequest("Verification link is invalid or has expired".to_string());
+
+  // Forged and expired tokens are rejected without touching the database
+  jwt
+    .verify_signed_token(TokenPurpose::VerifyEmail, &query.token)
+    .ok_or_else(invalid)?;
+  let user_id = repo
+    .verification
+    .redeem_token(&sha256_hex(&query.token))
+    .await
+    .map_err(|e| match e {
+      DbError::NotFound => invalid(),
+      e => e.into(),
+    })?;
+
+  log::info(&format!("User {} verified their email address", user_id));
+  Ok(HttpResponse::Ok().body("Email address verified"))
+}
+
+/// Sends another verification email. Always answers 202 so the response does not reveal whether
+/// the address belongs to an account; emails are limited per account by
+/// `verification.resend_interval_secs` and `verification.max_emails_per_day`.
+#[utoipa::path(
+  tag = "users",
+  request_body = ResendVerificationRequest,
+  responses((status = 202, description = "Email sent if the account exists and is unverified", body = String))
+)]
+#[post("/verify/resend")]
+async fn resend_verification(
+  repo: Data<Repositories>,
+  jwt: Data<JwtManager>,
+  config: Data<Config>,
+  mailer: Data<dyn Mailer>,
+  payload: Json<ResendVerificationRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let accepted = HttpResponse::Accepted()
+    .body("If the account exists and is not verified, a new email is on its way");
+
+  let user_row = match repo.user.get_user_email(&payload.email).await {
+    Ok(user_row) if !user_row.email_verified() => user_row,
+    Ok(_) | Err(DbError::NotFound) => return Ok(accepted),
+    Err(e) => return Err(e.into()),
+  };
+
+  let (sent, latest) = repo.verification.recent_tokens(user_row.id).await?;
+  let verification = &config.verification;
+  if email_throttled(
+    sent,
+    latest,
+    verification.resend_interval_secs,
+    verification.max_emails_per_day,
+  ) {
+    log::debug(&format!(
+      "Not resending verification email to user {}: {} sent today",
+      user_row.id, sent
+    ));
+    return Ok(accepted);
   }
+
+  send_verification_email(
+    &repo,
+    &jwt,
+    &config,
+    mailer.into_inner(),
+    user_row.id,
+    &user_row.username,
+    &user_row.email,
+  )
+  .await?;
+  Ok(accepted)
+}
+
+/// Changes the caller's password. Every login session of the user is revoked, including the
+/// current one, so earlier access tokens stop working too. A new token pair is returned so the
+/// caller stays logged in. A wrong current password counts as a failed login, so a stolen access
+/// token cannot be used to guess it.
+#[utoipa::path(
+  tag = "users",
+  security(("bearer_auth" = [])),
+  request_body = ChangePasswordRequest,
+  responses(
+    (status = 200, description = "Password changed, tokens of a new session", body = TokenPair),
+    (status = 400, description = "Invalid new password", body = ErrorBody),
+    (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    (status = 403, description = "Current password is incorrect", body = ErrorBody),
+    (status = 429, description = "Too many failed attempts, see `Retry-After`", body = ErrorBody),
+  )
+)]
+#[put("/password")]
+async fn change_password(
+  req: HttpRequest,
+  user: AuthUser,
+  repo: Data<Repositories>,
+  jwt: Data<JwtManager>,
+  config: Data<Config>,
+  payload: Json<ChangePasswordRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.0.id;
+  let user_row = repo
+    .user
+    .get_user_id(user_id)
+    .await
+    .map_err(|e| ApiError::or_not_found(e, "User not found"))?;
+  let guard = LoginGuard::new(
+    &repo.login_failures,
+    &config.login_protection,
+    Some(&user_row),
+    &user_row.username,
+    client_ip(&req, config.server.trust_forwarded_headers),
+  );
+  guard.check().await?;
+
+  let current = repo.user.get_password_hash(user_id).await?;
+  let valid = bcrypt::verify(&payload.current_password, &current)
+    .map_err(|e| ApiError::Internal(format!("Failed to verify password: {}", e)))?;
+  if !valid {
+    guard.record_failure().await?;
+    return Err(ApiError::Forbidden(
+      "Current password is incorrect".to_string(),
+    ));
+  }
+  guard.record_success().await?;
+
+  check_password(&payload.new_password)?;
+  if payload.new_password == payload.current_password {
+    return Err(ApiError::BadRequest(
+      "New password must be different from the current one".to_string(),
+    ));
+  }
+  let hashed_password = hash_password(&payload.new_password)?;
+  repo.user.update_password(user_id, &hashed_password).await?;
+  log::info(&format!(
+    "User {} changed their password, sessions revoked",
+    user_id
+  ));
+
+  let tokens = issue_tokens(&repo, &jwt, &user_row, Uuid::new_v4()).await?;
+  Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Emails a link to reset the password. Always answers 202 so the response does not reveal
+/// whether the address belongs to an account; emails are limited per account by
+/// `password_reset.resend_interval_secs` and `password_reset.max_emails_per_day`.
+#[utoipa::path(
+  tag = "users",
+  request_body = ForgotPasswordRequest,
+  responses((status = 202, description = "Email sent if the account exists", body = String))
+)]
+#[post("/password/forgot")]
+async fn forgot_password(
+  repo: Data<Repositories>,
+  jwt: Data<JwtManager>,
+  config: Data<Config>,
+  mailer: Data<dyn Mailer>,
+  payload: Json<ForgotPasswordRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let accepted =
+    HttpResponse::Accepted().body("If the account exists, a password reset link is on its way");
+
+  let user_row = match repo.user.get_user_email(&payload.email).await {
+    Ok(user_row) => user_row,
+    Err(DbError::NotFound) => return Ok(accepted),
+    Err(e) => return Err(e.into()),
+  };
+
+  let settings = &config.password_reset;
+  let (sent, latest) = repo.password_reset.recent_tokens(user_row.id).await?;
+  if email_throttled(
+    sent,
+    latest,
+    settings.resend_interval_secs,
+    settings.max_emails_per_day,
+  ) {
+    log::debug(&format!(
+      "Not sending password reset email to user {}: {} sent today",
+      user_row.id, sent
+    ));
+    return Ok(accepted);
+  }
+
+  let token = jwt.generate_signed_token(
+    TokenPurpose::ResetPassword,
+    user_row.id,
+    Duration::minutes(settings.token_ttl_minutes),
+  );
+  repo
+    .password_reset
+    .add_token(user_row.id, &token.hash, token.expires_at)
+    .await?;
+
+  let separator = if settings.url.contains('?') { '&' } else { '?' };
+  let link = format!("{}{}token={}", settings.url, separator, token.token);
+  mail::deliver(
+    mailer.into_inner(),
+    Email {
+      to: user_row.email.clone(),
+      subject: "Reset your password".to_string(),
+      body: format!(
+        "Hi {},\n\nSomeone asked to reset the password of your account. To choose a new one, \
+         open this link:\n\n{}\n\nThe link expires in {} minutes. If you did not ask for \
+         this, you can ignore this email; your password stays the same.\n",
+        user_row.username, link, settings.token_ttl_minutes
+      ),
+    },
+  );
+  Ok(accepted)
+}
+
+/// Sets a new password with the token from the reset email. Each token works once, and every
+/// login session of the user is revoked.
+#[utoipa::path(
+  tag = "users",
+  request_body = ResetPasswordRequest,
+  responses(
+    (status = 200, description = "Password changed", body = String),
+    (status = 400, description = "Invalid, expired or already used token, or invalid password", body = ErrorBody),
+  )
+)]
+#[post("/password/reset")]
+async fn reset_password(
+  repo: Data<Repositories>,
+  jwt: Data<JwtManager>,
+  payload: Json<ResetPasswordRequest>,
+) -> Result<HttpResponse, ApiError> {
+  let invalid = || ApiError::BadRequest("Reset link is invalid or has expired".to_string());
+
+  jwt
+    .verify_signed_token(TokenPurpose::ResetPassword, &payload.token)
+    .ok_or_else(invalid)?;
+  check_password(&payload.new_password)?;
+  let hashed_password = hash_password(&payload.new_password)?;
+
+  let user_id = repo
+    .password_reset
+    .reset_password(&sha256_hex(&payload.token), &hashed_password)
+    .await
+    .map_err(|e| match e {
+      DbError::NotFound => invalid(),
+      e => e.into(),
+    })?;
+
+  log::info(&format!(
+    "User {} reset their password, sessions revoked",
+    user_id
+  ));
+  Ok(HttpResponse::Ok().body("Password has been reset"))
 }
 
+routes!(
+  UserApi,
+  [
+    get_user,
+    user_login,
+    refresh_token,
+    user_logout,
+    list_sessions,
+    revoke_session,
+    revoke_other_sessions,
+    user_register,
+    verify_email,
+    resend_verification,
+    change_password,
+    forgot_password,
+    reset_password
+  ]
+);
+
 pub fn scope() -> actix_web::Scope {
-  web::scope("/api/users")
-    .service(get_user)
-    .service(user_login)
-    .service(user_register)
+  routes(web::scope("/api/users"))
+}
+
+#[cfg(test)]
+mod tests {
+  use actix_web::App;
+  use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+  use actix_web::test::{
+    TestRequest, call_and_read_body_json, call_service, init_service, read_body_json,
+  };
+
+  use super::*;
+  use crate::auth::auth_user::SessionRevocations;
+  use crate::config::AuthConfig;
+  use crate::mail::outbox::MemoryOutbox;
+  use crate::rate_limit::{RateLimitStore, memory::MemoryStore};
+  use crate::testing::TestDatabase;
+
+  fn auth_config(secret: &str) -> AuthConfig {
+    AuthConfig {
+      jwt_secret: secret.to_string(),
+      ..AuthConfig::default()
+    }
+  }
+
+  /// The users scope with its app data
+  fn app(
+    repos: Repositories,
+    mailer: Arc<dyn Mailer>,
+  ) -> App<
+    impl ServiceFactory<
+      ServiceRequest,
+      Config = (),
+      Response = ServiceResponse,
+      Error = actix_web::Error,
+      InitError = (),
+    >,
+  > {
+    let sessions: Arc<dyn SessionRevocations> = repos.tokens.clone();
+    let rate_limits: Arc<dyn RateLimitStore> = Arc::new(MemoryStore::default());
+    App::new()
+      .app_data(Data::new(repos))
+      .app_data(Data::from(sessions))
+      .app_data(Data::new(JwtManager::new(&auth_config("secret"))))
+      .app_data(Data::new(Config::default()))
+      .app_data(Data::from(mailer))
+      .app_data(Data::from(rate_limits))
+      .service(scope())
+  }
+
+  fn register(username: &str, password: &str) -> TestRequest {
+    TestRequest::post()
+      .uri("/api/users/register")
+      .set_json(serde_json::json!({
+        "username": username,
+        "email": format!("{}@example.com", username),
+        "password": password,
+        "terms": true,
+      }))
+  }
+
+  fn bearer(token: &str) -> (header::HeaderName, String) {
+    (header::AUTHORIZATION, format!("Bearer {}", token))
+  }
+
+  #[test]
+  fn email_throttled_by_daily_limit_and_interval() {
+    let now = Utc::now();
+    assert!(!email_throttled(0, None, 60, 5));
+    assert!(!email_throttled(
+      4,
+      Some(now - Duration::seconds(61)),
+      60,
+      5
+    ));
+    assert!(email_throttled(5, Some(now - Duration::hours(2)), 60, 5));
+    assert!(email_throttled(5, None, 60, 5));
+    assert!(email_throttled(1, Some(now - Duration::seconds(30)), 60, 5));
+  }
+
+  #[actix_web::test]
+  #[ignore = "needs PostgreSQL"]
+  async fn verification_link_works_once() {
+    let db = TestDatabase::create().await;
+    let outbox = Arc::new(MemoryOutbox::default());
+    let app = init_service(app(db.repositories(), outbox.clone())).await;
+
+    let res = call_service(&app, register("verifier", "correct horse").to_request()).await;
+    assert_eq!(res.status(), 201);
+
+    // The email is sent from a spawned task
+    let mut messages = outbox.messages();
+    for _ in 0..100 {
+      if !messages.is_empty() {
+        break;
+      }
+      actix_web::rt::time::sleep(std::time::Duration::from_millis(10)).await;
+      messages = outbox.messages();
+    }
+    let [email] = messages.as_slice() else {
+      panic!("expected one email, got {}", messages.len());
+    };
+    assert_eq!(email.to, "verifier@example.com");
+    let token = email
+      .body
+      .split_whitespace()
+      .find_map(|word| word.split_once("/api/users/verify?token=").map(|(_, t)| t))
+      .expect("a verification link");
+
+    let verify = |token: &str| {
+      TestRequest::get()
+        .uri(&format!("/api/users/verify?token={}", token))
+        .to_request()
+    };
+    assert_eq!(call_service(&app, verify(token)).await.status(), 200);
+    let user = db
+      .repositories()
+      .user
+      .get_user_email("verifier@example.com")
+      .await
+      .unwrap();
+    assert!(user.email_verified());
+    assert_eq!(call_service(&app, verify(token)).await.status(), 400);
+
+    let forged = JwtManager::new(&auth_config("another secret")).generate_signed_token(
+      TokenPurpose::VerifyEmail,
+      Uuid::new_v4(),
+      Duration::hours(1),
+    );
+    assert_eq!(
+      call_service(&app, verify(&forged.token)).await.status(),
+      400
+    );
+
+    db.remove().await;
+  }
+
+  #[actix_web::test]
+  #[ignore = "needs PostgreSQL"]
+  async fn password_change_revokes_access_tokens() {
+    let db = TestDatabase::create().await;
+    let app = init_service(app(db.repositories(), Arc::new(MemoryOutbox::default()))).await;
+    let res = call_service(&app, register("changer", "correct horse").to_request()).await;
+    assert_eq!(res.status(), 201);
+
+    let login = |username: &str, password: &str| {
+      TestRequest::post()
+        .uri("/api/users/login")
+        .set_json(serde_json::json!({ "username": username, "password": password }))
+        .to_request()
+    };
+    let me = |token: &str| {
+      TestRequest::get()
+        .uri("/api/users")
+        .insert_header(bearer(token))
+        .to_request()
+    };
+    let access_token = |body: serde_json::Value| body["access_token"].as_str().unwrap().to_string();
+
+    let first =
+      access_token(call_and_read_body_json(&app, login("changer", "correct horse")).await);
+    let second =
+      access_token(call_and_read_body_json(&app, login("changer", "correct horse")).await);
+    assert_eq!(call_service(&app, me(&first)).await.status(), 200);
+
+    let res = call_service(
+      &app,
+      TestRequest::put()
+        .uri("/api/users/password")
+        .insert_header(bearer(&first))
+        .set_json(serde_json::json!({
+          "current_password": "correct horse",
+          "new_password": "battery staple",
+        }))
+        .to_request(),
+    )
+    .await;
+    assert_eq!(res.status(), 200);
+    let renewed = access_token(read_body_json(res).await);
+
+    // Both earlier sessions are revoked, including the one that made the change
+    assert_eq!(call_service(&app, me(&first)).await.status(), 401);
+    assert_eq!(call_service(&app, me(&second)).await.status(), 401);
+    assert_eq!(call_service(&app, me(&renewed)).await.status(), 200);
+
+    db.remove().await;
+  }
+
+  #[actix_web::test]
+  #[ignore = "needs PostgreSQL"]
+  async fn wrong_current_password_counts_as_failed_login() {
+    let db = TestDatabase::create().await;
+    let app = init_service(app(db.repositories(), Arc::new(MemoryOutbox::default()))).await;
+    let res = call_service(&app, register("guessed", "correct horse").to_request()).await;
+    assert_eq!(res.status(), 201);
+    let login = || {
+      TestRequest::post()
+        .uri("/api/users/login")
+        .set_json(serde_json::json!({ "username": "guessed", "password": "correct horse" }))
+        .to_request()
+    };
+    let body: serde_json::Value = call_and_read_body_json(&app, login()).await;
+    let token = body["access_token"].as_str().unwrap().to_string();
+    let change = |current: &str| {
+      TestRequest::put()
+        .uri("/api/users/password")
+        .insert_header(bearer(&token))
+        .set_json(serde_json::json!({
+          "current_password": current,
+          "new_password": "battery staple",
+        }))
+        .to_request()
+    };
+
+    // The default policy allows three free failures and blocks the account after the fourth
+    for _ in 0..4 {
+      assert_eq!(call_service(&app, change("guess")).await.status(), 403);
+    }
+    let res = call_service(&app, change("correct horse")).await;
+    assert_eq!(res.status(), 429);
+    assert!(res.headers().contains_key(header::RETRY_AFTER));
+    assert_eq!(call_service(&app, login()).await.status(), 429);
+
+    db.remove().await;
+  }
+
+  #[actix_web::test]
+  #[ignore = "needs PostgreSQL"]
+  async fn failed_login_is_recorded_in_the_background() {
+    let db = TestDatabase::create().await;
+    let repos = db.repositories();
+    let app = init_service(app(repos.clone(), Arc::new(MemoryOutbox::default()))).await;
+    let res = call_service(&app, register("recorded", "correct horse").to_request()).await;
+    assert_eq!(res.status(), 201);
+    let user_id = repos
+      .user
+      .get_user_username_authentication("recorded")
+      .await
+      .unwrap()
+      .id;
+
+    let res = call_service(
+      &app,
+      TestRequest::post()
+        .uri("/api/users/login")
+        .set_json(serde_json::json!({ "username": "recorded", "password": "wrong" }))
+        .to_request(),
+    )
+    .await;
+    assert_eq!(res.status(), 401);
+
+    let mut events = repos.login_events.recent(user_id, 20).await.unwrap();
+    for _ in 0..100 {
+      if !events.is_empty() {
+        break;
+      }
+      actix_web::rt::time::sleep(std::time::Duration::from_millis(10)).await;
+      events = repos.login_events.recent(user_id, 20).await.unwrap();
+    }
+    let [event] = events.as_slice() else {
+      panic!("expected one login event, got {}", events.len());
+    };
+    assert_eq!(event.outcome, LoginOutcome::WrongPassword);
+
+    db.remove().await;
+  }
 }
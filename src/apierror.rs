@@ -0,0 +1,250 @@
+use std::fmt;
+
+use actix_web::http::{StatusCode, header};
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::Value;
+use tokio_postgres::error::SqlState;
+use utoipa::ToSchema;
+
+use crate::dberror::DbError;
+use crate::log;
+use crate::storage::StorageError;
+use crate::upload::UploadError;
+
+/// Error returned by every handler. Rendered as `{code, message, details, request_id}`.
+#[derive(Debug)]
+pub enum ApiError {
+  BadRequest(String),
+  Unauthorized(String),
+  Forbidden(String),
+  NotFound(String),
+  Conflict(String),
+  PayloadTooLarge(String),
+  UnsupportedMediaType(String),
+  // Message and the seconds until the client may retry, sent in the `Retry-After` header
+  TooManyRequests(String, u64),
+  UniqueViolation { constraint: Option<String> },
+  ForeignKeyViolation { constraint: Option<String> },
+  ServiceUnavailable(String),
+  Internal(String),
+}
+
+/// Body of every error response
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody<'a> {
+  /// Machine readable error code such as `not_found` or `unique_violation`
+  #[schema(example = "not_found")]
+  code: &'a str,
+  message: String,
+  #[schema(value_type = Option<Object>)]
+  details: Option<Value>,
+  request_id: Option<&'a str>,
+}
+
+impl ApiError {
+  pub fn missing_token() -> Self {
+    ApiError::Unauthorized("Missing or invalid token format".to_string())
+  }
+
+  /// Converts a database error, describing a missing row with `message`
+  pub fn or_not_found(err: DbError, message: &str) -> Self {
+    match err {
+      DbError::NotFound => ApiError::NotFound(message.to_string()),
+      err => err.into(),
+    }
+  }
+
+  pub fn code(&self) -> &'static str {
+    match *self {
+      ApiError::BadRequest(_) => "bad_request",
+      ApiError::Unauthorized(_) => "unauthorized",
+      ApiError::Forbidden(_) => "forbidden",
+      ApiError::NotFound(_) => "not_found",
+      ApiError::Conflict(_) => "conflict",
+      ApiError::PayloadTooLarge(_) => "payload_too_large",
+      ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+      ApiError::TooManyRequests(..) => "too_many_requests",
+      ApiError::UniqueViolation { .. } => "unique_violation",
+      ApiError::ForeignKeyViolation { .. } => "foreign_key_violation",
+      ApiError::ServiceUnavailable(_) => "service_unavailable",
+      ApiError::Internal(_) => "internal_error",
+    }
+  }
+
+  /// Message shown to the client. Internal errors are logged but not exposed.
+  pub fn message(&self) -> String {
+    match *self {
+      ApiError::UniqueViolation { .. } => "Resource already exists".to_string(),
+      ApiError::ForeignKeyViolation { .. } => {
+        "Referenced resource does not exist or is still in use".to_string()
+      }
+      ApiError::Internal(_) => "Internal server error".to_string(),
+      _ => self.to_string(),
+    }
+  }
+
+  pub fn details(&self) -> Option<Value> {
+    match *self {
+      ApiError::UniqueViolation { ref constraint }
+      | ApiError::ForeignKeyViolation { ref constraint } => constraint
+        .as_ref()
+        .map(|c| serde_json::json!({ "constraint": c })),
+      ApiError::TooManyRequests(_, retry_after) => {
+        Some(serde_json::json!({ "retry_after": retry_after }))
+      }
+      _ => None,
+    }
+  }
+
+  /// Builds the JSON response, tagging it with the request id when one is known
+  pub fn to_response(&self, request_id: Option<&str>) -> HttpResponse {
+    if let ApiError::Internal(ref msg) = *self {
+      log::error(msg);
+    }
+
+    let mut builder = HttpResponse::build(self.status_code());
+    match *self {
+      ApiError::Unauthorized(_) => {
+        builder.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+      }
+      ApiError::TooManyRequests(_, retry_after) => {
+        builder.insert_header((header::RETRY_AFTER, retry_after));
+      }
+      _ => {}
+    }
+
+    builder.json(ErrorBody {
+      code: self.code(),
+      message: self.message(),
+      details: self.details(),
+      request_id,
+    })
+  }
+}
+
+impl fmt::Display for ApiError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      ApiError::BadRequest(ref msg)
+      | ApiError::Unauthorized(ref msg)
+      | ApiError::Forbidden(ref msg)
+      | ApiError::NotFound(ref msg)
+      | ApiError::Conflict(ref msg)
+      | ApiError::PayloadTooLarge(ref msg)
+      | ApiError::UnsupportedMediaType(ref msg)
+      | ApiError::TooManyRequests(ref msg, _)
+      | ApiError::ServiceUnavailable(ref msg)
+      | ApiError::Internal(ref msg) => write!(f, "{}", msg),
+      ApiError::UniqueViolation { ref constraint } => {
+        write!(
+          f,
+          "Unique violation on {}",
+          constraint.as_deref().unwrap_or("unknown")
+        )
+      }
+      ApiError::ForeignKeyViolation { ref constraint } => write!(
+        f,
+        "Foreign key violation on {}",
+        constraint.as_deref().unwrap_or("unknown")
+      ),
+    }
+  }
+}
+
+impl ResponseError for ApiError {
+  fn status_code(&self) -> StatusCode {
+    match *self {
+      ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+      ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+      ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+      ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+      ApiError::Conflict(_)
+      | ApiError::UniqueViolation { .. }
+      | ApiError::ForeignKeyViolation { .. } => StatusCode::CONFLICT,
+      ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+      ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+      ApiError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+      ApiError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+      ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  fn error_response(&self) -> HttpResponse {
+    self.to_response(None)
+  }
+}
+
+impl From<DbError> for ApiError {
+  fn from(err: DbError) -> Self {
+    match err {
+      DbError::NotFound => ApiError::NotFound("Not found".to_string()),
+      DbError::DatabaseError(ref pg) => {
+        let constraint = pg
+          .as_db_error()
+          .and_then(|e| e.constraint())
+          .map(str::to_string);
+        match pg.code() {
+          Some(code) if *code == SqlState::UNIQUE_VIOLATION => {
+            ApiError::UniqueViolation { constraint }
+          }
+          Some(code) if *code == SqlState::FOREIGN_KEY_VIOLATION => {
+            ApiError::ForeignKeyViolation { constraint }
+          }
+          Some(code)
+            if *code == SqlState::NOT_NULL_VIOLATION
+              || *code == SqlState::CHECK_VIOLATION
+              || *code == SqlState::INVALID_TEXT_REPRESENTATION
+              || *code == SqlState::STRING_DATA_RIGHT_TRUNCATION =>
+          {
+            ApiError::BadRequest(
+              pg.as_db_error()
+                .map(|e| e.message().to_string())
+                .unwrap_or_else(|| "Invalid value".to_string()),
+            )
+          }
+          _ => ApiError::Internal(err.to_string()),
+        }
+      }
+      DbError::PoolError(ref e) => {
+        log::error(&format!("Database pool error: {}", e));
+        ApiError::ServiceUnavailable("Database is unavailable".to_string())
+      }
+      DbError::ConfigError(_) | DbError::MigrationError(_) | DbError::HashingError(_) => {
+        ApiError::Internal(err.to_string())
+      }
+    }
+  }
+}
+
+impl From<UploadError> for ApiError {
+  fn from(err: UploadError) -> Self {
+    match err {
+      UploadError::MissingImage
+      | UploadError::MultipleImages
+      | UploadError::InvalidImage(_)
+      | UploadError::Multipart(_) => ApiError::BadRequest(err.to_string()),
+      UploadError::TooLarge { .. } | UploadError::FieldsTooLarge { .. } => {
+        ApiError::PayloadTooLarge(err.to_string())
+      }
+      UploadError::UnsupportedType => ApiError::UnsupportedMediaType(err.to_string()),
+      UploadError::Io(_) | UploadError::Storage(_) => ApiError::Internal(err.to_string()),
+    }
+  }
+}
+
+impl From<StorageError> for ApiError {
+  fn from(err: StorageError) -> Self {
+    match err {
+      StorageError::NotFound | StorageError::InvalidKey(_) => {
+        ApiError::NotFound("File not found".to_string())
+      }
+      StorageError::Io(_) | StorageError::Backend(_) => ApiError::Internal(err.to_string()),
+    }
+  }
+}
+
+/// Turns actix extractor errors (bad JSON, query or path) into `ApiError::BadRequest`
+pub fn extractor_error(err: impl fmt::Display) -> actix_web::Error {
+  ApiError::BadRequest(err.to_string()).into()
+}
@@ -0,0 +1,134 @@
+use actix_web::http::header;
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, dev::Payload};
+use async_trait::async_trait;
+use futures_util::future::LocalBoxFuture;
+use uuid::Uuid;
+
+use crate::apierror::ApiError;
+use crate::config::Config;
+use crate::dberror::DbError;
+use crate::jwt::claims::Claims;
+use crate::jwt::jwt::JwtManager;
+use crate::log;
+
+/// Tells whether a login session was revoked. Access tokens name their session in `sid`, so
+/// logging out, revoking a session and changing or resetting the password also stop the access
+/// tokens issued for it, not only its refresh token.
+#[async_trait]
+pub trait SessionRevocations: Send + Sync {
+  async fn is_revoked(&self, session_id: Uuid) -> Result<bool, DbError>;
+}
+
+/// Returns the claims of the request's Bearer token. Claims already verified by the
+/// `require_auth` middleware are reused instead of decoding the token and looking up its session
+/// again.
+pub async fn authenticate(req: &HttpRequest) -> Result<Option<Claims>, ApiError> {
+  if let Some(claims) = req.extensions().get::<Claims>() {
+    return Ok(Some(claims.clone()));
+  }
+
+  let Some(auth) = req.headers().get(header::AUTHORIZATION) else {
+    return Ok(None);
+  };
+
+  let token = auth
+    .to_str()
+    .ok()
+    .and_then(|h| h.strip_prefix("Bearer "))
+    .ok_or_else(|| {
+      log::debug("Missing or invalid token format");
+      ApiError::missing_token()
+    })?;
+
+  let jwt = req.app_data::<Data<JwtManager>>().ok_or_else(|| {
+    ApiError::Internal("JwtManager is not registered as app data".to_string())
+  })?;
+
+  let claims = jwt.validate_token(token).map_err(|_| {
+    log::debug("Invalid token");
+    ApiError::Unauthorized("Invalid token".to_string())
+  })?;
+
+  let sessions = req
+    .app_data::<Data<dyn SessionRevocations>>()
+    .ok_or_else(|| {
+      ApiError::Internal("SessionRevocations is not registered as app data".to_string())
+    })?;
+  if sessions.is_revoked(claims.sid).await? {
+    log::debug(&format!("Session {} was revoked", claims.sid));
+    return Err(ApiError::Unauthorized("Invalid token".to_string()));
+  }
+
+  log::add_context("user_id", serde_json::json!(claims.id));
+  req.extensions_mut().insert(claims.clone());
+  Ok(Some(claims))
+}
+
+/// Extracts the verified claims of the caller and rejects the request with 401 otherwise
+pub struct AuthUser(pub Claims);
+
+impl FromRequest for AuthUser {
+  type Error = ApiError;
+  type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+  fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+    let req = req.clone();
+    Box::pin(async move {
+      match authenticate(&req).await? {
+        Some(claims) => Ok(AuthUser(claims)),
+        None => {
+          log::debug("Missing or invalid token format");
+          Err(ApiError::missing_token())
+        }
+      }
+    })
+  }
+}
+
+/// Like `AuthUser` but also rejects users whose email address is not verified with 403, unless
+/// `verification.allow_unverified_writes` is set. Used by endpoints that change data.
+pub struct VerifiedUser(pub Claims);
+
+impl FromRequest for VerifiedUser {
+  type Error = ApiError;
+  type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+  fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+    let user = AuthUser::from_request(req, payload);
+    let req = req.clone();
+    Box::pin(async move {
+      let AuthUser(claims) = user.await?;
+      if claims.email_verified {
+        return Ok(VerifiedUser(claims));
+      }
+
+      match req.app_data::<Data<Config>>() {
+        Some(config) if config.verification.allow_unverified_writes => Ok(VerifiedUser(claims)),
+        Some(_) => {
+          log::debug(&format!("User {} has not verified their email", claims.id));
+          Err(ApiError::Forbidden(
+            "Verify your email address before doing this".to_string(),
+          ))
+        }
+        None => Err(ApiError::Internal(
+          "Config is not registered as app data".to_string(),
+        )),
+      }
+    })
+  }
+}
+
+/// Like `AuthUser` but lets requests without an `Authorization` header through. A header
+/// carrying an invalid token is still rejected.
+pub struct OptionalAuthUser(pub Option<Claims>);
+
+impl FromRequest for OptionalAuthUser {
+  type Error = ApiError;
+  type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+  fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+    let req = req.clone();
+    Box::pin(async move { authenticate(&req).await.map(OptionalAuthUser) })
+  }
+}
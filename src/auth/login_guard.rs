@@ -0,0 +1,231 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::apierror::ApiError;
+use crate::config::LoginProtectionConfig;
+use crate::dberror::DbError;
+use crate::log;
+use crate::repositories::login_failure_repo::LoginFailureRepo;
+use crate::tables::login_failure::LoginFailureKind;
+use crate::tables::user::User;
+
+/// Longest subject stored for a failed login
+const MAX_SUBJECT_LEN: usize = 255;
+
+/// Counts failed logins for one attempt's account and client IP address, and refuses the attempt
+/// while either is locked. See `LoginProtectionConfig` for the policy.
+pub struct LoginGuard<'a> {
+  repo: &'a LoginFailureRepo,
+  config: &'a LoginProtectionConfig,
+  account: String,
+  ip: String,
+}
+
+impl<'a> LoginGuard<'a> {
+  /// `user` is the account matching `name`, if any. Names without an account are tracked by
+  /// themselves so that they lock the same way existing accounts do.
+  pub fn new(
+    repo: &'a LoginFailureRepo,
+    config: &'a LoginProtectionConfig,
+    user: Option<&User>,
+    name: &str,
+    ip: String,
+  ) -> Self {
+    let account = match user {
+      Some(user) => user.id.to_string(),
+      None => name.to_lowercase().chars().take(MAX_SUBJECT_LEN).collect(),
+    };
+    LoginGuard {
+      repo,
+      config,
+      account,
+      ip,
+    }
+  }
+
+  /// Fails with `TooManyRequests` while the account or the IP address is locked
+  pub async fn check(&self) -> Result<(), ApiError> {
+    let Some(until) = self.repo.locked_until(&self.account, &self.ip).await? else {
+      return Ok(());
+    };
+    let retry_after = (until - Utc::now()).num_seconds().max(0) + 1;
+    Err(ApiError::TooManyRequests(
+      "Too many failed login attempts, try again later".to_string(),
+      retry_after as u64,
+    ))
+  }
+
+  /// Counts a failed login against the account and the IP address, locking either one once it
+  /// is past its free attempts
+  pub async fn record_failure(&self) -> Result<(), ApiError> {
+    let config = self.config;
+    self
+      .count(
+        LoginFailureKind::Account,
+        &self.account,
+        config.account_free_attempts,
+        config.account_max_failures,
+      )
+      .await?;
+    self
+      .count(
+        LoginFailureKind::Ip,
+        &self.ip,
+        config.ip_free_attempts,
+        config.ip_max_failures,
+      )
+      .await
+  }
+
+  /// Forgets the failures of the account. The IP address keeps its count, so that logging into
+  /// one account does not reset the attempts made against others.
+  pub async fn record_success(&self) -> Result<(), ApiError> {
+    match self
+      .repo
+      .clear(LoginFailureKind::Account, &self.account)
+      .await
+    {
+      Ok(()) | Err(DbError::NotFound) => Ok(()),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  async fn count(
+    &self,
+    kind: LoginFailureKind,
+    subject: &str,
+    free_attempts: i32,
+    max_failures: i32,
+  ) -> Result<(), ApiError> {
+    let since = failures_since(self.config);
+    let failures = self.repo.record_failure(kind, subject, since).await?;
+    let Some(secs) = lock_secs(self.config, failures, free_attempts, max_failures) else {
+      return Ok(());
+    };
+    if failures >= max_failures {
+      log::warn(&format!(
+        "Locked logins for {} {} for {}s after {} failed attempts",
+        kind.as_str(),
+        subject,
+        secs,
+        failures
+      ));
+    }
+    // Only an unvalidated config can ask for more than chrono represents; lock for as long as it
+    // allows then
+    let until = Duration::try_seconds(secs)
+      .and_then(|lock| Utc::now().checked_add_signed(lock))
+      .unwrap_or(DateTime::<Utc>::MAX_UTC);
+    self.repo.lock(kind, subject, until).await?;
+    Ok(())
+  }
+}
+
+/// Failures before this time are forgotten, see `reset_after_secs`
+pub fn failures_since(config: &LoginProtectionConfig) -> DateTime<Utc> {
+  Duration::try_seconds(config.reset_after_secs)
+    .and_then(|reset| Utc::now().checked_sub_signed(reset))
+    .unwrap_or(DateTime::<Utc>::UNIX_EPOCH)
+}
+
+/// How long to refuse logins after the given number of failures. The delay starts at
+/// `backoff_base_secs` past the free attempts and doubles with each failure, up to
+/// `backoff_max_secs`, until `max_failures` locks logins for `lockout_secs`.
+fn lock_secs(
+  config: &LoginProtectionConfig,
+  failures: i32,
+  free_attempts: i32,
+  max_failures: i32,
+) -> Option<i64> {
+  if failures >= max_failures {
+    return Some(config.lockout_secs);
+  }
+  if failures <= free_attempts {
+    return None;
+  }
+  let doublings = (failures - free_attempts - 1).min(32) as u32;
+  Some(
+    config
+      .backoff_base_secs
+      .saturating_mul(1 << doublings)
+      .min(config.backoff_max_secs),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::TestDatabase;
+
+  fn config() -> LoginProtectionConfig {
+    LoginProtectionConfig {
+      backoff_base_secs: 2,
+      backoff_max_secs: 30,
+      lockout_secs: 600,
+      ..LoginProtectionConfig::default()
+    }
+  }
+
+  #[test]
+  fn free_attempts_have_no_delay() {
+    for failures in 0..=3 {
+      assert_eq!(lock_secs(&config(), failures, 3, 10), None);
+    }
+  }
+
+  #[test]
+  fn delay_doubles_up_to_the_cap() {
+    let delays: Vec<_> = (4..10).map(|f| lock_secs(&config(), f, 3, 10)).collect();
+    assert_eq!(
+      delays,
+      [Some(2), Some(4), Some(8), Some(16), Some(30), Some(30)]
+    );
+  }
+
+  #[test]
+  fn max_failures_lock_out() {
+    assert_eq!(lock_secs(&config(), 10, 3, 10), Some(600));
+    assert_eq!(lock_secs(&config(), 11, 3, 10), Some(600));
+    // The lockout wins even when it comes before the free attempts run out
+    assert_eq!(lock_secs(&config(), 2, 3, 2), Some(600));
+  }
+
+  #[actix_web::test]
+  #[ignore = "needs PostgreSQL"]
+  async fn long_backoff_still_locks() {
+    let db = TestDatabase::create().await;
+    let repos = db.repositories();
+    // Past what chrono can add to the current time, as only an unvalidated config allows
+    let config = LoginProtectionConfig {
+      account_free_attempts: 0,
+      backoff_base_secs: 1 << 60,
+      backoff_max_secs: i64::MAX,
+      reset_after_secs: i64::MAX,
+      ..config()
+    };
+    assert_eq!(lock_secs(&config, 1000, 0, i32::MAX), Some(i64::MAX));
+
+    let guard = LoginGuard::new(
+      &repos.login_failures,
+      &config,
+      None,
+      "someone",
+      "192.0.2.1".to_string(),
+    );
+    guard.check().await.unwrap();
+    guard.record_failure().await.unwrap();
+    let until = repos
+      .login_failures
+      .locked_until("someone", "198.51.100.1")
+      .await
+      .unwrap()
+      .expect("a stored lock");
+    // PostgreSQL keeps microseconds
+    assert_eq!(until.timestamp(), DateTime::<Utc>::MAX_UTC.timestamp());
+    assert!(matches!(
+      guard.check().await,
+      Err(ApiError::TooManyRequests(_, _))
+    ));
+
+    db.remove().await;
+  }
+}
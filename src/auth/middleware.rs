@@ -0,0 +1,104 @@
+use std::future::{Ready, ready};
+use std::rc::Rc;
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::middleware::Next;
+use futures_util::future::LocalBoxFuture;
+
+use crate::apierror::ApiError;
+use crate::auth::auth_user::authenticate;
+use crate::auth::role::Permission;
+use crate::log;
+
+/// Rejects every request in the wrapped scope that does not carry a valid Bearer token.
+/// Use with `web::scope(..).wrap(from_fn(require_auth))`.
+pub async fn require_auth(
+  req: ServiceRequest,
+  next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+  match authenticate(req.request()).await {
+    Ok(Some(_)) => next
+      .call(req)
+      .await
+      .map(ServiceResponse::map_into_left_body),
+    Ok(None) => Ok(
+      req
+        .error_response(ApiError::missing_token())
+        .map_into_right_body(),
+    ),
+    Err(e) => Ok(req.error_response(e).map_into_right_body()),
+  }
+}
+
+/// Only lets callers whose role grants the permission through. Declared on a route with
+/// `#[get("..", wrap = "RequirePermission(Permission::ViewUsers)")]` or on a whole scope.
+pub struct RequirePermission(pub Permission);
+
+impl<S, B> Transform<S, ServiceRequest> for RequirePermission
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+  B: 'static,
+{
+  type Response = ServiceResponse<EitherBody<B>>;
+  type Error = actix_web::Error;
+  type Transform = RequirePermissionMiddleware<S>;
+  type InitError = ();
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ready(Ok(RequirePermissionMiddleware {
+      service: Rc::new(service),
+      permission: self.0,
+    }))
+  }
+}
+
+pub struct RequirePermissionMiddleware<S> {
+  service: Rc<S>,
+  permission: Permission,
+}
+
+impl<S, B> Service<ServiceRequest> for RequirePermissionMiddleware<S>
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+  B: 'static,
+{
+  type Response = ServiceResponse<EitherBody<B>>;
+  type Error = actix_web::Error;
+  type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+  forward_ready!(service);
+
+  fn call(&self, req: ServiceRequest) -> Self::Future {
+    let service = self.service.clone();
+    let permission = self.permission;
+
+    Box::pin(async move {
+      let claims = match authenticate(req.request()).await {
+        Ok(Some(claims)) => claims,
+        Ok(None) => {
+          return Ok(
+            req
+              .error_response(ApiError::missing_token())
+              .map_into_right_body(),
+          );
+        }
+        Err(e) => return Ok(req.error_response(e).map_into_right_body()),
+      };
+
+      if !claims.role.has(permission) {
+        log::debug(&format!(
+          "User {} with role {} lacks permission {:?}",
+          claims.id, claims.role, permission
+        ));
+        let error = ApiError::Forbidden("You do not have permission to do this".to_string());
+        return Ok(req.error_response(error).map_into_right_body());
+      }
+      service
+        .call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+    })
+  }
+}
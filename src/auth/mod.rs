@@ -0,0 +1,4 @@
+pub mod auth_user;
+pub mod login_guard;
+pub mod middleware;
+pub mod role;
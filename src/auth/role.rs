@@ -0,0 +1,69 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+  #[default]
+  User,
+  Moderator,
+  Admin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+  /// List and inspect every user
+  ViewUsers,
+  /// Change the role of other users
+  ManageUsers,
+  /// Deactivate apps owned by other users
+  ManageApps,
+}
+
+impl Role {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Role::User => "user",
+      Role::Moderator => "moderator",
+      Role::Admin => "admin",
+    }
+  }
+
+  pub fn permissions(&self) -> &'static [Permission] {
+    match self {
+      Role::User => &[],
+      Role::Moderator => &[Permission::ViewUsers, Permission::ManageApps],
+      Role::Admin => &[
+        Permission::ViewUsers,
+        Permission::ManageUsers,
+        Permission::ManageApps,
+      ],
+    }
+  }
+
+  pub fn has(&self, permission: Permission) -> bool {
+    self.permissions().contains(&permission)
+  }
+}
+
+impl fmt::Display for Role {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+impl FromStr for Role {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "user" => Ok(Role::User),
+      "moderator" => Ok(Role::Moderator),
+      "admin" => Ok(Role::Admin),
+      other => Err(format!("Unknown role: {}", other)),
+    }
+  }
+}
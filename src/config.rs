@@ -0,0 +1,1253 @@
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::db::PoolSettings;
+use crate::log::LogConfig;
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Longest lifetime, interval or delay that can be configured, 100 years. `chrono` can add far
+/// longer durations to the current time, so values up to this never overflow.
+const MAX_DURATION_SECS: i64 = 100 * 365 * 24 * 60 * 60;
+
+/// Application configuration. Values are read from the defaults below, then the TOML file, then
+/// environment variables and finally command-line flags, each overriding the previous source.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  pub server: ServerConfig,
+  pub database: DatabaseConfig,
+  pub auth: AuthConfig,
+  pub media: MediaConfig,
+  pub storage: StorageConfig,
+  pub mail: MailConfig,
+  pub verification: VerificationConfig,
+  pub password_reset: PasswordResetConfig,
+  pub login_protection: LoginProtectionConfig,
+  pub rate_limit: RateLimitConfig,
+  pub metrics: MetricsConfig,
+  pub log: LogConfig,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+  pub bind_address: String,
+  pub port: u16,
+  /// Number of worker threads, defaults to the number of CPU cores
+  pub workers: Option<usize>,
+  /// How long in-flight requests may keep running after a shutdown signal
+  pub shutdown_timeout_secs: u64,
+  /// Take the client address from `Forwarded` or `X-Forwarded-For`. Only enable behind a reverse
+  /// proxy that sets these headers, since clients can forge them otherwise.
+  pub trust_forwarded_headers: bool,
+}
+
+impl Default for ServerConfig {
+  fn default() -> Self {
+    ServerConfig {
+      bind_address: "127.0.0.1".to_string(),
+      port: 8080,
+      workers: None,
+      shutdown_timeout_secs: 30,
+      trust_forwarded_headers: false,
+    }
+  }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+  pub username: String,
+  pub password: String,
+  pub host: String,
+  pub port: u16,
+  pub name: String,
+  pub pool: PoolSettings,
+}
+
+impl Default for DatabaseConfig {
+  fn default() -> Self {
+    DatabaseConfig {
+      username: String::new(),
+      password: String::new(),
+      host: "localhost".to_string(),
+      port: 5432,
+      name: String::new(),
+      pool: PoolSettings::default(),
+    }
+  }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+  pub jwt_secret: String,
+  pub access_token_ttl_minutes: i64,
+  pub refresh_token_ttl_days: i64,
+}
+
+impl Default for AuthConfig {
+  fn default() -> Self {
+    AuthConfig {
+      jwt_secret: String::new(),
+      access_token_ttl_minutes: 15,
+      refresh_token_ttl_days: 30,
+    }
+  }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MediaConfig {
+  pub dir: PathBuf,
+  /// Largest accepted image upload
+  pub max_upload_bytes: u64,
+  /// Store image variants as WebP instead of the uploaded format
+  pub convert_to_webp: bool,
+}
+
+impl Default for MediaConfig {
+  fn default() -> Self {
+    MediaConfig {
+      dir: PathBuf::from("./media"),
+      max_upload_bytes: 5 * 1024 * 1024,
+      convert_to_webp: false,
+    }
+  }
+}
+
+/// Where stored media lives. Uploads are always staged in `media.dir` before they are processed.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+  pub backend: StorageBackend,
+  /// Lifetime of the links handed out for stored files
+  pub presign_expiry_secs: u64,
+  pub s3: S3Config,
+}
+
+impl Default for StorageConfig {
+  fn default() -> Self {
+    StorageConfig {
+      backend: StorageBackend::Local,
+      presign_expiry_secs: 3600,
+      s3: S3Config::default(),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+  /// Files under `media.dir`
+  Local,
+  /// An S3 compatible object store
+  S3,
+}
+
+impl FromStr for StorageBackend {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "local" => Ok(StorageBackend::Local),
+      "s3" => Ok(StorageBackend::S3),
+      other => Err(format!("Unknown storage backend: {}", other)),
+    }
+  }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+  /// Base URL of the service, e.g. `https://s3.eu-west-1.amazonaws.com` or `http://localhost:9000`
+  pub endpoint: String,
+  pub region: String,
+  pub bucket: String,
+  pub access_key_id: String,
+  pub secret_access_key: String,
+  /// Address the bucket as `{endpoint}/{bucket}` instead of `{bucket}.{host}`
+  pub path_style: bool,
+}
+
+impl Default for S3Config {
+  fn default() -> Self {
+    S3Config {
+      endpoint: String::new(),
+      region: "us-east-1".to_string(),
+      bucket: String::new(),
+      access_key_id: String::new(),
+      secret_access_key: String::new(),
+      path_style: true,
+    }
+  }
+}
+
+/// How emails are delivered
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+  pub backend: MailBackend,
+  /// Sender of every email, e.g. `Apps <no-reply@example.com>`
+  pub from: String,
+  /// Directory the `file` backend writes `.eml` files to
+  pub outbox_dir: PathBuf,
+  /// Base URL of the API used in links sent by email
+  pub public_url: String,
+  pub smtp: SmtpConfig,
+}
+
+impl Default for MailConfig {
+  fn default() -> Self {
+    MailConfig {
+      backend: MailBackend::File,
+      from: "no-reply@localhost".to_string(),
+      outbox_dir: PathBuf::from("./outbox"),
+      public_url: "http://localhost:8080".to_string(),
+      smtp: SmtpConfig::default(),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailBackend {
+  Smtp,
+  /// Writes every email to `mail.outbox_dir` instead of sending it
+  File,
+  /// Keeps emails in memory and logs them, for tests
+  Memory,
+}
+
+impl FromStr for MailBackend {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "smtp" => Ok(MailBackend::Smtp),
+      "file" => Ok(MailBackend::File),
+      "memory" => Ok(MailBackend::Memory),
+      other => Err(format!("Unknown mail backend: {}", other)),
+    }
+  }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmtpConfig {
+  pub host: String,
+  pub port: u16,
+  /// Leave empty for servers that do not require authentication
+  pub username: String,
+  pub password: String,
+  pub tls: SmtpTls,
+}
+
+impl Default for SmtpConfig {
+  fn default() -> Self {
+    SmtpConfig {
+      host: String::new(),
+      port: 587,
+      username: String::new(),
+      password: String::new(),
+      tls: SmtpTls::StartTls,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+  /// Upgrade the connection with STARTTLS, usually on port 587
+  StartTls,
+  /// Connect over TLS, usually on port 465
+  Tls,
+  /// Plain text, only for local relays
+  None,
+}
+
+impl FromStr for SmtpTls {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "starttls" => Ok(SmtpTls::StartTls),
+      "tls" => Ok(SmtpTls::Tls),
+      "none" => Ok(SmtpTls::None),
+      other => Err(format!("Unknown SMTP TLS mode: {}", other)),
+    }
+  }
+}
+
+/// Email address verification. New accounts start unverified and get a link by email.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VerificationConfig {
+  /// How long a verification link stays valid
+  pub token_ttl_hours: i64,
+  /// Minimum time between two verification emails to the same account
+  pub resend_interval_secs: i64,
+  /// Most verification emails sent to one account in 24 hours
+  pub max_emails_per_day: i64,
+  /// Let unverified users log in
+  pub allow_unverified_login: bool,
+  /// Let unverified users create, change and delete apps
+  pub allow_unverified_writes: bool,
+}
+
+impl Default for VerificationConfig {
+  fn default() -> Self {
+    VerificationConfig {
+      token_ttl_hours: 24,
+      resend_interval_secs: 60,
+      max_emails_per_day: 5,
+      allow_unverified_login: true,
+      allow_unverified_writes: false,
+    }
+  }
+}
+
+/// Forgotten password recovery by email
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordResetConfig {
+  /// Page of the client app that asks for the new password; the emailed link adds `?token=`
+  pub url: String,
+  /// How long a reset link stays valid
+  pub token_ttl_minutes: i64,
+  /// Minimum time between two reset emails to the same account
+  pub resend_interval_secs: i64,
+  /// Most reset emails sent to one account in 24 hours
+  pub max_emails_per_day: i64,
+}
+
+impl Default for PasswordResetConfig {
+  fn default() -> Self {
+    PasswordResetConfig {
+      url: "http://localhost:3000/reset-password".to_string(),
+      token_ttl_minutes: 60,
+      resend_interval_secs: 60,
+      max_emails_per_day: 5,
+    }
+  }
+}
+
+/// Brute-force protection for logins. Failed attempts are counted per account and per client IP
+/// address. Past the free attempts each failure blocks further logins for a delay that doubles
+/// every time, and reaching the maximum locks logins for `lockout_secs`.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginProtectionConfig {
+  /// Failed logins to one account before delays start
+  pub account_free_attempts: i32,
+  /// Failed logins after which an account is locked
+  pub account_max_failures: i32,
+  /// Failed logins from one IP address before delays start
+  pub ip_free_attempts: i32,
+  /// Failed logins after which an IP address is locked
+  pub ip_max_failures: i32,
+  /// First delay, doubled with every further failure
+  pub backoff_base_secs: i64,
+  /// Longest delay before the lockout
+  pub backoff_max_secs: i64,
+  /// How long a lockout lasts
+  pub lockout_secs: i64,
+  /// Failures are forgotten after this long without another one
+  pub reset_after_secs: i64,
+}
+
+impl Default for LoginProtectionConfig {
+  fn default() -> Self {
+    LoginProtectionConfig {
+      account_free_attempts: 3,
+      account_max_failures: 10,
+      ip_free_attempts: 20,
+      ip_max_failures: 100,
+      backoff_base_secs: 1,
+      backoff_max_secs: 60,
+      lockout_secs: 900,
+      reset_after_secs: 3600,
+    }
+  }
+}
+
+/// Request rate limits for groups of routes
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+  pub enabled: bool,
+  pub backend: RateLimitBackend,
+  /// `POST /api/users/register`
+  pub register: RateLimitRule,
+  /// `POST /api/users/login`
+  pub login: RateLimitRule,
+  /// Image uploads with `POST /api/apps`
+  pub upload: RateLimitRule,
+}
+
+impl Default for RateLimitConfig {
+  fn default() -> Self {
+    RateLimitConfig {
+      enabled: true,
+      backend: RateLimitBackend::Memory,
+      register: RateLimitRule {
+        requests: 5,
+        window_secs: 3600,
+        key: RateLimitKey::Ip,
+      },
+      login: RateLimitRule {
+        requests: 10,
+        window_secs: 60,
+        key: RateLimitKey::Ip,
+      },
+      upload: RateLimitRule {
+        requests: 30,
+        window_secs: 3600,
+        key: RateLimitKey::User,
+      },
+    }
+  }
+}
+
+/// Access to `GET /metrics`
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+  /// Bearer token scrapers must send. The endpoint is disabled while this is empty.
+  pub token: String,
+}
+
+/// Allows `requests` per `window_secs` for each key. A full window's worth can be used at once,
+/// after which requests are let through as evenly spaced as the rate allows.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitRule {
+  pub requests: u32,
+  pub window_secs: u64,
+  /// What requests are counted by
+  pub key: RateLimitKey,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+  /// Counts in the memory of each process
+  Memory,
+  /// Counts in the database, shared by every instance
+  Postgres,
+}
+
+impl FromStr for RateLimitBackend {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "memory" => Ok(RateLimitBackend::Memory),
+      "postgres" => Ok(RateLimitBackend::Postgres),
+      other => Err(format!("Unknown rate limit backend: {}", other)),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+  /// The client IP address
+  Ip,
+  /// The authenticated user, or the IP address without a valid token
+  User,
+}
+
+impl FromStr for RateLimitKey {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "ip" => Ok(RateLimitKey::Ip),
+      "user" => Ok(RateLimitKey::User),
+      other => Err(format!("Unknown rate limit key: {}", other)),
+    }
+  }
+}
+
+/// Environment variables and the configuration keys they set
+const ENV_VARS: &[(&str, &str)] = &[
+  ("SERVER_BIND_ADDRESS", "server.bind_address"),
+  ("SERVER_PORT", "server.port"),
+  ("SERVER_WORKERS", "server.workers"),
+  (
+    "SERVER_SHUTDOWN_TIMEOUT_SECS",
+    "server.shutdown_timeout_secs",
+  ),
+  (
+    "SERVER_TRUST_FORWARDED_HEADERS",
+    "server.trust_forwarded_headers",
+  ),
+  ("DATABASE_USERNAME", "database.username"),
+  ("DATABASE_PASSWORD", "database.password"),
+  ("DATABASE_HOST", "database.host"),
+  ("DATABASE_PORT", "database.port"),
+  ("DATABASE_NAME", "database.name"),
+  ("DATABASE_POOL_MAX_SIZE", "database.pool.max_size"),
+  (
+    "DATABASE_POOL_WAIT_TIMEOUT_MS",
+    "database.pool.wait_timeout_ms",
+  ),
+  (
+    "DATABASE_POOL_CREATE_TIMEOUT_MS",
+    "database.pool.create_timeout_ms",
+  ),
+  (
+    "DATABASE_POOL_RECYCLE_TIMEOUT_MS",
+    "database.pool.recycle_timeout_ms",
+  ),
+  ("JWT_SECRET", "auth.jwt_secret"),
+  ("JWT_ACCESS_TTL_MINUTES", "auth.access_token_ttl_minutes"),
+  ("JWT_REFRESH_TTL_DAYS", "auth.refresh_token_ttl_days"),
+  ("MEDIA_DIR", "media.dir"),
+  ("MEDIA_MAX_UPLOAD_BYTES", "media.max_upload_bytes"),
+  ("MEDIA_CONVERT_TO_WEBP", "media.convert_to_webp"),
+  ("STORAGE_BACKEND", "storage.backend"),
+  ("STORAGE_PRESIGN_EXPIRY_SECS", "storage.presign_expiry_secs"),
+  ("S3_ENDPOINT", "storage.s3.endpoint"),
+  ("S3_REGION", "storage.s3.region"),
+  ("S3_BUCKET", "storage.s3.bucket"),
+  ("S3_ACCESS_KEY_ID", "storage.s3.access_key_id"),
+  ("S3_SECRET_ACCESS_KEY", "storage.s3.secret_access_key"),
+  ("S3_PATH_STYLE", "storage.s3.path_style"),
+  ("MAIL_BACKEND", "mail.backend"),
+  ("MAIL_FROM", "mail.from"),
+  ("MAIL_OUTBOX_DIR", "mail.outbox_dir"),
+  ("MAIL_PUBLIC_URL", "mail.public_url"),
+  ("SMTP_HOST", "mail.smtp.host"),
+  ("SMTP_PORT", "mail.smtp.port"),
+  ("SMTP_USERNAME", "mail.smtp.username"),
+  ("SMTP_PASSWORD", "mail.smtp.password"),
+  ("SMTP_TLS", "mail.smtp.tls"),
+  (
+    "VERIFICATION_TOKEN_TTL_HOURS",
+    "verification.token_ttl_hours",
+  ),
+  (
+    "VERIFICATION_RESEND_INTERVAL_SECS",
+    "verification.resend_interval_secs",
+  ),
+  (
+    "VERIFICATION_MAX_EMAILS_PER_DAY",
+    "verification.max_emails_per_day",
+  ),
+  (
+    "VERIFICATION_ALLOW_UNVERIFIED_LOGIN",
+    "verification.allow_unverified_login",
+  ),
+  (
+    "VERIFICATION_ALLOW_UNVERIFIED_WRITES",
+    "verification.allow_unverified_writes",
+  ),
+  ("PASSWORD_RESET_URL", "password_reset.url"),
+  (
+    "PASSWORD_RESET_TOKEN_TTL_MINUTES",
+    "password_reset.token_ttl_minutes",
+  ),
+  (
+    "PASSWORD_RESET_RESEND_INTERVAL_SECS",
+    "password_reset.resend_interval_secs",
+  ),
+  (
+    "PASSWORD_RESET_MAX_EMAILS_PER_DAY",
+    "password_reset.max_emails_per_day",
+  ),
+  (
+    "LOGIN_ACCOUNT_FREE_ATTEMPTS",
+    "login_protection.account_free_attempts",
+  ),
+  (
+    "LOGIN_ACCOUNT_MAX_FAILURES",
+    "login_protection.account_max_failures",
+  ),
+  (
+    "LOGIN_IP_FREE_ATTEMPTS",
+    "login_protection.ip_free_attempts",
+  ),
+  ("LOGIN_IP_MAX_FAILURES", "login_protection.ip_max_failures"),
+  (
+    "LOGIN_BACKOFF_BASE_SECS",
+    "login_protection.backoff_base_secs",
+  ),
+  (
+    "LOGIN_BACKOFF_MAX_SECS",
+    "login_protection.backoff_max_secs",
+  ),
+  ("LOGIN_LOCKOUT_SECS", "login_protection.lockout_secs"),
+  (
+    "LOGIN_RESET_AFTER_SECS",
+    "login_protection.reset_after_secs",
+  ),
+  ("RATE_LIMIT_ENABLED", "rate_limit.enabled"),
+  ("RATE_LIMIT_BACKEND", "rate_limit.backend"),
+  (
+    "RATE_LIMIT_REGISTER_REQUESTS",
+    "rate_limit.register.requests",
+  ),
+  (
+    "RATE_LIMIT_REGISTER_WINDOW_SECS",
+    "rate_limit.register.window_secs",
+  ),
+  ("RATE_LIMIT_REGISTER_KEY", "rate_limit.register.key"),
+  ("RATE_LIMIT_LOGIN_REQUESTS", "rate_limit.login.requests"),
+  (
+    "RATE_LIMIT_LOGIN_WINDOW_SECS",
+    "rate_limit.login.window_secs",
+  ),
+  ("RATE_LIMIT_LOGIN_KEY", "rate_limit.login.key"),
+  ("RATE_LIMIT_UPLOAD_REQUESTS", "rate_limit.upload.requests"),
+  (
+    "RATE_LIMIT_UPLOAD_WINDOW_SECS",
+    "rate_limit.upload.window_secs",
+  ),
+  ("RATE_LIMIT_UPLOAD_KEY", "rate_limit.upload.key"),
+  ("METRICS_TOKEN", "metrics.token"),
+  ("LOG_LEVEL", "log.level"),
+  ("LOG_FORMAT", "log.format"),
+  ("LOG_DIR", "log.dir"),
+  ("LOG_STDOUT", "log.stdout"),
+  ("LOG_MAX_FILE_SIZE_MB", "log.max_file_size_mb"),
+  ("LOG_MAX_AGE_DAYS", "log.max_age_days"),
+  ("LOG_MAX_FILES", "log.max_files"),
+  ("LOG_BUFFER_SIZE", "log.buffer_size"),
+];
+
+/// Short command-line flags and the configuration keys they set
+const FLAG_ALIASES: &[(&str, &str)] = &[
+  ("bind", "server.bind_address"),
+  ("port", "server.port"),
+  ("workers", "server.workers"),
+  ("log-level", "log.level"),
+];
+
+/// Every problem found while loading the configuration
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "Invalid configuration:")?;
+    for problem in &self.0 {
+      writeln!(f, "  - {}", problem)?;
+    }
+    Ok(())
+  }
+}
+
+/// Parsed command line: `[--config FILE] [--key value | --key=value]... [command args...]`
+pub struct Cli {
+  pub config_file: Option<PathBuf>,
+  pub overrides: Vec<(String, String)>,
+  pub command: Vec<String>,
+  pub help: bool,
+}
+
+impl Cli {
+  pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, ConfigError> {
+    let mut cli = Cli {
+      config_file: None,
+      overrides: Vec::new(),
+      command: Vec::new(),
+      help: false,
+    };
+    let mut problems = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+      let Some(flag) = arg.strip_prefix("--") else {
+        cli.command.push(arg);
+        continue;
+      };
+      if flag == "help" {
+        cli.help = true;
+        continue;
+      }
+
+      let (name, value) = match flag.split_once('=') {
+        Some((name, value)) => (name.to_string(), Some(value.to_string())),
+        None => (flag.to_string(), args.next()),
+      };
+      let Some(value) = value else {
+        problems.push(format!("--{} needs a value", name));
+        continue;
+      };
+
+      if name == "config" {
+        cli.config_file = Some(PathBuf::from(value));
+        continue;
+      }
+      let key = FLAG_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map(|(_, key)| key.to_string())
+        .unwrap_or_else(|| name.replace('-', "_"));
+      cli.overrides.push((key, value));
+    }
+
+    if problems.is_empty() {
+      Ok(cli)
+    } else {
+      Err(ConfigError(problems))
+    }
+  }
+
+  pub fn usage() -> String {
+    let mut usage = String::from(
+      "Usage: rest_api_first [--config FILE] [--KEY VALUE]... [migrate up|down VERSION|status]\n\n\
+       Every configuration key can be set as a flag, e.g. --server.port 9000 or --log.level=debug.\n\
+       Shorthands:\n",
+    );
+    for (alias, key) in FLAG_ALIASES {
+      usage.push_str(&format!("  --{:<12} {}\n", alias, key));
+    }
+    usage.push_str("\nEnvironment variables:\n");
+    for (var, key) in ENV_VARS {
+      usage.push_str(&format!("  {:<34} {}\n", var, key));
+    }
+    usage
+  }
+}
+
+impl Config {
+  /// Loads the configuration file (from `--config`, `CONFIG_FILE` or `./config.toml` if it
+  /// exists), applies environment variables and flags, and validates the result
+  pub fn load(cli: &Cli) -> Result<Config, ConfigError> {
+    Config::load_with(cli, |var| env::var(var).ok())
+  }
+
+  /// `load` with environment variables looked up through `env`
+  fn load_with(cli: &Cli, env: impl Fn(&str) -> Option<String>) -> Result<Config, ConfigError> {
+    let file = cli
+      .config_file
+      .clone()
+      .or_else(|| env("CONFIG_FILE").map(PathBuf::from));
+
+    let mut config = match file {
+      Some(path) => Config::from_file(&path)?,
+      None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+        Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+      }
+      None => Config::default(),
+    };
+
+    let mut problems = Vec::new();
+    for (var, key) in ENV_VARS {
+      if let Some(value) = env(var)
+        && let Err(e) = config.set(key, &value)
+      {
+        problems.push(format!("{}: {}", var, e));
+      }
+    }
+    for (key, value) in &cli.overrides {
+      if let Err(e) = config.set(key, value) {
+        problems.push(format!("--{}: {}", key, e));
+      }
+    }
+
+    problems.extend(config.validate());
+    if problems.is_empty() {
+      Ok(config)
+    } else {
+      Err(ConfigError(problems))
+    }
+  }
+
+  fn from_file(path: &Path) -> Result<Config, ConfigError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+      ConfigError(vec![format!(
+        "Failed to read config file {}: {}",
+        path.display(),
+        e
+      )])
+    })?;
+    toml::from_str(&contents)
+      .map_err(|e| ConfigError(vec![format!("{}: {}", path.display(), e.message())]))
+  }
+
+  /// Sets one value by its dotted key, e.g. `server.port`
+  pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+    match key {
+      "server.bind_address" => self.server.bind_address = value.to_string(),
+      "server.port" => self.server.port = parse(value)?,
+      "server.workers" => self.server.workers = Some(parse(value)?),
+      "server.shutdown_timeout_secs" => self.server.shutdown_timeout_secs = parse(value)?,
+      "server.trust_forwarded_headers" => self.server.trust_forwarded_headers = parse(value)?,
+      "database.username" => self.database.username = value.to_string(),
+      "database.password" => self.database.password = value.to_string(),
+      "database.host" => self.database.host = value.to_string(),
+      "database.port" => self.database.port = parse(value)?,
+      "database.name" => self.database.name = value.to_string(),
+      "database.pool.max_size" => self.database.pool.max_size = parse(value)?,
+      "database.pool.wait_timeout_ms" => self.database.pool.wait_timeout_ms = parse(value)?,
+      "database.pool.create_timeout_ms" => self.database.pool.create_timeout_ms = parse(value)?,
+      "database.pool.recycle_timeout_ms" => self.database.pool.recycle_timeout_ms = parse(value)?,
+      "auth.jwt_secret" => self.auth.jwt_secret = value.to_string(),
+      "auth.access_token_ttl_minutes" => self.auth.access_token_ttl_minutes = parse(value)?,
+      "auth.refresh_token_ttl_days" => self.auth.refresh_token_ttl_days = parse(value)?,
+      "media.dir" => self.media.dir = PathBuf::from(value),
+      "media.max_upload_bytes" => self.media.max_upload_bytes = parse(value)?,
+      "media.convert_to_webp" => self.media.convert_to_webp = parse(value)?,
+      "storage.backend" => self.storage.backend = parse(value)?,
+      "storage.presign_expiry_secs" => self.storage.presign_expiry_secs = parse(value)?,
+      "storage.s3.endpoint" => self.storage.s3.endpoint = value.to_string(),
+      "storage.s3.region" => self.storage.s3.region = value.to_string(),
+      "storage.s3.bucket" => self.storage.s3.bucket = value.to_string(),
+      "storage.s3.access_key_id" => self.storage.s3.access_key_id = value.to_string(),
+      "storage.s3.secret_access_key" => self.storage.s3.secret_access_key = value.to_string(),
+      "storage.s3.path_style" => self.storage.s3.path_style = parse(value)?,
+      "mail.backend" => self.mail.backend = parse(value)?,
+      "mail.from" => self.mail.from = value.to_string(),
+      "mail.outbox_dir" => self.mail.outbox_dir = PathBuf::from(value),
+      "mail.public_url" => self.mail.public_url = value.to_string(),
+      "mail.smtp.host" => self.mail.smtp.host = value.to_string(),
+      "mail.smtp.port" => self.mail.smtp.port = parse(value)?,
+      "mail.smtp.username" => self.mail.smtp.username = value.to_string(),
+      "mail.smtp.password" => self.mail.smtp.password = value.to_string(),
+      "mail.smtp.tls" => self.mail.smtp.tls = parse(value)?,
+      "verification.token_ttl_hours" => self.verification.token_ttl_hours = parse(value)?,
+      "verification.resend_interval_secs" => self.verification.resend_interval_secs = parse(value)?,
+      "verification.max_emails_per_day" => self.verification.max_emails_per_day = parse(value)?,
+      "verification.allow_unverified_login" => {
+        self.verification.allow_unverified_login = parse(value)?
+      }
+      "verification.allow_unverified_writes" => {
+        self.verification.allow_unverified_writes = parse(value)?
+      }
+      "password_reset.url" => self.password_reset.url = value.to_string(),
+      "password_reset.token_ttl_minutes" => self.password_reset.token_ttl_minutes = parse(value)?,
+      "password_reset.resend_interval_secs" => {
+        self.password_reset.resend_interval_secs = parse(value)?
+      }
+      "password_reset.max_emails_per_day" => self.password_reset.max_emails_per_day = parse(value)?,
+      "login_protection.account_free_attempts" => {
+        self.login_protection.account_free_attempts = parse(value)?
+      }
+      "login_protection.account_max_failures" => {
+        self.login_protection.account_max_failures = parse(value)?
+      }
+      "login_protection.ip_free_attempts" => self.login_protection.ip_free_attempts = parse(value)?,
+      "login_protection.ip_max_failures" => self.login_protection.ip_max_failures = parse(value)?,
+      "login_protection.backoff_base_secs" => {
+        self.login_protection.backoff_base_secs = parse(value)?
+      }
+      "login_protection.backoff_max_secs" => self.login_protection.backoff_max_secs = parse(value)?,
+      "login_protection.lockout_secs" => self.login_protection.lockout_secs = parse(value)?,
+      "login_protection.reset_after_secs" => self.login_protection.reset_after_secs = parse(value)?,
+      "rate_limit.enabled" => self.rate_limit.enabled = parse(value)?,
+      "rate_limit.backend" => self.rate_limit.backend = parse(value)?,
+      "rate_limit.register.requests" => self.rate_limit.register.requests = parse(value)?,
+      "rate_limit.register.window_secs" => self.rate_limit.register.window_secs = parse(value)?,
+      "rate_limit.register.key" => self.rate_limit.register.key = parse(value)?,
+      "rate_limit.login.requests" => self.rate_limit.login.requests = parse(value)?,
+      "rate_limit.login.window_secs" => self.rate_limit.login.window_secs = parse(value)?,
+      "rate_limit.login.key" => self.rate_limit.login.key = parse(value)?,
+      "rate_limit.upload.requests" => self.rate_limit.upload.requests = parse(value)?,
+      "rate_limit.upload.window_secs" => self.rate_limit.upload.window_secs = parse(value)?,
+      "rate_limit.upload.key" => self.rate_limit.upload.key = parse(value)?,
+      "metrics.token" => self.metrics.token = value.to_string(),
+      "log.level" => self.log.level = parse(value)?,
+      "log.format" => self.log.format = parse(value)?,
+      "log.dir" if value.is_empty() => self.log.dir = None,
+      "log.dir" => self.log.dir = Some(PathBuf::from(value)),
+      "log.stdout" => self.log.stdout = parse(value)?,
+      "log.max_file_size_mb" => self.log.max_file_size_mb = parse(value)?,
+      "log.max_age_days" => self.log.max_age_days = parse(value)?,
+      "log.max_files" => self.log.max_files = parse(value)?,
+      "log.buffer_size" => self.log.buffer_size = parse(value)?,
+      _ => return Err(format!("unknown configuration key {}", key)),
+    }
+    Ok(())
+  }
+
+  fn validate(&self) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut require = |ok: bool, message: &str| {
+      if !ok {
+        problems.push(message.to_string());
+      }
+    };
+
+    require(
+      !self.server.bind_address.is_empty(),
+      "server.bind_address must not be empty",
+    );
+    require(
+      self.server.workers != Some(0),
+      "server.workers must be at least 1",
+    );
+    require(
+      !self.database.username.is_empty(),
+      "database.username is required (DATABASE_USERNAME)",
+    );
+    require(
+      !self.database.host.is_empty(),
+      "database.host is required (DATABASE_HOST)",
+    );
+    require(
+      !self.database.name.is_empty(),
+      "database.name is required (DATABASE_NAME)",
+    );
+    require(
+      self.database.pool.max_size > 0,
+      "database.pool.max_size must be at least 1",
+    );
+    require(
+      !self.auth.jwt_secret.is_empty(),
+      "auth.jwt_secret is required (JWT_SECRET)",
+    );
+    require(
+      duration_in_range(self.auth.access_token_ttl_minutes, 60),
+      &duration_range("auth.access_token_ttl_minutes", 60),
+    );
+    require(
+      duration_in_range(self.auth.refresh_token_ttl_days, 24 * 60 * 60),
+      &duration_range("auth.refresh_token_ttl_days", 24 * 60 * 60),
+    );
+    // Both are at most 100 years, so the multiplication cannot overflow
+    require(
+      self.auth.refresh_token_ttl_days.saturating_mul(24 * 60) > self.auth.access_token_ttl_minutes,
+      "auth.refresh_token_ttl_days must be longer than the access token lifetime",
+    );
+    require(
+      !self.media.dir.as_os_str().is_empty(),
+      "media.dir must not be empty",
+    );
+    require(
+      self.media.max_upload_bytes > 0,
+      "media.max_upload_bytes must be at least 1",
+    );
+    require(
+      (1..=7 * 24 * 60 * 60).contains(&self.storage.presign_expiry_secs),
+      "storage.presign_expiry_secs must be between 1 and 604800",
+    );
+    if self.storage.backend == StorageBackend::S3 {
+      let s3 = &self.storage.s3;
+      require(
+        s3.endpoint.starts_with("http://") || s3.endpoint.starts_with("https://"),
+        "storage.s3.endpoint must be an http:// or https:// URL (S3_ENDPOINT)",
+      );
+      require(
+        !s3.bucket.is_empty(),
+        "storage.s3.bucket is required (S3_BUCKET)",
+      );
+      require(
+        !s3.access_key_id.is_empty() && !s3.secret_access_key.is_empty(),
+        "storage.s3.access_key_id and storage.s3.secret_access_key are required",
+      );
+    }
+    require(
+      self.mail.from.parse::<lettre::message::Mailbox>().is_ok(),
+      "mail.from must be an email address (MAIL_FROM)",
+    );
+    require(
+      self.mail.public_url.starts_with("http://") || self.mail.public_url.starts_with("https://"),
+      "mail.public_url must be an http:// or https:// URL (MAIL_PUBLIC_URL)",
+    );
+    match self.mail.backend {
+      MailBackend::Smtp => require(
+        !self.mail.smtp.host.is_empty(),
+        "mail.smtp.host is required (SMTP_HOST)",
+      ),
+      MailBackend::File => require(
+        !self.mail.outbox_dir.as_os_str().is_empty(),
+        "mail.outbox_dir must not be empty",
+      ),
+      MailBackend::Memory => {}
+    }
+    require(
+      duration_in_range(self.verification.token_ttl_hours, 60 * 60),
+      &duration_range("verification.token_ttl_hours", 60 * 60),
+    );
+    require(
+      (0..=MAX_DURATION_SECS).contains(&self.verification.resend_interval_secs),
+      &format!(
+        "verification.resend_interval_secs must be between 0 and {}",
+        MAX_DURATION_SECS
+      ),
+    );
+    require(
+      self.verification.max_emails_per_day > 0,
+      "verification.max_emails_per_day must be at least 1",
+    );
+    require(
+      self.password_reset.url.starts_with("http://")
+        || self.password_reset.url.starts_with("https://"),
+      "password_reset.url must be an http:// or https:// URL (PASSWORD_RESET_URL)",
+    );
+    require(
+      duration_in_range(self.password_reset.token_ttl_minutes, 60),
+      &duration_range("password_reset.token_ttl_minutes", 60),
+    );
+    require(
+      (0..=MAX_DURATION_SECS).contains(&self.password_reset.resend_interval_secs),
+      &format!(
+        "password_reset.resend_interval_secs must be between 0 and {}",
+        MAX_DURATION_SECS
+      ),
+    );
+    require(
+      self.password_reset.max_emails_per_day > 0,
+      "password_reset.max_emails_per_day must be at least 1",
+    );
+    let login = &self.login_protection;
+    require(
+      login.account_free_attempts >= 0 && login.ip_free_attempts >= 0,
+      "login_protection.account_free_attempts and ip_free_attempts must not be negative",
+    );
+    require(
+      login.account_max_failures > login.account_free_attempts,
+      "login_protection.account_max_failures must be greater than account_free_attempts",
+    );
+    require(
+      login.ip_max_failures > login.ip_free_attempts,
+      "login_protection.ip_max_failures must be greater than ip_free_attempts",
+    );
+    require(
+      login.backoff_base_secs > 0 && login.backoff_max_secs >= login.backoff_base_secs,
+      "login_protection.backoff_base_secs must be positive and at most backoff_max_secs",
+    );
+    for (key, secs) in [
+      ("login_protection.backoff_max_secs", login.backoff_max_secs),
+      ("login_protection.lockout_secs", login.lockout_secs),
+      ("login_protection.reset_after_secs", login.reset_after_secs),
+    ] {
+      require(duration_in_range(secs, 1), &duration_range(key, 1));
+    }
+    for (name, rule) in [
+      ("register", &self.rate_limit.register),
+      ("login", &self.rate_limit.login),
+      ("upload", &self.rate_limit.upload),
+    ] {
+      require(
+        rule.requests > 0 && rule.window_secs > 0,
+        &format!(
+          "rate_limit.{}.requests and window_secs must be at least 1",
+          name
+        ),
+      );
+    }
+    require(
+      self.log.max_file_size_mb > 0,
+      "log.max_file_size_mb must be at least 1",
+    );
+    require(
+      self.log.max_file_size_mb.checked_mul(1024 * 1024).is_some(),
+      "log.max_file_size_mb is too large",
+    );
+    // A zero sized queue would hand lines over only while the writer thread waits for one
+    require(
+      self.log.buffer_size > 0,
+      "log.buffer_size must be at least 1",
+    );
+    problems
+  }
+}
+
+/// Whether `value` units of `unit_secs` seconds each are positive and at most 100 years
+fn duration_in_range(value: i64, unit_secs: i64) -> bool {
+  value > 0
+    && value
+      .checked_mul(unit_secs)
+      .is_some_and(|secs| secs <= MAX_DURATION_SECS)
+}
+
+/// The problem reported when `duration_in_range` fails for `key`
+fn duration_range(key: &str, unit_secs: i64) -> String {
+  format!(
+    "{} must be between 1 and {}",
+    key,
+    MAX_DURATION_SECS / unit_secs
+  )
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String>
+where
+  T::Err: fmt::Display,
+{
+  value
+    .trim()
+    .parse()
+    .map_err(|e| format!("invalid value {:?}: {}", value, e))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use uuid::Uuid;
+
+  use super::*;
+  use crate::log::Level;
+
+  fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+  }
+
+  /// Writes a config file to load, so a `config.toml` in the working directory is not picked up
+  fn config_file(contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("config-test-{}.toml", Uuid::new_v4()));
+    std::fs::write(&path, contents).unwrap();
+    path
+  }
+
+  /// The settings `validate` requires on top of the defaults
+  fn valid() -> Config {
+    let mut config = Config::default();
+    config.database.username = "api".to_string();
+    config.database.name = "api".to_string();
+    config.auth.jwt_secret = "secret".to_string();
+    config
+  }
+
+  #[test]
+  fn parses_flags_shorthands_and_commands() {
+    let cli = Cli::parse(args(&[
+      "--config",
+      "api.toml",
+      "--port=9000",
+      "--log-level",
+      "debug",
+      "--media.max-upload-bytes",
+      "1024",
+      "migrate",
+      "down",
+      "3",
+    ]))
+    .unwrap();
+    assert_eq!(cli.config_file, Some(PathBuf::from("api.toml")));
+    assert_eq!(
+      cli.overrides,
+      [
+        ("server.port".to_string(), "9000".to_string()),
+        ("log.level".to_string(), "debug".to_string()),
+        ("media.max_upload_bytes".to_string(), "1024".to_string()),
+      ]
+    );
+    assert_eq!(cli.command, ["migrate", "down", "3"]);
+    assert!(!cli.help);
+    assert!(Cli::parse(args(&["--help"])).unwrap().help);
+  }
+
+  #[test]
+  fn flags_without_a_value_are_rejected() {
+    let error = Cli::parse(args(&["--port"])).err().unwrap();
+    assert_eq!(error.0, ["--port needs a value"]);
+  }
+
+  #[test]
+  fn flags_override_env_which_overrides_the_file() {
+    let path = config_file(
+      "[server]\nport = 9000\nbind_address = \"0.0.0.0\"\n\n\
+       [database]\nusername = \"file\"\nname = \"api\"\n\n\
+       [auth]\njwt_secret = \"from file\"\n\n[log]\nlevel = \"debug\"\n",
+    );
+    let env: HashMap<&str, &str> = HashMap::from([
+      ("CONFIG_FILE", path.to_str().unwrap()),
+      ("SERVER_PORT", "9001"),
+      ("DATABASE_USERNAME", "env"),
+    ]);
+    let cli = Cli::parse(args(&["--port", "9002"])).unwrap();
+
+    let config = Config::load_with(&cli, |var| env.get(var).map(|v| v.to_string())).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(config.server.port, 9002);
+    assert_eq!(config.database.username, "env");
+    assert_eq!(config.server.bind_address, "0.0.0.0");
+    assert_eq!(config.auth.jwt_secret, "from file");
+    assert_eq!(config.log.level, Level::Debug);
+    assert_eq!(config.media.max_upload_bytes, 5 * 1024 * 1024);
+  }
+
+  #[test]
+  fn load_reports_every_problem() {
+    let path = config_file("");
+    let env = HashMap::from([("SERVER_PORT", "http"), ("LOG_BUFFER_SIZE", "0")]);
+    let cli = Cli::parse(args(&[
+      "--config",
+      path.to_str().unwrap(),
+      "--no.such.key",
+      "1",
+    ]))
+    .unwrap();
+    let error = Config::load_with(&cli, |var| env.get(var).map(|v| v.to_string()))
+      .err()
+      .unwrap();
+    std::fs::remove_file(path).unwrap();
+    let problems = error.0.join("\n");
+    assert!(problems.contains("SERVER_PORT: invalid value \"http\""));
+    assert!(problems.contains("--no.such.key: unknown configuration key no.such.key"));
+    assert!(problems.contains("database.username is required"));
+    assert!(problems.contains("log.buffer_size must be at least 1"));
+  }
+
+  #[test]
+  fn defaults_with_required_settings_are_valid() {
+    assert!(valid().validate().is_empty());
+  }
+
+  #[test]
+  fn durations_are_bounded() {
+    for (key, value) in [
+      ("auth.access_token_ttl_minutes", "0"),
+      ("auth.access_token_ttl_minutes", "60000000"),
+      ("auth.refresh_token_ttl_days", "100000000"),
+      ("auth.refresh_token_ttl_days", "9223372036854775807"),
+      ("verification.token_ttl_hours", "900000"),
+      ("verification.resend_interval_secs", "-1"),
+      ("password_reset.token_ttl_minutes", "60000000"),
+      ("password_reset.resend_interval_secs", "9223372036854775807"),
+      ("login_protection.backoff_max_secs", "9223372036854775807"),
+      ("login_protection.lockout_secs", "0"),
+      ("login_protection.lockout_secs", "9223372036854775807"),
+      ("login_protection.reset_after_secs", "3153600001"),
+    ] {
+      let mut config = valid();
+      config.set(key, value).unwrap();
+      let problems = config.validate();
+      assert!(
+        problems.iter().any(|p| p.starts_with(key)),
+        "{} = {} gave {:?}",
+        key,
+        value,
+        problems
+      );
+    }
+
+    let mut config = valid();
+    config.set("auth.refresh_token_ttl_days", "36500").unwrap();
+    config
+      .set("verification.token_ttl_hours", "876000")
+      .unwrap();
+    assert!(config.validate().is_empty());
+  }
+
+  #[test]
+  fn refresh_tokens_outlive_access_tokens() {
+    let mut config = valid();
+    config.set("auth.access_token_ttl_minutes", "1440").unwrap();
+    config.set("auth.refresh_token_ttl_days", "1").unwrap();
+    assert_eq!(
+      config.validate(),
+      ["auth.refresh_token_ttl_days must be longer than the access token lifetime"]
+    );
+  }
+
+  #[test]
+  fn log_file_size_must_fit_in_bytes() {
+    let mut config = valid();
+    config
+      .set("log.max_file_size_mb", &u64::MAX.to_string())
+      .unwrap();
+    assert_eq!(config.validate(), ["log.max_file_size_mb is too large"]);
+  }
+}
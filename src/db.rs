@@ -1,34 +1,103 @@
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio_postgres::Error as PgError;
-use tokio_postgres::{Client, NoTls};
+use std::time::Duration;
 
+use deadpool_postgres::{
+  Config, CreatePoolError, ManagerConfig, Object, Pool, PoolConfig, RecyclingMethod, Runtime,
+  Status, Timeouts,
+};
+use serde::Deserialize;
+use tokio_postgres::NoTls;
+
+use crate::config::DatabaseConfig;
+use crate::dberror::DbError;
 use crate::log;
 
+/// Sizing and timeout settings for the connection pool
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolSettings {
+  pub max_size: usize,
+  pub wait_timeout_ms: u64,
+  pub create_timeout_ms: u64,
+  pub recycle_timeout_ms: u64,
+}
+
+impl Default for PoolSettings {
+  fn default() -> Self {
+    PoolSettings {
+      max_size: 16,
+      wait_timeout_ms: 5000,
+      create_timeout_ms: 5000,
+      recycle_timeout_ms: 5000,
+    }
+  }
+}
+
 #[derive(Clone)]
 pub struct DbPool {
-  client: Arc<Mutex<Client>>,
+  pool: Pool,
 }
 
 impl DbPool {
-  // Initialize a new database connection
-  pub async fn new(connection_string: &str) -> Result<DbPool, PgError> {
-    let (client, connection) = tokio_postgres::connect(connection_string, NoTls).await?;
-
-    tokio::spawn(async move {
-      if let Err(e) = connection.await {
-        log::error(&format!("Connection error: {}", e));
-      }
+  // Build the pool and check out one connection to make sure the database is reachable. The
+  // settings are passed as separate fields rather than a URL, so the password needs no escaping.
+  pub async fn new(database: &DatabaseConfig) -> Result<DbPool, DbError> {
+    let settings = &database.pool;
+    let mut config = Config::new();
+    config.user = Some(database.username.clone());
+    config.password = Some(database.password.clone());
+    config.host = Some(database.host.clone());
+    config.port = Some(database.port);
+    config.dbname = Some(database.name.clone());
+    config.manager = Some(ManagerConfig {
+      recycling_method: RecyclingMethod::Fast,
     });
+    config.pool = Some(PoolConfig {
+      max_size: settings.max_size,
+      timeouts: Timeouts {
+        wait: Some(Duration::from_millis(settings.wait_timeout_ms)),
+        create: Some(Duration::from_millis(settings.create_timeout_ms)),
+        recycle: Some(Duration::from_millis(settings.recycle_timeout_ms)),
+      },
+      ..Default::default()
+    });
+
+    let pool = config
+      .create_pool(Some(Runtime::Tokio1), NoTls)
+      .map_err(|e| match e {
+        CreatePoolError::Config(e) => DbError::ConfigError(e.to_string()),
+        CreatePoolError::Build(e) => DbError::ConfigError(e.to_string()),
+      })?;
 
-    log::info("Database connection established successfully!", true);
+    let db_pool = DbPool { pool };
+    let _ = db_pool.get().await?;
+
+    log::info(&format!(
+      "Database pool established successfully (max size {})",
+      settings.max_size
+    ));
+
+    Ok(db_pool)
+  }
+
+  /// Checks out a connection from the pool; it is returned when dropped
+  pub async fn get(&self) -> Result<Object, DbError> {
+    self.pool.get().await.map_err(DbError::from)
+  }
+
+  /// Current size and usage of the pool
+  pub fn status(&self) -> Status {
+    self.pool.status()
+  }
 
-    Ok(DbPool {
-      client: Arc::new(Mutex::new(client)),
-    })
+  /// Closes idle connections and makes every later checkout fail
+  pub fn close(&self) {
+    self.pool.close();
   }
 
-  pub fn get_client(&self) -> Arc<Mutex<Client>> {
-    self.client.clone()
+  /// Runs a trivial query to check that the database answers
+  pub async fn ping(&self) -> Result<(), DbError> {
+    let client = self.get().await?;
+    client.simple_query("SELECT 1").await?;
+    Ok(())
   }
 }
@@ -1,10 +1,15 @@
+use deadpool_postgres::PoolError;
 use std::fmt;
 use tokio_postgres::Error as PgError;
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum DbError {
   NotFound,
   DatabaseError(PgError),
+  PoolError(PoolError),
+  ConfigError(String),
+  MigrationError(String),
   HashingError(String),
 }
 
@@ -13,6 +18,9 @@ impl fmt::Display for DbError {
     match *self {
       DbError::NotFound => write!(f, "Not found"),
       DbError::DatabaseError(ref err) => write!(f, "Database error: {}", err),
+      DbError::PoolError(ref err) => write!(f, "Pool error: {}", err),
+      DbError::ConfigError(ref msg) => write!(f, "Configuration error: {}", msg),
+      DbError::MigrationError(ref msg) => write!(f, "Migration error: {}", msg),
       DbError::HashingError(ref msg) => write!(f, "Hashing error: {}", msg),
     }
   }
@@ -23,3 +31,9 @@ impl From<PgError> for DbError {
     DbError::DatabaseError(err)
   }
 }
+
+impl From<PoolError> for DbError {
+  fn from(err: PoolError) -> Self {
+    DbError::PoolError(err)
+  }
+}
@@ -1,9 +1,16 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::auth::role::Role;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
   pub sub: String,
   pub id: Uuid,
+  pub role: Role,
+  /// Whether the email address was verified when the token was issued
+  pub email_verified: bool,
+  /// Refresh token family (login session) the access token was issued for
+  pub sid: Uuid,
   pub exp: usize,
 }
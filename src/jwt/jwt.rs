@@ -1,35 +1,88 @@
-use chrono::{Duration, Utc};
-use dotenv::dotenv;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use sha2::Sha256;
 use uuid::Uuid;
-use std::env;
 
+use crate::auth::role::Role;
+use crate::config::AuthConfig;
 use crate::jwt::claims::Claims;
+use crate::tools::sha256_hex;
 
 pub struct JwtManager {
   secret: String,
+  access_token_ttl: Duration,
+  refresh_token_ttl: Duration,
+}
+
+/// A newly issued refresh token. Only the hash is stored; the token itself is sent to the client.
+pub struct RefreshToken {
+  pub token: String,
+  pub hash: String,
+  pub expires_at: DateTime<Utc>,
+}
+
+/// What a signed token may be used for. The purpose is part of the signature, so a token issued
+/// for one cannot be redeemed for another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenPurpose {
+  VerifyEmail,
+  ResetPassword,
+}
+
+impl TokenPurpose {
+  fn as_str(&self) -> &'static str {
+    match self {
+      TokenPurpose::VerifyEmail => "verify-email",
+      TokenPurpose::ResetPassword => "reset-password",
+    }
+  }
+}
+
+/// A token sent by email as `{user_id}.{expires}.{nonce}.{signature}`. The signature rejects
+/// forged and expired tokens without a database lookup; the hash is stored so each token can be
+/// redeemed only once.
+pub struct SignedToken {
+  pub token: String,
+  pub hash: String,
+  pub expires_at: DateTime<Utc>,
 }
 
 impl JwtManager {
-  pub fn new() -> Self {
-    dotenv().ok();
-    let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    Self { secret }
+  pub fn new(config: &AuthConfig) -> Self {
+    Self {
+      secret: config.jwt_secret.clone(),
+      access_token_ttl: Duration::minutes(config.access_token_ttl_minutes),
+      refresh_token_ttl: Duration::days(config.refresh_token_ttl_days),
+    }
   }
 
+  pub fn access_token_ttl(&self) -> Duration {
+    self.access_token_ttl
+  }
+
+  /// Issues a short-lived access token. `session_id` is the refresh token family it belongs to.
   pub fn generate_token(
     &self,
     username: &str,
     id: Uuid,
+    role: Role,
+    email_verified: bool,
+    session_id: Uuid,
   ) -> Result<String, jsonwebtoken::errors::Error> {
     let expiration = Utc::now()
-      .checked_add_signed(Duration::hours(24))
+      .checked_add_signed(self.access_token_ttl)
       .expect("valid timestamp")
       .timestamp() as usize;
 
     let claims = Claims {
       sub: username.to_owned(),
       id,
+      role,
+      email_verified,
+      sid: session_id,
       exp: expiration,
     };
 
@@ -40,6 +93,64 @@ impl JwtManager {
     )
   }
 
+  /// Creates a random opaque refresh token
+  pub fn generate_refresh_token(&self) -> RefreshToken {
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    RefreshToken {
+      hash: sha256_hex(&token),
+      token,
+      expires_at: Utc::now() + self.refresh_token_ttl,
+    }
+  }
+
+  /// Creates a signed token for `user_id` that expires after `ttl`
+  pub fn generate_signed_token(
+    &self,
+    purpose: TokenPurpose,
+    user_id: Uuid,
+    ttl: Duration,
+  ) -> SignedToken {
+    let expires_at = Utc::now() + ttl;
+    let payload = format!(
+      "{}.{}.{}",
+      user_id.simple(),
+      expires_at.timestamp(),
+      Uuid::new_v4().simple()
+    );
+    let signature =
+      URL_SAFE_NO_PAD.encode(self.token_mac(purpose, &payload).finalize().into_bytes());
+    let token = format!("{}.{}", payload, signature);
+    SignedToken {
+      hash: sha256_hex(&token),
+      token,
+      expires_at,
+    }
+  }
+
+  /// The user a signed token was issued to, if the signature is valid and it has not expired
+  pub fn verify_signed_token(&self, purpose: TokenPurpose, token: &str) -> Option<Uuid> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    self
+      .token_mac(purpose, payload)
+      .verify_slice(&signature)
+      .ok()?;
+
+    let mut parts = payload.split('.');
+    let user_id = Uuid::parse_str(parts.next()?).ok()?;
+    let expires_at: i64 = parts.next()?.parse().ok()?;
+    (expires_at > Utc::now().timestamp()).then_some(user_id)
+  }
+
+  fn token_mac(&self, purpose: TokenPurpose, payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+      .expect("HMAC accepts keys of any length");
+    mac.update(purpose.as_str().as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+    mac
+  }
+
   pub fn validate_token(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
     let token_data = decode::<Claims>(
       token,
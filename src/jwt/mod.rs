@@ -1,2 +1,3 @@
 pub mod claims;
+#[allow(clippy::module_inception)]
 pub mod jwt;
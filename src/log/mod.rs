@@ -0,0 +1,281 @@
+mod writer;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::log::writer::{LogWriter, Message};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+  Debug,
+  Info,
+  #[serde(alias = "warning")]
+  Warn,
+  Error,
+}
+
+impl Level {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Level::Debug => "DEBUG",
+      Level::Info => "INFO",
+      Level::Warn => "WARNING",
+      Level::Error => "ERROR",
+    }
+  }
+}
+
+impl FromStr for Level {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "debug" => Ok(Level::Debug),
+      "info" => Ok(Level::Info),
+      "warn" | "warning" => Ok(Level::Warn),
+      "error" => Ok(Level::Error),
+      other => Err(format!("Unknown log level: {}", other)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+  Text,
+  Json,
+}
+
+impl FromStr for Format {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "text" => Ok(Format::Text),
+      "json" => Ok(Format::Json),
+      other => Err(format!("Unknown log format: {}", other)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+  pub level: Level,
+  pub format: Format,
+  /// Directory for log files, `None` logs to stdout only
+  pub dir: Option<PathBuf>,
+  pub stdout: bool,
+  /// The active file is rotated once it grows past this many megabytes, and at midnight
+  pub max_file_size_mb: u64,
+  /// Rotated files older than this are deleted
+  pub max_age_days: u32,
+  /// At most this many rotated files are kept
+  pub max_files: usize,
+  /// Lines queued for the writer thread before new lines are dropped
+  pub buffer_size: usize,
+}
+
+impl Default for LogConfig {
+  fn default() -> Self {
+    LogConfig {
+      level: Level::Info,
+      format: Format::Text,
+      dir: Some(PathBuf::from("logs")),
+      stdout: true,
+      max_file_size_mb: 10,
+      max_age_days: 14,
+      max_files: 30,
+      buffer_size: 10_000,
+    }
+  }
+}
+
+tokio::task_local! {
+  static CONTEXT: RefCell<Vec<(&'static str, Value)>>;
+}
+
+/// Runs the future with fields that are attached to every line it logs, such as the request id
+pub async fn scope<F: Future>(fields: Vec<(&'static str, Value)>, fut: F) -> F::Output {
+  CONTEXT.scope(RefCell::new(fields), fut).await
+}
+
+/// Adds a field to the current `scope`, for values only known part way through a request
+pub fn add_context(key: &'static str, value: Value) {
+  let _ = CONTEXT.try_with(|context| {
+    let mut context = context.borrow_mut();
+    context.retain(|(k, _)| *k != key);
+    context.push((key, value));
+  });
+}
+
+struct Logger {
+  level: Level,
+  format: Format,
+  sender: SyncSender<Message>,
+  dropped: AtomicU64,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Starts the background writer. Until this is called, lines are printed to stdout.
+pub fn init(config: LogConfig) {
+  let (sender, receiver) = mpsc::sync_channel(config.buffer_size);
+  let logger = Logger {
+    level: config.level,
+    format: config.format,
+    sender,
+    dropped: AtomicU64::new(0),
+  };
+  if LOGGER.set(logger).is_err() {
+    warn("Logger is already initialized");
+    return;
+  }
+
+  let writer = LogWriter::new(config);
+  std::thread::Builder::new()
+    .name("log-writer".to_string())
+    .spawn(move || writer.run(receiver))
+    .expect("Failed to spawn log writer thread");
+}
+
+/// Blocks until every queued line has been written, or the timeout passes
+pub fn flush(timeout: Duration) {
+  let Some(logger) = LOGGER.get() else {
+    return;
+  };
+  let (done, wait) = mpsc::channel();
+  if logger.sender.send(Message::Flush(done)).is_ok() {
+    let _ = wait.recv_timeout(timeout);
+  }
+}
+
+/// Logs error messages
+pub fn error(message: &str) {
+  log(Level::Error, message, &[]);
+}
+
+/// Logs warnings
+pub fn warn(message: &str) {
+  log(Level::Warn, message, &[]);
+}
+
+/// Logs informational messages
+pub fn info(message: &str) {
+  log(Level::Info, message, &[]);
+}
+
+/// Logs debug messages, hidden unless the level is `debug`
+pub fn debug(message: &str) {
+  log(Level::Debug, message, &[]);
+}
+
+pub fn enabled(level: Level) -> bool {
+  match LOGGER.get() {
+    Some(logger) => level >= logger.level,
+    None => level >= Level::Info,
+  }
+}
+
+/// Logs a message with structured fields such as `request_id`, `user_id` or `latency_ms`
+pub fn log(level: Level, message: &str, fields: &[(&str, Value)]) {
+  if !enabled(level) {
+    return;
+  }
+
+  let format = LOGGER.get().map_or(Format::Text, |l| l.format);
+  let line = CONTEXT
+    .try_with(|context| {
+      let context = context.borrow();
+      if context.is_empty() {
+        return format_line(format, level, message, fields);
+      }
+      let merged: Vec<(&str, Value)> = context
+        .iter()
+        .filter(|(key, _)| !fields.iter().any(|(k, _)| k == key))
+        .cloned()
+        .chain(fields.iter().cloned())
+        .collect();
+      format_line(format, level, message, &merged)
+    })
+    .unwrap_or_else(|_| format_line(format, level, message, fields));
+
+  match LOGGER.get() {
+    Some(logger) => match logger.sender.try_send(Message::Line(line)) {
+      Ok(()) => {
+        let dropped = logger.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+          let notice = format!(
+            "{} log lines were dropped because the writer fell behind",
+            dropped
+          );
+          let _ = logger.sender.try_send(Message::Line(format_line(
+            format,
+            Level::Warn,
+            &notice,
+            &[],
+          )));
+        }
+      }
+      Err(TrySendError::Full(_)) => {
+        logger.dropped.fetch_add(1, Ordering::Relaxed);
+      }
+      Err(TrySendError::Disconnected(Message::Line(line))) => println!("{}", line),
+      Err(TrySendError::Disconnected(_)) => {}
+    },
+    None => println!("{}", line),
+  }
+}
+
+fn format_line(format: Format, level: Level, message: &str, fields: &[(&str, Value)]) -> String {
+  match format {
+    Format::Text => {
+      let date_time = chrono::Local::now().format("%d-%b-%Y %H:%M:%S");
+      let mut line = format!("[{}] [{}]: {}", date_time, level.as_str(), message);
+      for (key, value) in fields {
+        line.push_str(&format!(" {}={}", key, TextValue(value)));
+      }
+      line
+    }
+    Format::Json => {
+      let mut object = Map::new();
+      object.insert(
+        "timestamp".to_string(),
+        Value::String(chrono::Utc::now().to_rfc3339()),
+      );
+      object.insert(
+        "level".to_string(),
+        Value::String(level.as_str().to_string()),
+      );
+      object.insert("message".to_string(), Value::String(message.to_string()));
+      for (key, value) in fields {
+        object.insert(key.to_string(), value.clone());
+      }
+      Value::Object(object).to_string()
+    }
+  }
+}
+
+/// Renders strings without quotes unless they contain spaces
+struct TextValue<'a>(&'a Value);
+
+impl fmt::Display for TextValue<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.0 {
+      Value::String(s) if s.contains(' ') => write!(f, "{:?}", s),
+      Value::String(s) => write!(f, "{}", s),
+      other => write!(f, "{}", other),
+    }
+  }
+}
@@ -0,0 +1,333 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{Local, NaiveDate};
+
+use crate::log::LogConfig;
+
+const ACTIVE_FILE: &str = "api.log";
+const RETRY_OPEN_AFTER: Duration = Duration::from_secs(30);
+
+pub enum Message {
+  Line(String),
+  Flush(Sender<()>),
+}
+
+/// Owns the log file on the writer thread. IO errors are reported on stderr and never panic.
+pub struct LogWriter {
+  config: LogConfig,
+  file: Option<BufWriter<File>>,
+  size: u64,
+  opened_on: NaiveDate,
+  retry_at: Option<Instant>,
+}
+
+impl LogWriter {
+  pub fn new(config: LogConfig) -> Self {
+    LogWriter {
+      config,
+      file: None,
+      size: 0,
+      opened_on: Local::now().date_naive(),
+      retry_at: None,
+    }
+  }
+
+  pub fn run(mut self, receiver: Receiver<Message>) {
+    while let Ok(message) = receiver.recv() {
+      self.handle(message);
+      while let Ok(message) = receiver.try_recv() {
+        self.handle(message);
+      }
+      self.flush();
+    }
+    self.flush();
+  }
+
+  fn handle(&mut self, message: Message) {
+    match message {
+      Message::Line(line) => self.write_line(&line),
+      Message::Flush(done) => {
+        self.flush();
+        let _ = done.send(());
+      }
+    }
+  }
+
+  fn write_line(&mut self, line: &str) {
+    if self.config.stdout {
+      println!("{}", line);
+    }
+
+    let Some(dir) = self.config.dir.clone() else {
+      return;
+    };
+
+    let len = line.len() as u64 + 1;
+    let today = Local::now().date_naive();
+    if self.file.is_some() && (today != self.opened_on || self.size + len > self.max_file_size()) {
+      let _ = self.rotate(&dir);
+    }
+
+    if self.file.is_none() && !self.open(&dir) {
+      return;
+    }
+
+    if let Some(file) = self.file.as_mut() {
+      match writeln!(file, "{}", line) {
+        Ok(()) => self.size += len,
+        Err(e) => {
+          eprintln!("Failed to write to log file: {}", e);
+          self.file = None;
+          self.retry_at = Some(Instant::now() + RETRY_OPEN_AFTER);
+        }
+      }
+    }
+  }
+
+  fn open(&mut self, dir: &Path) -> bool {
+    if self.retry_at.is_some_and(|at| Instant::now() < at) {
+      return false;
+    }
+
+    if let Err(e) = self.open_active(dir) {
+      eprintln!(
+        "Failed to open log file {}: {}",
+        dir.join(ACTIVE_FILE).display(),
+        e
+      );
+      self.retry_at = Some(Instant::now() + RETRY_OPEN_AFTER);
+      return false;
+    }
+
+    // A file left over from a previous day or run may already need rotating
+    if (self.opened_on != Local::now().date_naive() || self.size >= self.max_file_size())
+      && self.rotate(dir).is_ok()
+      && let Err(e) = self.open_active(dir)
+    {
+      eprintln!("Failed to open log file after rotating it: {}", e);
+      self.retry_at = Some(Instant::now() + RETRY_OPEN_AFTER);
+      return false;
+    }
+    self.file.is_some()
+  }
+
+  /// Opens the active file for appending and picks up its size and the day it was written on
+  fn open_active(&mut self, dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let file = OpenOptions::new()
+      .append(true)
+      .create(true)
+      .open(dir.join(ACTIVE_FILE))?;
+
+    let metadata = file.metadata().ok();
+    self.size = metadata.as_ref().map_or(0, |m| m.len());
+    self.opened_on = metadata
+      .and_then(|m| m.modified().ok())
+      .map(|t| chrono::DateTime::<Local>::from(t).date_naive())
+      .unwrap_or_else(|| Local::now().date_naive());
+    self.file = Some(BufWriter::new(file));
+    self.retry_at = None;
+    Ok(())
+  }
+
+  /// Moves the active file aside. When that fails the error is reported once and the active file
+  /// is reopened and treated as today's empty file, so writing goes on in the same file and
+  /// rotation is only tried again at the next day or size boundary.
+  fn rotate(&mut self, dir: &Path) -> io::Result<()> {
+    self.flush();
+    self.file = None;
+
+    let rotated = dir.join(format!(
+      "api-{}-{}.log",
+      self.opened_on.format("%Y%m%d"),
+      Local::now().format("%H%M%S%3f")
+    ));
+    if let Err(e) = fs::rename(dir.join(ACTIVE_FILE), &rotated) {
+      eprintln!("Failed to rotate log file, appending to it instead: {}", e);
+      if self.open_active(dir).is_err() {
+        self.retry_at = Some(Instant::now() + RETRY_OPEN_AFTER);
+      }
+      self.opened_on = Local::now().date_naive();
+      self.size = 0;
+      return Err(e);
+    }
+    self.size = 0;
+    self.cleanup(dir);
+    Ok(())
+  }
+
+  /// Deletes rotated files past the retention age or count
+  fn cleanup(&self, dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+      return;
+    };
+
+    let mut rotated: Vec<(PathBuf, SystemTime)> = entries
+      .filter_map(|e| e.ok())
+      .filter(|e| {
+        let name = e.file_name().to_string_lossy().to_string();
+        name.starts_with("api-") && name.ends_with(".log")
+      })
+      .filter_map(|e| Some((e.path(), e.metadata().ok()?.modified().ok()?)))
+      .collect();
+    rotated.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+
+    let max_age = Duration::from_secs(u64::from(self.config.max_age_days) * 24 * 60 * 60);
+    for (index, (path, modified)) in rotated.iter().enumerate() {
+      let too_old = modified.elapsed().is_ok_and(|age| age > max_age);
+      if (index >= self.config.max_files || too_old)
+        && let Err(e) = fs::remove_file(path)
+      {
+        eprintln!("Failed to delete old log file {}: {}", path.display(), e);
+      }
+    }
+  }
+
+  fn max_file_size(&self) -> u64 {
+    self.config.max_file_size_mb.saturating_mul(1024 * 1024)
+  }
+
+  fn flush(&mut self) {
+    if let Some(file) = self.file.as_mut()
+      && let Err(e) = file.flush()
+    {
+      eprintln!("Failed to flush log file: {}", e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs::FileTimes;
+
+  use chrono::Days;
+  use uuid::Uuid;
+
+  use super::*;
+
+  fn writer(dir: &Path) -> LogWriter {
+    LogWriter::new(LogConfig {
+      dir: Some(dir.to_path_buf()),
+      stdout: false,
+      max_file_size_mb: 1,
+      ..LogConfig::default()
+    })
+  }
+
+  fn temp_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("log-test-{}", Uuid::new_v4()))
+  }
+
+  /// Names of the rotated files in the directory, sorted
+  fn rotated(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+      .unwrap()
+      .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+      .filter(|name| name != ACTIVE_FILE)
+      .collect();
+    names.sort();
+    names
+  }
+
+  fn active(dir: &Path) -> String {
+    fs::read_to_string(dir.join(ACTIVE_FILE)).unwrap()
+  }
+
+  #[test]
+  fn rotates_when_the_size_limit_is_hit() {
+    let dir = temp_dir();
+    let mut writer = writer(&dir);
+    let line = "x".repeat(400 * 1024);
+    writer.write_line(&line);
+    writer.write_line(&line);
+    assert!(rotated(&dir).is_empty());
+
+    // A third line would take the file past 1 MB, so it starts a new one
+    writer.write_line(&line);
+    writer.flush();
+    let rotated = rotated(&dir);
+    assert_eq!(rotated.len(), 1);
+    assert_eq!(
+      fs::metadata(dir.join(&rotated[0])).unwrap().len(),
+      2 * (400 * 1024 + 1)
+    );
+    assert_eq!(active(&dir).len(), 400 * 1024 + 1);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn rotates_at_the_day_boundary() {
+    let dir = temp_dir();
+    let mut writer = writer(&dir);
+    writer.write_line("yesterday");
+    let yesterday = Local::now().date_naive() - Days::new(1);
+    writer.opened_on = yesterday;
+
+    writer.write_line("today");
+    writer.flush();
+    let rotated = rotated(&dir);
+    assert_eq!(rotated.len(), 1);
+    assert!(rotated[0].starts_with(&format!("api-{}-", yesterday.format("%Y%m%d"))));
+    assert_eq!(
+      fs::read_to_string(dir.join(&rotated[0])).unwrap(),
+      "yesterday\n"
+    );
+    assert_eq!(active(&dir), "today\n");
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn cleanup_deletes_files_past_retention() {
+    let dir = temp_dir();
+    fs::create_dir_all(&dir).unwrap();
+    let now = SystemTime::now();
+    let hour = Duration::from_secs(60 * 60);
+    let day = 24 * hour;
+    for (name, age) in [
+      ("api-1.log", hour),
+      ("api-2.log", 2 * hour),
+      ("api-3.log", 3 * hour),
+      ("api-old.log", 15 * day),
+      ("other.log", 15 * day),
+    ] {
+      let file = File::create(dir.join(name)).unwrap();
+      file
+        .set_times(FileTimes::new().set_modified(now - age))
+        .unwrap();
+    }
+
+    let writer = LogWriter::new(LogConfig {
+      dir: Some(dir.clone()),
+      max_age_days: 14,
+      max_files: 2,
+      ..LogConfig::default()
+    });
+    writer.cleanup(&dir);
+    // Only the two newest rotated files are kept; files that are not rotated logs are left alone
+    assert_eq!(rotated(&dir), ["api-1.log", "api-2.log", "other.log"]);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn keeps_appending_when_rotation_fails() {
+    let dir = temp_dir();
+    let mut writer = writer(&dir);
+    writer.write_line("first");
+    writer.flush();
+    // The rename fails once the active file is gone
+    fs::remove_file(dir.join(ACTIVE_FILE)).unwrap();
+    writer.opened_on = Local::now().date_naive() - Days::new(1);
+
+    writer.write_line("second");
+    writer.write_line("third");
+    writer.flush();
+    assert!(rotated(&dir).is_empty());
+    assert_eq!(active(&dir), "second\nthird\n");
+    assert!(writer.retry_at.is_none());
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
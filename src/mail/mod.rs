@@ -0,0 +1,83 @@
+use std::fmt;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use lettre::Message;
+use lettre::message::Mailbox;
+use lettre::message::header::ContentType;
+
+use crate::config::{Config, MailBackend};
+
+pub mod outbox;
+pub mod smtp;
+
+pub use outbox::{FileOutbox, MemoryOutbox};
+pub use smtp::SmtpMailer;
+
+/// A plain text email
+#[derive(Clone, Debug)]
+pub struct Email {
+  pub to: String,
+  pub subject: String,
+  pub body: String,
+}
+
+#[derive(Debug)]
+pub enum MailError {
+  InvalidAddress(String),
+  /// The message could not be handed to the server or written to the outbox
+  Transport(String),
+}
+
+impl fmt::Display for MailError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      MailError::InvalidAddress(ref address) => write!(f, "Invalid email address: {:?}", address),
+      MailError::Transport(ref msg) => write!(f, "Failed to send email: {}", msg),
+    }
+  }
+}
+
+/// Delivers emails
+#[async_trait]
+pub trait Mailer: Send + Sync {
+  async fn send(&self, email: &Email) -> Result<(), MailError>;
+}
+
+/// Creates the backend selected by `mail.backend`
+pub fn from_config(config: &Config) -> Arc<dyn Mailer> {
+  match config.mail.backend {
+    MailBackend::Smtp => Arc::new(SmtpMailer::new(&config.mail)),
+    MailBackend::File => Arc::new(FileOutbox::new(&config.mail)),
+    MailBackend::Memory => Arc::new(MemoryOutbox::default()),
+  }
+}
+
+/// Sends the email in the background so slow mail servers do not hold up the request, and
+/// logs failures
+pub fn deliver(mailer: Arc<dyn Mailer>, email: Email) {
+  actix_web::rt::spawn(async move {
+    match mailer.send(&email).await {
+      Ok(()) => crate::log::debug(&format!("Sent {:?} to {}", email.subject, email.to)),
+      Err(e) => crate::log::error(&format!("{} ({:?} to {})", e, email.subject, email.to)),
+    }
+  });
+}
+
+/// Builds the RFC 5322 message sent by the SMTP and file backends
+fn build_message(from: &str, email: &Email) -> Result<Message, MailError> {
+  let from: Mailbox = from
+    .parse()
+    .map_err(|_| MailError::InvalidAddress(from.to_string()))?;
+  let to: Mailbox = email
+    .to
+    .parse()
+    .map_err(|_| MailError::InvalidAddress(email.to.clone()))?;
+  Message::builder()
+    .from(from)
+    .to(to)
+    .subject(&email.subject)
+    .header(ContentType::TEXT_PLAIN)
+    .body(email.body.clone())
+    .map_err(|e| MailError::Transport(e.to_string()))
+}
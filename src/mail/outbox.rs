@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use super::{Email, MailError, Mailer, build_message};
+use crate::config::MailConfig;
+use crate::log;
+
+/// Writes every email to `{outbox_dir}/{uuid}.eml` instead of sending it, for development
+pub struct FileOutbox {
+  from: String,
+  dir: PathBuf,
+}
+
+impl FileOutbox {
+  pub fn new(config: &MailConfig) -> Self {
+    FileOutbox {
+      from: config.from.clone(),
+      dir: config.outbox_dir.clone(),
+    }
+  }
+}
+
+#[async_trait]
+impl Mailer for FileOutbox {
+  async fn send(&self, email: &Email) -> Result<(), MailError> {
+    let message = build_message(&self.from, email)?;
+    tokio::fs::create_dir_all(&self.dir)
+      .await
+      .map_err(|e| MailError::Transport(e.to_string()))?;
+    let id = AsyncFileTransport::<Tokio1Executor>::new(&self.dir)
+      .send(message)
+      .await
+      .map_err(|e| MailError::Transport(e.to_string()))?;
+    log::info(&format!(
+      "Wrote email to {} to {}",
+      email.to,
+      self.dir.join(format!("{}.eml", id)).display()
+    ));
+    Ok(())
+  }
+}
+
+/// Keeps every email in memory and logs it
+#[derive(Default)]
+pub struct MemoryOutbox {
+  messages: Mutex<Vec<Email>>,
+}
+
+impl MemoryOutbox {
+  /// Emails sent so far, oldest first
+  #[cfg(test)]
+  pub fn messages(&self) -> Vec<Email> {
+    self.messages.lock().unwrap().clone()
+  }
+}
+
+#[async_trait]
+impl Mailer for MemoryOutbox {
+  async fn send(&self, email: &Email) -> Result<(), MailError> {
+    log::info(&format!(
+      "Email to {}: {}\n{}",
+      email.to, email.subject, email.body
+    ));
+    self.messages.lock().unwrap().push(email.clone());
+    Ok(())
+  }
+}
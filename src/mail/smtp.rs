@@ -0,0 +1,51 @@
+use async_trait::async_trait;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+
+use super::{Email, MailError, Mailer, build_message};
+use crate::config::{MailConfig, SmtpTls};
+
+/// Sends emails through an SMTP relay, reusing pooled connections
+pub struct SmtpMailer {
+  from: String,
+  transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+  pub fn new(config: &MailConfig) -> Self {
+    let smtp = &config.smtp;
+    let mut builder = match smtp.tls {
+      SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host),
+      SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host),
+      SmtpTls::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+        &smtp.host,
+      )),
+    }
+    .expect("TLS parameters for mail.smtp.host")
+    .port(smtp.port);
+    if !smtp.username.is_empty() {
+      builder = builder.credentials(Credentials::new(
+        smtp.username.clone(),
+        smtp.password.clone(),
+      ));
+    }
+
+    SmtpMailer {
+      from: config.from.clone(),
+      transport: builder.build(),
+    }
+  }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+  async fn send(&self, email: &Email) -> Result<(), MailError> {
+    let message = build_message(&self.from, email)?;
+    self
+      .transport
+      .send(message)
+      .await
+      .map_err(|e| MailError::Transport(e.to_string()))?;
+    Ok(())
+  }
+}
@@ -3,77 +3,231 @@ use db::DbPool;
 use dotenv::dotenv;
 use std::{env, sync::Arc};
 
-use crate::{jwt::jwt::JwtManager, repositories::apps_repo::AppsRepo, repository::Repositories};
+use crate::{
+  api::health::Readiness,
+  auth::auth_user::SessionRevocations,
+  config::{Cli, Config},
+  jwt::jwt::JwtManager,
+  migrations::Migrator,
+  repository::Repositories,
+};
 
 mod api;
+mod apierror;
+mod auth;
+mod config;
 mod db;
 mod dberror;
 mod jwt;
 mod log;
+mod mail;
+mod media;
+mod metrics;
+mod middleware;
+mod migrations;
+mod pagination;
+mod rate_limit;
 mod repositories;
 mod repository;
 mod requests;
+mod storage;
 mod tables;
+#[cfg(test)]
+mod testing;
 mod tools;
+mod upload;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
   dotenv().ok();
 
-  let database_username = env::var("DATABASE_USERNAME").expect("DATABASE_USERNAME not set in .env");
-  let database_password = env::var("DATABASE_PASSWORD").expect("DATABASE_PASSWORD not set in .env");
-  let database_host = env::var("DATABASE_HOST").expect("DATABASE_HOST not set in .env");
-  let database_port = env::var("DATABASE_PORT").expect("DATABASE_PORT not set in .env");
-  let database_name = env::var("DATABASE_NAME").expect("DATABASE_NAME not set in .env");
-
-  let database_url = format!(
-    "postgres://{}:{}@{}:{}/{}",
-    database_username, database_password, database_host, database_port, database_name
-  );
+  let cli = match Cli::parse(env::args().skip(1)) {
+    Ok(cli) => cli,
+    Err(e) => {
+      eprint!("{}\n{}", e, Cli::usage());
+      std::process::exit(2);
+    }
+  };
+  if cli.help {
+    print!("{}", Cli::usage());
+    return Ok(());
+  }
+  // Needs neither the database nor a configuration, so CI can export the document
+  if cli.command.first().map(String::as_str) == Some("openapi") {
+    std::process::exit(api::openapi::run_command(&cli.command[1..]));
+  }
+  let config = match Config::load(&cli) {
+    Ok(config) => config,
+    Err(e) => {
+      eprint!("{}", e);
+      std::process::exit(2);
+    }
+  };
+  log::init(config.log.clone());
 
-  let db_pool_data;
-  log::info(&format!("Connecting to database {}", database_name), true);
-  match DbPool::new(&database_url).await {
+  log::info(&format!("Connecting to database {}", config.database.name));
+  let db_pool = match DbPool::new(&config.database).await {
     Ok(pool) => {
-      log::info("Connected to database successfully!", true);
-      db_pool_data = web::Data::new(pool);
+      log::info("Connected to database successfully!");
+      pool
     }
     Err(e) => {
       log::error(&format!("Failed to connect to database: {}", e));
       std::process::exit(1);
     }
+  };
+
+  let migrator = Migrator::new(db_pool.clone());
+  let args = &cli.command;
+  if args.first().map(String::as_str) == Some("migrate") {
+    match migrations::run_command(&migrator, &args[1..]).await {
+      Ok(()) => std::process::exit(0),
+      Err(e) => {
+        log::error(&e.to_string());
+        std::process::exit(1);
+      }
+    }
   }
 
-  let repos: web::Data<Repositories> = web::Data::new(Repositories {
-    apps: Arc::new(AppsRepo::new(db_pool_data.get_client())),
-    user: Arc::new(repositories::user_repo::UserRepo::new(
-      db_pool_data.get_client(),
+  match migrator.up().await {
+    Ok(count) => log::info(&format!(
+      "Database schema is up to date ({} applied)",
+      count
     )),
-  });
+    Err(e) => {
+      log::error(&format!("Refusing to start: {}", e));
+      std::process::exit(1);
+    }
+  }
 
-  let _ = repos.create_tables().await;
+  let repos = web::Data::new(Repositories::new(&db_pool));
+  let sessions: Arc<dyn SessionRevocations> = repos.tokens.clone();
+  let sessions: web::Data<dyn SessionRevocations> = web::Data::from(sessions);
 
-  let jwt_manager = web::Data::new(JwtManager::new());
+  let jwt_manager = web::Data::new(JwtManager::new(&config.auth));
+  let storage: web::Data<dyn storage::Storage> = web::Data::from(storage::from_config(&config));
+  let mailer: web::Data<dyn mail::Mailer> = web::Data::from(mail::from_config(&config));
+  let rate_limits = rate_limit::from_config(&config, &db_pool);
+  rate_limit::purge_periodically(rate_limits.clone());
+  let rate_limits: web::Data<dyn rate_limit::RateLimitStore> = web::Data::from(rate_limits);
 
-  let server = match HttpServer::new(move || {
+  let address = (config.server.bind_address.clone(), config.server.port);
+  let workers = config.server.workers;
+  let shutdown_timeout = config.server.shutdown_timeout_secs;
+  let media_dir = config.media.dir.clone();
+  let db_pool_data = web::Data::new(db_pool.clone());
+  let config = web::Data::new(config);
+  let readiness = web::Data::new(Readiness::default());
+  let app_readiness = readiness.clone();
+
+  let mut server = HttpServer::new(move || {
     App::new()
+      .wrap(actix_web::middleware::from_fn(
+        middleware::metrics::track_requests,
+      ))
+      .wrap(actix_web::middleware::from_fn(
+        middleware::access_log::access_log,
+      ))
       .app_data(db_pool_data.clone())
       .app_data(repos.clone())
       .app_data(jwt_manager.clone())
+      .app_data(sessions.clone())
+      .app_data(storage.clone())
+      .app_data(mailer.clone())
+      .app_data(rate_limits.clone())
+      .app_data(config.clone())
+      .app_data(app_readiness.clone())
+      .app_data(web::JsonConfig::default().error_handler(|e, _| apierror::extractor_error(e)))
+      .app_data(web::QueryConfig::default().error_handler(|e, _| apierror::extractor_error(e)))
+      .app_data(web::PathConfig::default().error_handler(|e, _| apierror::extractor_error(e)))
+      .service(api::health::scope())
+      .service(api::metrics::get_metrics)
+      .service(api::media::get_media)
+      .service(api::openapi::get_openapi)
       .service(api::apps::scope())
       .service(api::user::scope())
+      .service(api::admin::scope())
+      .configure(docs_ui)
+      .default_service(web::to(|| async {
+        Err::<actix_web::HttpResponse, _>(apierror::ApiError::NotFound(
+          "Route not found".to_string(),
+        ))
+      }))
   })
-  .bind(("127.0.0.1", 8080))
-  {
+  .disable_signals()
+  .shutdown_timeout(shutdown_timeout);
+  if let Some(workers) = workers {
+    server = server.workers(workers);
+  }
+  let server = match server.bind(&address) {
     Ok(srv) => {
-      log::info("Server running at port 8080", true);
+      log::info(&format!("Server running at {}:{}", address.0, address.1));
       srv
     }
     Err(e) => {
-      log::error(&format!("Failed to bind to port 8080: {}", e));
+      log::error(&format!(
+        "Failed to bind to {}:{}: {}",
+        address.0, address.1, e
+      ));
       std::process::exit(1);
     }
   };
 
-  server.run().await
+  let server = server.run();
+  let handle = server.handle();
+  readiness.set_ready(true);
+
+  actix_web::rt::spawn(async move {
+    shutdown_signal().await;
+    log::info(&format!(
+      "Shutdown requested, draining requests for up to {}s",
+      shutdown_timeout
+    ));
+    readiness.set_ready(false);
+    handle.stop(true).await;
+  });
+
+  let result = server.await;
+  log::info("Server stopped");
+
+  let removed = upload::remove_partial_uploads(&media_dir);
+  if removed > 0 {
+    log::info(&format!("Removed {} partially written upload(s)", removed));
+  }
+  db_pool.close();
+  log::info("Database pool closed");
+  log::flush(std::time::Duration::from_secs(5));
+  result
 }
+
+/// Resolves on Ctrl+C, or SIGTERM on Unix
+async fn shutdown_signal() {
+  #[cfg(unix)]
+  {
+    use tokio::signal::unix::{SignalKind, signal};
+    match signal(SignalKind::terminate()) {
+      Ok(mut sigterm) => tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+      },
+      Err(e) => {
+        log::warn(&format!("Failed to listen for SIGTERM: {}", e));
+        let _ = tokio::signal::ctrl_c().await;
+      }
+    }
+  }
+  #[cfg(not(unix))]
+  let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Serves the bundled Swagger UI at `/api/docs/`, pointed at the generated document
+#[cfg(feature = "docs-ui")]
+fn docs_ui(cfg: &mut web::ServiceConfig) {
+  cfg.service(
+    utoipa_swagger_ui::SwaggerUi::new("/api/docs/{_:.*}")
+      .config(utoipa_swagger_ui::Config::from("/api/openapi.json")),
+  );
+}
+
+#[cfg(not(feature = "docs-ui"))]
+fn docs_ui(_: &mut web::ServiceConfig) {}
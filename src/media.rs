@@ -0,0 +1,273 @@
+use std::io::Cursor;
+
+use actix_web::web::Bytes;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::error::{ImageFormatHint, UnsupportedError, UnsupportedErrorKind};
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageError, ImageReader, ImageResult, Limits};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::config::MediaConfig;
+use crate::log;
+use crate::storage::{self, Storage};
+use crate::tables::app_image::AppImage;
+use crate::upload::{self, ImageFormat, UploadError};
+
+/// Largest width or height of an uploaded image that is decoded
+const MAX_SOURCE_DIMENSION: u32 = 10_000;
+
+/// Memory the decoder may use for a single image
+const MAX_DECODE_BYTES: u64 = 256 * 1024 * 1024;
+
+const JPEG_QUALITY: u8 = 85;
+
+/// Sizes every uploaded image is stored in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageVariant {
+  /// Square crop, 150×150
+  Thumbnail,
+  /// Fits in 600×400
+  Card,
+  /// Fits in 1920×1920
+  #[default]
+  Full,
+}
+
+impl ImageVariant {
+  pub const ALL: [ImageVariant; 3] = [
+    ImageVariant::Thumbnail,
+    ImageVariant::Card,
+    ImageVariant::Full,
+  ];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      ImageVariant::Thumbnail => "thumbnail",
+      ImageVariant::Card => "card",
+      ImageVariant::Full => "full",
+    }
+  }
+
+  pub fn from_name(value: &str) -> Option<ImageVariant> {
+    ImageVariant::ALL.into_iter().find(|v| v.as_str() == value)
+  }
+
+  /// Resizes the image for this variant. Images are never scaled up.
+  fn render(&self, image: &DynamicImage) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    match self {
+      ImageVariant::Thumbnail => {
+        let side = 150.min(width).min(height);
+        image.resize_to_fill(side, side, FilterType::Lanczos3)
+      }
+      ImageVariant::Card => fit(image, 600, 400),
+      ImageVariant::Full => fit(image, 1920, 1920),
+    }
+  }
+}
+
+fn fit(image: &DynamicImage, max_width: u32, max_height: u32) -> DynamicImage {
+  if image.width() <= max_width && image.height() <= max_height {
+    image.clone()
+  } else {
+    image.resize(max_width, max_height, FilterType::Lanczos3)
+  }
+}
+
+/// Decodes an upload staged by `upload::save_image` and puts every variant into storage as
+/// `images/{image_type}/{id}-{variant}.{ext}`. The EXIF orientation is applied to the pixels,
+/// then re-encoding drops EXIF and other metadata. The staged upload is removed afterwards,
+/// whether or not processing succeeded.
+pub async fn create_variants(
+  storage: &dyn Storage,
+  media: &MediaConfig,
+  image_type: &str,
+  upload_name: &str,
+) -> Result<Vec<AppImage>, UploadError> {
+  let directory = media.dir.join("images").join(image_type);
+  let source = tokio::fs::read(directory.join(upload_name)).await;
+  upload::delete_image(&media.dir, upload_name, image_type)
+    .await
+    .ok();
+  let source = source?;
+
+  let webp = media.convert_to_webp;
+  let encoded = tokio::task::spawn_blocking(move || encode_variants(&source, webp))
+    .await
+    .map_err(|e| UploadError::Io(std::io::Error::other(e)))?
+    .map_err(|e| match e {
+      ImageError::Unsupported(_) => UploadError::UnsupportedType,
+      // The file is already in memory, so read errors mean it is truncated or corrupt
+      e => UploadError::InvalidImage(e.to_string()),
+    })?;
+
+  let id = Uuid::new_v4();
+  let mut images: Vec<AppImage> = Vec::with_capacity(encoded.len());
+  for (variant, format, width, height, data) in encoded {
+    let file_name = format!("{}-{}.{}", id, variant.as_str(), format.extension());
+    let key = storage::image_key(image_type, &file_name);
+    if let Err(e) = storage
+      .put(&key, Bytes::from(data), format.mime_type())
+      .await
+    {
+      delete_variants(storage, &images, image_type).await;
+      return Err(UploadError::Storage(e));
+    }
+    images.push(AppImage {
+      variant,
+      file_name,
+      width: width as i32,
+      height: height as i32,
+      content_type: format.mime_type().to_string(),
+    });
+  }
+  Ok(images)
+}
+
+/// Removes stored variants, e.g. when saving the app failed
+pub async fn delete_variants(storage: &dyn Storage, images: &[AppImage], image_type: &str) {
+  for image in images {
+    let key = storage::image_key(image_type, &image.file_name);
+    if let Err(e) = storage.delete(&key).await {
+      log::warn(&format!("Failed to delete {}: {}", key, e));
+    }
+  }
+}
+
+/// A variant encoded in memory, with its width and height
+type EncodedVariant = (ImageVariant, ImageFormat, u32, u32, Vec<u8>);
+
+fn encode_variants(source: &[u8], webp: bool) -> ImageResult<Vec<EncodedVariant>> {
+  let mut reader = ImageReader::new(Cursor::new(source)).with_guessed_format()?;
+  let mut limits = Limits::default();
+  limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+  limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+  limits.max_alloc = Some(MAX_DECODE_BYTES);
+  reader.limits(limits.clone());
+
+  let source_format = reader
+    .format()
+    .and_then(|f| match f {
+      image::ImageFormat::Jpeg => Some(ImageFormat::Jpeg),
+      image::ImageFormat::WebP => Some(ImageFormat::WebP),
+      // GIFs lose their animation anyway, so they are stored as PNG
+      image::ImageFormat::Png | image::ImageFormat::Gif => Some(ImageFormat::Png),
+      _ => None,
+    })
+    .ok_or_else(|| {
+      ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+        ImageFormatHint::Unknown,
+        UnsupportedErrorKind::Format(ImageFormatHint::Unknown),
+      ))
+    })?;
+  let format = if webp {
+    ImageFormat::WebP
+  } else {
+    source_format
+  };
+  let mut decoder = reader.into_decoder()?;
+  // Check the size of the buffer before allocating it, as `ImageReader::decode` does
+  limits.reserve(decoder.total_bytes())?;
+  decoder.set_limits(limits)?;
+  // Cameras store photos as taken and record the rotation in EXIF, which re-encoding drops, so
+  // it is applied to the pixels instead. Unreadable EXIF leaves the image as it is.
+  let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+  let mut image = DynamicImage::from_decoder(decoder)?;
+  image.apply_orientation(orientation);
+
+  ImageVariant::ALL
+    .into_iter()
+    .map(|variant| {
+      let resized = variant.render(&image);
+      let data = encode(&resized, format)?;
+      Ok((variant, format, resized.width(), resized.height(), data))
+    })
+    .collect()
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> ImageResult<Vec<u8>> {
+  let mut data = Cursor::new(Vec::new());
+  match format {
+    ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+      .write_with_encoder(JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY))?,
+    ImageFormat::WebP => DynamicImage::ImageRgba8(image.to_rgba8())
+      .write_with_encoder(WebPEncoder::new_lossless(&mut data))?,
+    ImageFormat::Png | ImageFormat::Gif => image.write_with_encoder(PngEncoder::new(&mut data))?,
+  }
+  Ok(data.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{Rgb, RgbImage};
+
+  use super::*;
+
+  /// A JPEG `width` by `height` pixels, red on the left and blue on the right, with an EXIF
+  /// orientation tag
+  fn jpeg_with_orientation(width: u32, height: u32, orientation: u16) -> Vec<u8> {
+    let image = RgbImage::from_fn(width, height, |x, _| {
+      if x < width / 2 {
+        Rgb([255, 0, 0])
+      } else {
+        Rgb([0, 0, 255])
+      }
+    });
+    let jpeg = encode(&DynamicImage::ImageRgb8(image), ImageFormat::Jpeg).unwrap();
+
+    // Big-endian TIFF header and a single IFD entry: tag 0x0112, SHORT, count 1
+    let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+    exif.extend_from_slice(&orientation.to_be_bytes());
+    exif.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    let mut app1 = vec![0xff, 0xe1];
+    app1.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+    app1.extend_from_slice(&exif);
+
+    // Right after the start of image marker
+    let mut data = jpeg[..2].to_vec();
+    data.extend_from_slice(&app1);
+    data.extend_from_slice(&jpeg[2..]);
+    data
+  }
+
+  fn full(variants: &[EncodedVariant]) -> DynamicImage {
+    let (_, _, _, _, data) = variants
+      .iter()
+      .find(|(variant, ..)| *variant == ImageVariant::Full)
+      .unwrap();
+    image::load_from_memory(data).unwrap()
+  }
+
+  #[test]
+  fn applies_exif_rotation_before_resizing() {
+    // Orientation 6: the camera was turned clockwise, so the picture must be rotated 90°
+    let variants = encode_variants(&jpeg_with_orientation(80, 40, 6), false).unwrap();
+    for (variant, format, width, height, _) in &variants {
+      assert_eq!(*format, ImageFormat::Jpeg);
+      match variant {
+        ImageVariant::Thumbnail => assert_eq!((*width, *height), (40, 40)),
+        _ => assert_eq!((*width, *height), (40, 80), "{:?}", variant),
+      }
+    }
+
+    // The left half, which was red, is now on top
+    let image = full(&variants).to_rgb8();
+    assert!(image.get_pixel(20, 5)[0] > 200);
+    assert!(image.get_pixel(20, 75)[2] > 200);
+  }
+
+  #[test]
+  fn keeps_images_without_orientation() {
+    let variants = encode_variants(&jpeg_with_orientation(80, 40, 1), false).unwrap();
+    let image = full(&variants).to_rgb8();
+    assert_eq!(image.dimensions(), (80, 40));
+    assert!(image.get_pixel(5, 20)[0] > 200);
+    assert!(image.get_pixel(75, 20)[2] > 200);
+  }
+}
@@ -0,0 +1,375 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use deadpool_postgres::Status;
+
+use crate::db::DbPool;
+
+/// Upper bounds in seconds of the latency histogram buckets
+const LATENCY_BUCKETS: &[f64] = &[
+  0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+  buckets: Vec<u64>,
+  sum: f64,
+  count: u64,
+}
+
+impl Histogram {
+  fn observe(&mut self, value: f64) {
+    if self.buckets.is_empty() {
+      self.buckets = vec![0; LATENCY_BUCKETS.len()];
+    }
+    for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+      if value <= *bound {
+        *bucket += 1;
+      }
+    }
+    self.sum += value;
+    self.count += 1;
+  }
+
+  fn render(&self, out: &mut String, name: &str, labels: &str) {
+    let separator = if labels.is_empty() { "" } else { "," };
+    for (count, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+      let _ = writeln!(
+        out,
+        "{}_bucket{{{}{}le=\"{}\"}} {}",
+        name, labels, separator, bound, count
+      );
+    }
+    let _ = writeln!(
+      out,
+      "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+      name, labels, separator, self.count
+    );
+    let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+    let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+  }
+}
+
+#[derive(Default)]
+struct Metrics {
+  /// Requests by (method, route, status)
+  requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+  /// Request latency by (method, route)
+  request_duration: Mutex<BTreeMap<(String, String), Histogram>>,
+  /// Query latency by repository operation, e.g. `apps.get_app_by_id`
+  query_duration: Mutex<BTreeMap<&'static str, Histogram>>,
+  login_success: AtomicU64,
+  login_failure: AtomicU64,
+  uploaded_bytes: AtomicU64,
+  /// Requests rejected by a rate limit, by route group
+  rate_limited: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Records a finished request. `route` is the matched route pattern, never the raw path, so
+/// ids in the URL do not create a new series per request.
+pub fn record_request(method: &str, route: &str, status: u16, duration: Duration) {
+  let key = (method.to_string(), route.to_string());
+  if let Ok(mut requests) = METRICS.requests.lock() {
+    *requests
+      .entry((key.0.clone(), key.1.clone(), status))
+      .or_default() += 1;
+  }
+  if let Ok(mut histograms) = METRICS.request_duration.lock() {
+    histograms
+      .entry(key)
+      .or_default()
+      .observe(duration.as_secs_f64());
+  }
+}
+
+pub fn record_query(operation: &'static str, duration: Duration) {
+  if let Ok(mut histograms) = METRICS.query_duration.lock() {
+    histograms
+      .entry(operation)
+      .or_default()
+      .observe(duration.as_secs_f64());
+  }
+}
+
+pub fn record_login(success: bool) {
+  let counter = if success {
+    &METRICS.login_success
+  } else {
+    &METRICS.login_failure
+  };
+  counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_upload(bytes: u64) {
+  METRICS.uploaded_bytes.fetch_add(bytes, Ordering::Relaxed);
+}
+
+pub fn record_rate_limited(group: &'static str) {
+  if let Ok(mut rate_limited) = METRICS.rate_limited.lock() {
+    *rate_limited.entry(group).or_default() += 1;
+  }
+}
+
+/// Records how long a repository call took when it goes out of scope
+pub struct QueryTimer {
+  operation: &'static str,
+  start: Instant,
+}
+
+impl QueryTimer {
+  pub fn start(operation: &'static str) -> Self {
+    QueryTimer {
+      operation,
+      start: Instant::now(),
+    }
+  }
+}
+
+impl Drop for QueryTimer {
+  fn drop(&mut self) {
+    record_query(self.operation, self.start.elapsed());
+  }
+}
+
+/// Escapes a label value for the Prometheus text format
+fn label(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+  let _ = writeln!(out, "# HELP {} {}", name, help);
+  let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Renders every metric in the Prometheus text exposition format
+pub fn render(pool: &DbPool) -> String {
+  render_metrics(&METRICS, pool.status())
+}
+
+fn render_metrics(metrics: &Metrics, status: Status) -> String {
+  let mut out = String::new();
+
+  header(
+    &mut out,
+    "http_requests_total",
+    "counter",
+    "HTTP requests by method, route and status.",
+  );
+  let mut errors: BTreeMap<u16, u64> = BTreeMap::new();
+  if let Ok(requests) = metrics.requests.lock() {
+    for ((method, route, status), count) in requests.iter() {
+      let _ = writeln!(
+        out,
+        "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+        label(method),
+        label(route),
+        status,
+        count
+      );
+      if *status >= 400 {
+        *errors.entry(*status).or_default() += count;
+      }
+    }
+  }
+
+  header(
+    &mut out,
+    "http_errors_total",
+    "counter",
+    "HTTP responses with a 4xx or 5xx status.",
+  );
+  for (status, count) in &errors {
+    let _ = writeln!(out, "http_errors_total{{status=\"{}\"}} {}", status, count);
+  }
+
+  header(
+    &mut out,
+    "http_request_duration_seconds",
+    "histogram",
+    "HTTP request latency by method and route.",
+  );
+  if let Ok(histograms) = metrics.request_duration.lock() {
+    for ((method, route), histogram) in histograms.iter() {
+      let labels = format!("method=\"{}\",route=\"{}\"", label(method), label(route));
+      histogram.render(&mut out, "http_request_duration_seconds", &labels);
+    }
+  }
+
+  header(
+    &mut out,
+    "db_query_duration_seconds",
+    "histogram",
+    "Database latency by repository operation.",
+  );
+  if let Ok(histograms) = metrics.query_duration.lock() {
+    for (operation, histogram) in histograms.iter() {
+      let labels = format!("operation=\"{}\"", label(operation));
+      histogram.render(&mut out, "db_query_duration_seconds", &labels);
+    }
+  }
+
+  header(
+    &mut out,
+    "db_pool_connections",
+    "gauge",
+    "Database pool connections by state.",
+  );
+  let in_use = status.size.saturating_sub(status.available);
+  for (state, value) in [
+    ("max", status.max_size),
+    ("open", status.size),
+    ("idle", status.available),
+    ("in_use", in_use),
+  ] {
+    let _ = writeln!(out, "db_pool_connections{{state=\"{}\"}} {}", state, value);
+  }
+  header(
+    &mut out,
+    "db_pool_waiting",
+    "gauge",
+    "Requests waiting for a database connection.",
+  );
+  let _ = writeln!(out, "db_pool_waiting {}", status.waiting);
+
+  header(
+    &mut out,
+    "login_attempts_total",
+    "counter",
+    "Login attempts by outcome.",
+  );
+  let _ = writeln!(
+    out,
+    "login_attempts_total{{outcome=\"success\"}} {}",
+    metrics.login_success.load(Ordering::Relaxed)
+  );
+  let _ = writeln!(
+    out,
+    "login_attempts_total{{outcome=\"failure\"}} {}",
+    metrics.login_failure.load(Ordering::Relaxed)
+  );
+
+  header(
+    &mut out,
+    "uploaded_bytes_total",
+    "counter",
+    "Bytes of uploaded images stored.",
+  );
+  let _ = writeln!(
+    out,
+    "uploaded_bytes_total {}",
+    metrics.uploaded_bytes.load(Ordering::Relaxed)
+  );
+
+  header(
+    &mut out,
+    "rate_limited_requests_total",
+    "counter",
+    "Requests rejected by a rate limit, by route group.",
+  );
+  if let Ok(rate_limited) = metrics.rate_limited.lock() {
+    for (group, count) in rate_limited.iter() {
+      let _ = writeln!(
+        out,
+        "rate_limited_requests_total{{group=\"{}\"}} {}",
+        group, count
+      );
+    }
+  }
+
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn status() -> Status {
+    Status {
+      max_size: 16,
+      size: 3,
+      available: 1,
+      waiting: 0,
+    }
+  }
+
+  #[test]
+  fn histogram_buckets_are_cumulative() {
+    let mut histogram = Histogram::default();
+    histogram.observe(0.003);
+    histogram.observe(0.2);
+    histogram.observe(30.0);
+
+    let mut out = String::new();
+    histogram.render(&mut out, "latency", "route=\"/\"");
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), LATENCY_BUCKETS.len() + 3);
+    assert_eq!(lines[0], "latency_bucket{route=\"/\",le=\"0.001\"} 0");
+    assert!(lines.contains(&"latency_bucket{route=\"/\",le=\"0.005\"} 1"));
+    assert!(lines.contains(&"latency_bucket{route=\"/\",le=\"0.25\"} 2"));
+    assert!(lines.contains(&"latency_bucket{route=\"/\",le=\"10\"} 2"));
+    assert!(lines.contains(&"latency_bucket{route=\"/\",le=\"+Inf\"} 3"));
+    assert!(lines.contains(&"latency_sum{route=\"/\"} 30.203"));
+    assert!(lines.contains(&"latency_count{route=\"/\"} 3"));
+  }
+
+  #[test]
+  fn renders_the_text_exposition_format() {
+    let metrics = Metrics::default();
+    {
+      let mut requests = metrics.requests.lock().unwrap();
+      requests.insert(("GET".to_string(), "/api/apps/{id}".to_string(), 200), 5);
+      requests.insert(("GET".to_string(), "/api/apps/{id}".to_string(), 404), 2);
+      requests.insert(("POST".to_string(), "/a\"b".to_string(), 404), 1);
+    }
+    metrics
+      .request_duration
+      .lock()
+      .unwrap()
+      .entry(("GET".to_string(), "/api/apps/{id}".to_string()))
+      .or_default()
+      .observe(0.02);
+    metrics.login_failure.fetch_add(4, Ordering::Relaxed);
+    metrics.rate_limited.lock().unwrap().insert("login", 7);
+
+    let out = render_metrics(&metrics, status());
+    for line in [
+      "# HELP http_requests_total HTTP requests by method, route and status.",
+      "# TYPE http_requests_total counter",
+      "http_requests_total{method=\"GET\",route=\"/api/apps/{id}\",status=\"200\"} 5",
+      "http_requests_total{method=\"POST\",route=\"/a\\\"b\",status=\"404\"} 1",
+      "http_errors_total{status=\"404\"} 3",
+      "# TYPE http_request_duration_seconds histogram",
+      "http_request_duration_seconds_bucket{method=\"GET\",route=\"/api/apps/{id}\",le=\"0.025\"} 1",
+      "http_request_duration_seconds_count{method=\"GET\",route=\"/api/apps/{id}\"} 1",
+      "db_pool_connections{state=\"max\"} 16",
+      "db_pool_connections{state=\"in_use\"} 2",
+      "db_pool_waiting 0",
+      "login_attempts_total{outcome=\"success\"} 0",
+      "login_attempts_total{outcome=\"failure\"} 4",
+      "uploaded_bytes_total 0",
+      "rate_limited_requests_total{group=\"login\"} 7",
+    ] {
+      assert!(
+        out.lines().any(|l| l == line),
+        "missing {:?} in\n{}",
+        line,
+        out
+      );
+    }
+    assert!(!out.contains("http_errors_total{status=\"200\"}"));
+    assert!(out.ends_with('\n'));
+  }
+
+  #[test]
+  fn escapes_label_values() {
+    assert_eq!(label("a\\b\"c\nd"), "a\\\\b\\\"c\\nd");
+  }
+}
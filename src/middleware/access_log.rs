@@ -0,0 +1,206 @@
+use std::time::Instant;
+
+use actix_web::HttpMessage;
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::apierror::ApiError;
+use crate::jwt::claims::Claims;
+use crate::log::{self, Level};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Accepts a client supplied id only if it is short and printable
+fn incoming_request_id(req: &ServiceRequest) -> Option<String> {
+  let value = req.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+  let valid = !value.is_empty()
+    && value.len() <= 128
+    && value
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+  valid.then(|| value.to_string())
+}
+
+/// Assigns every request an id, logs one line per request and tags error bodies with the id
+pub async fn access_log(
+  req: ServiceRequest,
+  next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+  let start = Instant::now();
+  let id = incoming_request_id(&req).unwrap_or_else(|| Uuid::new_v4().to_string());
+
+  let method = req.method().to_string();
+  let path = req.path().to_string();
+  let peer = req
+    .connection_info()
+    .realip_remote_addr()
+    .unwrap_or("-")
+    .to_string();
+
+  let result = log::scope(vec![("request_id", json!(id))], next.call(req)).await;
+
+  let request = RequestLine {
+    id: &id,
+    method: &method,
+    path: &path,
+    peer: &peer,
+    start,
+  };
+
+  // Errors raised by middleware never reach a handler; they are rendered further up, from the
+  // response built here so they carry the request id like handler errors
+  let res = match result {
+    Ok(res) => res.map_into_boxed_body(),
+    Err(e) => {
+      let mut response = match e.as_error::<ApiError>() {
+        Some(api_error) => api_error.to_response(Some(&id)),
+        None => e.error_response(),
+      };
+      if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+      }
+      request.log(response.status(), None, json!(null));
+      return Err(InternalError::from_response(e, response).into());
+    }
+  };
+
+  let mut res = match res
+    .response()
+    .error()
+    .and_then(|e| e.as_error::<ApiError>())
+  {
+    Some(api_error) => {
+      let mut response = api_error.to_response(Some(&id));
+      // Keep headers that middleware added to the error response, such as rate limit headers
+      for (name, value) in res.headers() {
+        if !response.headers().contains_key(name) {
+          response.headers_mut().append(name.clone(), value.clone());
+        }
+      }
+      let (http_req, _) = res.into_parts();
+      ServiceResponse::new(http_req, response)
+    }
+    None => res,
+  };
+
+  if let Ok(value) = HeaderValue::from_str(&id) {
+    res.headers_mut().insert(REQUEST_ID_HEADER, value);
+  }
+
+  let user_id = res.request().extensions().get::<Claims>().map(|c| c.id);
+  let bytes = match res.response().body().size() {
+    BodySize::Sized(n) => json!(n),
+    _ => json!(null),
+  };
+  request.log(res.status(), user_id, bytes);
+
+  Ok(res)
+}
+
+struct RequestLine<'a> {
+  id: &'a str,
+  method: &'a str,
+  path: &'a str,
+  peer: &'a str,
+  start: Instant,
+}
+
+impl RequestLine<'_> {
+  fn log(&self, status: StatusCode, user_id: Option<Uuid>, bytes: serde_json::Value) {
+    let level = if status.is_server_error() {
+      Level::Error
+    } else {
+      Level::Info
+    };
+
+    log::log(
+      level,
+      &format!("{} {} {}", self.method, self.path, status.as_u16()),
+      &[
+        ("request_id", json!(self.id)),
+        ("method", json!(self.method)),
+        ("path", json!(self.path)),
+        ("status", json!(status.as_u16())),
+        (
+          "latency_ms",
+          json!(self.start.elapsed().as_secs_f64() * 1000.0),
+        ),
+        ("bytes", bytes),
+        ("user_id", json!(user_id)),
+        ("peer", json!(self.peer)),
+      ],
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use actix_web::body::to_bytes;
+  use actix_web::middleware::from_fn;
+  use actix_web::test::{
+    TestRequest, call_service, init_service, read_body_json, try_call_service,
+  };
+  use actix_web::{App, HttpResponse, web};
+
+  use super::*;
+
+  async fn reject(
+    _: ServiceRequest,
+    _: Next<impl MessageBody>,
+  ) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    Err(ApiError::Forbidden("Rejected by middleware".to_string()).into())
+  }
+
+  #[actix_web::test]
+  async fn tags_handler_errors_with_the_request_id() {
+    let app = init_service(App::new().wrap(from_fn(access_log)).route(
+      "/",
+      web::get().to(|| async { Err::<HttpResponse, _>(ApiError::NotFound("Gone".to_string())) }),
+    ))
+    .await;
+    let req = TestRequest::get()
+      .uri("/")
+      .insert_header((REQUEST_ID_HEADER, "client-id.1"))
+      .to_request();
+    let res = call_service(&app, req).await;
+    assert_eq!(res.status(), 404);
+    assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "client-id.1");
+    let body: serde_json::Value = read_body_json(res).await;
+    assert_eq!(body["request_id"], "client-id.1");
+  }
+
+  #[actix_web::test]
+  async fn tags_middleware_errors_with_the_request_id() {
+    let app = init_service(
+      App::new()
+        .wrap(from_fn(reject))
+        .wrap(from_fn(access_log))
+        .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+    // The server renders the error from the response attached to it
+    let error = try_call_service(&app, TestRequest::get().uri("/").to_request())
+      .await
+      .expect_err("the middleware error is passed on");
+    let res = error.error_response();
+    assert_eq!(res.status(), 403);
+    let id = res
+      .headers()
+      .get(REQUEST_ID_HEADER)
+      .unwrap()
+      .to_str()
+      .unwrap()
+      .to_string();
+    assert!(Uuid::parse_str(&id).is_ok());
+    let body = to_bytes(res.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["request_id"], id.as_str());
+    assert_eq!(body["message"], "Rejected by middleware");
+  }
+}
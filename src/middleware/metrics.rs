@@ -0,0 +1,39 @@
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+
+use crate::metrics;
+
+/// Route label for requests that did not match any route
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Counts requests and records their latency by method and matched route pattern
+pub async fn track_requests(
+  req: ServiceRequest,
+  next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+  let start = Instant::now();
+  let method = req.method().to_string();
+
+  let result = next.call(req).await;
+  match result {
+    Ok(ref res) => {
+      let route = res.request().match_pattern();
+      metrics::record_request(
+        &method,
+        route.as_deref().unwrap_or(UNMATCHED_ROUTE),
+        res.status().as_u16(),
+        start.elapsed(),
+      );
+    }
+    Err(ref e) => metrics::record_request(
+      &method,
+      UNMATCHED_ROUTE,
+      e.as_response_error().status_code().as_u16(),
+      start.elapsed(),
+    ),
+  }
+  result
+}
@@ -0,0 +1,152 @@
+use std::future::{Ready, ready};
+use std::rc::Rc;
+
+use actix_web::HttpRequest;
+use actix_web::body::EitherBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::web::Data;
+use futures_util::future::LocalBoxFuture;
+
+use crate::apierror::ApiError;
+use crate::auth::auth_user::authenticate;
+use crate::config::{Config, RateLimitKey};
+use crate::log;
+use crate::metrics;
+use crate::rate_limit::{Decision, RateLimitGroup, RateLimitStore};
+use crate::tools::client_ip;
+
+/// Limits how often the routes of a group can be called, as configured under `rate_limit`.
+/// Declared with `#[post("..", wrap = "RateLimit(RateLimitGroup::Login)")]`. Responses carry
+/// `RateLimit-*` headers, and requests over the limit get 429 with `Retry-After`. When the store
+/// fails, requests are let through.
+pub struct RateLimit(pub RateLimitGroup);
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+  B: 'static,
+{
+  type Response = ServiceResponse<EitherBody<B>>;
+  type Error = actix_web::Error;
+  type Transform = RateLimitMiddleware<S>;
+  type InitError = ();
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ready(Ok(RateLimitMiddleware {
+      service: Rc::new(service),
+      group: self.0,
+    }))
+  }
+}
+
+pub struct RateLimitMiddleware<S> {
+  service: Rc<S>,
+  group: RateLimitGroup,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+  B: 'static,
+{
+  type Response = ServiceResponse<EitherBody<B>>;
+  type Error = actix_web::Error;
+  type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+  forward_ready!(service);
+
+  fn call(&self, req: ServiceRequest) -> Self::Future {
+    let service = self.service.clone();
+    let group = self.group;
+
+    Box::pin(async move {
+      let config = req.app_data::<Data<Config>>().cloned();
+      let store = req.app_data::<Data<dyn RateLimitStore>>().cloned();
+      let (Some(config), Some(store)) = (config, store) else {
+        let error = ApiError::Internal("RateLimitStore is not registered as app data".to_string());
+        return Ok(req.error_response(error).map_into_right_body());
+      };
+      if !config.rate_limit.enabled {
+        return service
+          .call(req)
+          .await
+          .map(ServiceResponse::map_into_left_body);
+      }
+
+      let rule = group.rule(&config.rate_limit);
+      let key = format!(
+        "{}:{}",
+        group.as_str(),
+        client_key(
+          req.request(),
+          rule.key,
+          config.server.trust_forwarded_headers
+        )
+        .await
+      );
+      let decision = match store.acquire(&key, rule).await {
+        Ok(decision) => decision,
+        Err(e) => {
+          log::error(&format!("{} (letting {} through)", e, key));
+          return service
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+        }
+      };
+
+      if !decision.allowed {
+        log::debug(&format!(
+          "Rate limited {} for {}s",
+          key, decision.retry_after_secs
+        ));
+        metrics::record_rate_limited(group.as_str());
+        let error = ApiError::TooManyRequests(
+          "Too many requests, try again later".to_string(),
+          decision.retry_after_secs,
+        );
+        let mut response = req.error_response(error);
+        set_headers(response.headers_mut(), &decision, rule.window_secs);
+        return Ok(response.map_into_right_body());
+      }
+
+      let mut response = service.call(req).await?;
+      set_headers(response.headers_mut(), &decision, rule.window_secs);
+      Ok(response.map_into_left_body())
+    })
+  }
+}
+
+/// What the request is counted by. Falls back to the IP address when the request has no valid
+/// token.
+async fn client_key(req: &HttpRequest, key: RateLimitKey, trust_forwarded: bool) -> String {
+  match key {
+    RateLimitKey::User => {
+      if let Ok(Some(claims)) = authenticate(req).await {
+        return format!("user:{}", claims.id);
+      }
+    }
+    RateLimitKey::Ip => {}
+  }
+  format!("ip:{}", client_ip(req, trust_forwarded))
+}
+
+/// Adds the `RateLimit-Limit`, `RateLimit-Remaining`, `RateLimit-Reset` and `RateLimit-Policy`
+/// headers of the IETF rate limit headers draft
+fn set_headers(headers: &mut HeaderMap, decision: &Decision, window_secs: u64) {
+  for (name, value) in [
+    ("ratelimit-limit", decision.limit.to_string()),
+    ("ratelimit-remaining", decision.remaining.to_string()),
+    ("ratelimit-reset", decision.reset_secs.to_string()),
+    (
+      "ratelimit-policy",
+      format!("{};w={}", decision.limit, window_secs),
+    ),
+  ] {
+    if let Ok(value) = HeaderValue::from_str(&value) {
+      headers.insert(HeaderName::from_static(name), value);
+    }
+  }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use deadpool_postgres::Object;
+
+use crate::db::DbPool;
+use crate::dberror::DbError;
+use crate::log;
+use crate::tools::sha256_hex;
+
+/// Key for the advisory lock that keeps concurrent instances from migrating at the same time
+const MIGRATION_LOCK_KEY: i64 = 0x006d_6967_7261_7465;
+
+pub struct Migration {
+  pub version: i64,
+  pub name: &'static str,
+  pub up: &'static str,
+  pub down: &'static str,
+}
+
+macro_rules! migration {
+  ($version:expr, $name:literal) => {
+    Migration {
+      version: $version,
+      name: $name,
+      up: include_str!(concat!("../database/migrations/", $name, ".up.sql")),
+      down: include_str!(concat!("../database/migrations/", $name, ".down.sql")),
+    }
+  };
+}
+
+/// Every migration in the order it is applied. The SQL files under `database/migrations`
+/// are the only definition of the schema.
+pub static MIGRATIONS: &[Migration] = &[
+  migration!(1, "0001_create_users"),
+  migration!(2, "0002_create_apps"),
+  migration!(3, "0003_apps_updated_at"),
+  migration!(4, "0004_create_refresh_tokens"),
+  migration!(5, "0005_add_user_roles"),
+  migration!(6, "0006_apps_listing_indexes"),
+  migration!(7, "0007_apps_search"),
+  migration!(8, "0008_app_image_variants"),
+  migration!(9, "0009_email_verification"),
+  migration!(10, "0010_password_reset_tokens"),
+  migration!(11, "0011_login_failures"),
+  migration!(12, "0012_rate_limits"),
+  migration!(13, "0013_login_events"),
+];
+
+impl Migration {
+  /// Covers both scripts, so editing either one after it was applied is detected
+  pub fn checksum(&self) -> String {
+    sha256_hex(&format!("{}\0{}", self.up, self.down))
+  }
+}
+
+/// A migration recorded in the `schema_migrations` table
+pub struct AppliedMigration {
+  pub version: i64,
+  pub name: String,
+  pub checksum: String,
+  pub applied_at: chrono::DateTime<chrono::Utc>,
+}
+
+pub struct Migrator {
+  pool: DbPool,
+  migrations: &'static [Migration],
+}
+
+impl Migrator {
+  pub fn new(pool: DbPool) -> Self {
+    Self {
+      pool,
+      migrations: MIGRATIONS,
+    }
+  }
+
+  // Everything below runs on the caller's connection, the one holding the migration lock while
+  // migrating, so a pool of one connection is enough
+  async fn ensure_table(&self, client: &Object) -> Result<(), DbError> {
+    client
+      .batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+          version BIGINT PRIMARY KEY,
+          name VARCHAR(255) NOT NULL,
+          checksum CHAR(64) NOT NULL,
+          applied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+      )
+      .await?;
+    Ok(())
+  }
+
+  pub async fn applied(&self, client: &Object) -> Result<Vec<AppliedMigration>, DbError> {
+    self.ensure_table(client).await?;
+    let rows = client
+      .query(
+        "SELECT version, name, checksum, applied_at FROM schema_migrations ORDER BY version",
+        &[],
+      )
+      .await?;
+
+    Ok(
+      rows
+        .iter()
+        .map(|row| AppliedMigration {
+          version: row.get("version"),
+          name: row.get("name"),
+          checksum: row.get("checksum"),
+          applied_at: row.get("applied_at"),
+        })
+        .collect(),
+    )
+  }
+
+  /// Compares the applied migrations with the ones compiled into the binary. Returns an error
+  /// describing every mismatch when a script was edited after being applied or the database
+  /// contains migrations this build does not know about.
+  pub async fn check(&self, client: &Object) -> Result<(), DbError> {
+    let applied = self.applied(client).await?;
+    let known: HashMap<i64, &Migration> = self.migrations.iter().map(|m| (m.version, m)).collect();
+
+    let mut problems = Vec::new();
+    for record in &applied {
+      match known.get(&record.version) {
+        Some(migration) if migration.checksum() != record.checksum => problems.push(format!(
+          "migration {} ({}) was modified after it was applied",
+          record.version, record.name
+        )),
+        Some(_) => {}
+        None => problems.push(format!(
+          "migration {} ({}) is applied but unknown to this build",
+          record.version, record.name
+        )),
+      }
+    }
+
+    if problems.is_empty() {
+      Ok(())
+    } else {
+      Err(DbError::MigrationError(format!(
+        "Schema drift detected: {}",
+        problems.join("; ")
+      )))
+    }
+  }
+
+  /// Applies every pending migration, each in its own transaction
+  pub async fn up(&self) -> Result<usize, DbError> {
+    let mut client = self.pool.get().await?;
+    lock(&client).await?;
+
+    let result = async {
+      self.check(&client).await?;
+      let rows = client
+        .query("SELECT version FROM schema_migrations", &[])
+        .await?;
+      let applied: Vec<i64> = rows.iter().map(|row| row.get("version")).collect();
+
+      let mut count = 0;
+      for migration in self
+        .migrations
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+      {
+        let transaction = client.transaction().await?;
+        transaction.batch_execute(migration.up).await.map_err(|e| {
+          DbError::MigrationError(format!(
+            "Migration {} ({}) failed: {}",
+            migration.version, migration.name, e
+          ))
+        })?;
+        transaction
+          .execute(
+            "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)",
+            &[&migration.version, &migration.name, &migration.checksum()],
+          )
+          .await?;
+        transaction.commit().await?;
+
+        log::info(&format!(
+          "Applied migration {} ({})",
+          migration.version, migration.name
+        ));
+        count += 1;
+      }
+      Ok(count)
+    }
+    .await;
+
+    unlock(client).await;
+    result
+  }
+
+  /// Reverts applied migrations newer than `target`, newest first
+  pub async fn down(&self, target: i64) -> Result<usize, DbError> {
+    let mut client = self.pool.get().await?;
+    lock(&client).await?;
+
+    let result = async {
+      self.check(&client).await?;
+      let rows = client
+        .query(
+          "SELECT version FROM schema_migrations WHERE version > $1 ORDER BY version DESC",
+          &[&target],
+        )
+        .await?;
+      let versions: Vec<i64> = rows.iter().map(|row| row.get("version")).collect();
+
+      let mut count = 0;
+      for version in versions {
+        let Some(migration) = self.migrations.iter().find(|m| m.version == version) else {
+          continue;
+        };
+        let transaction = client.transaction().await?;
+        transaction
+          .batch_execute(migration.down)
+          .await
+          .map_err(|e| {
+            DbError::MigrationError(format!(
+              "Reverting migration {} ({}) failed: {}",
+              migration.version, migration.name, e
+            ))
+          })?;
+        transaction
+          .execute(
+            "DELETE FROM schema_migrations WHERE version = $1",
+            &[&migration.version],
+          )
+          .await?;
+        transaction.commit().await?;
+
+        log::info(&format!(
+          "Reverted migration {} ({})",
+          migration.version, migration.name
+        ));
+        count += 1;
+      }
+      Ok(count)
+    }
+    .await;
+
+    unlock(client).await;
+    result
+  }
+
+  /// Prints one line per known migration with its applied state
+  pub async fn status(&self) -> Result<(), DbError> {
+    let client = self.pool.get().await?;
+    let applied = self.applied(&client).await?;
+    for migration in self.migrations {
+      match applied.iter().find(|a| a.version == migration.version) {
+        Some(record) => println!(
+          "{:>4}  {:<30} applied {}",
+          migration.version,
+          migration.name,
+          record.applied_at.to_rfc3339()
+        ),
+        None => println!("{:>4}  {:<30} pending", migration.version, migration.name),
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Waits for other instances to finish migrating
+async fn lock(client: &Object) -> Result<(), DbError> {
+  client
+    .execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK_KEY])
+    .await?;
+  Ok(())
+}
+
+/// Releases the lock taken by `lock`. A connection that fails to release it is taken out of the
+/// pool and closed, which ends its session and the lock with it.
+async fn unlock(client: Object) {
+  if let Err(e) = client
+    .execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK_KEY])
+    .await
+  {
+    log::error(&format!("Failed to release the migration lock: {}", e));
+    drop(Object::take(client));
+  }
+}
+
+/// Handles `migrate up`, `migrate down <version>` and `migrate status` from the command line
+pub async fn run_command(migrator: &Migrator, args: &[String]) -> Result<(), DbError> {
+  match args.first().map(String::as_str) {
+    Some("up") | None => {
+      let count = migrator.up().await?;
+      println!("Applied {} migration(s)", count);
+    }
+    Some("down") => {
+      let target = match args.get(1).map(|v| v.parse::<i64>()) {
+        Some(Ok(v)) => v,
+        _ => {
+          return Err(DbError::MigrationError(
+            "Usage: migrate down <target version>".to_string(),
+          ));
+        }
+      };
+      let count = migrator.down(target).await?;
+      println!("Reverted {} migration(s)", count);
+    }
+    Some("status") => migrator.status().await?,
+    Some(other) => {
+      return Err(DbError::MigrationError(format!(
+        "Unknown migrate command: {}",
+        other
+      )));
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,106 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+pub const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+  Asc,
+  #[default]
+  Desc,
+}
+
+impl SortOrder {
+  pub fn as_sql(&self) -> &'static str {
+    match self {
+      SortOrder::Asc => "ASC",
+      SortOrder::Desc => "DESC",
+    }
+  }
+}
+
+/// One page of a listing
+#[derive(Serialize, ToSchema)]
+pub struct Page<T> {
+  pub items: Vec<T>,
+  /// Number of rows matching the filters across every page
+  pub total: i64,
+  pub limit: i64,
+  /// Set when the page was requested by offset
+  pub offset: Option<i64>,
+  /// Pass as `cursor` to get the next page; `null` on the last page
+  pub next_cursor: Option<String>,
+}
+
+/// Position after the last row of a page: the value of the sort column and the row id, which
+/// breaks ties. The sort it was created for is included so it cannot be reused with another.
+#[derive(Serialize, Deserialize)]
+pub struct Cursor {
+  pub sort: String,
+  pub order: SortOrder,
+  pub value: String,
+  pub id: Uuid,
+}
+
+impl Cursor {
+  pub fn encode(&self) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+  }
+
+  pub fn decode(value: &str) -> Option<Cursor> {
+    let bytes = URL_SAFE_NO_PAD.decode(value).ok()?;
+    serde_json::from_slice(&bytes).ok()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cursor() -> Cursor {
+    Cursor {
+      sort: "created_at".to_string(),
+      order: SortOrder::Desc,
+      value: "2024-05-01T12:00:00+00:00".to_string(),
+      id: Uuid::new_v4(),
+    }
+  }
+
+  #[test]
+  fn cursor_round_trips() {
+    let original = cursor();
+    let decoded = Cursor::decode(&original.encode()).expect("cursor decodes");
+    assert_eq!(decoded.sort, original.sort);
+    assert_eq!(decoded.order, original.order);
+    assert_eq!(decoded.value, original.value);
+    assert_eq!(decoded.id, original.id);
+  }
+
+  #[test]
+  fn cursor_is_url_safe() {
+    let encoded = cursor().encode();
+    assert!(
+      encoded
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    );
+  }
+
+  #[test]
+  fn tampered_cursors_are_rejected() {
+    let encoded = cursor().encode();
+    assert!(Cursor::decode("").is_none());
+    assert!(Cursor::decode("not a cursor").is_none());
+    assert!(Cursor::decode(&encoded[..encoded.len() / 2]).is_none());
+    assert!(Cursor::decode(&format!("{}!", encoded)).is_none());
+    // Valid base64 and JSON, but not a cursor
+    assert!(Cursor::decode(&URL_SAFE_NO_PAD.encode(br#"{"sort":"name"}"#)).is_none());
+    let bad_id = br#"{"sort":"name","order":"asc","value":"a","id":"nope"}"#;
+    assert!(Cursor::decode(&URL_SAFE_NO_PAD.encode(bad_id)).is_none());
+  }
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::{Decision, RateLimitError, RateLimitStore, decide, next_tat, now_ms};
+use crate::config::RateLimitRule;
+
+/// Keeps TATs in the memory of this process, so every instance counts on its own
+#[derive(Default)]
+pub struct MemoryStore {
+  tats: Mutex<HashMap<String, i64>>,
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+  async fn acquire(&self, key: &str, rule: &RateLimitRule) -> Result<Decision, RateLimitError> {
+    let now = now_ms();
+    let mut tats = self
+      .tats
+      .lock()
+      .map_err(|_| RateLimitError("rate limit state is poisoned".to_string()))?;
+    let stored = tats.get(key).copied();
+    match next_tat(stored, now, rule) {
+      Some(tat) => {
+        tats.insert(key.to_string(), tat);
+        Ok(decide(true, tat, now, rule))
+      }
+      None => Ok(decide(false, stored.unwrap_or(now), now, rule)),
+    }
+  }
+
+  async fn purge(&self) -> Result<u64, RateLimitError> {
+    let now = now_ms();
+    let mut tats = self
+      .tats
+      .lock()
+      .map_err(|_| RateLimitError("rate limit state is poisoned".to_string()))?;
+    let before = tats.len();
+    tats.retain(|_, tat| *tat > now);
+    Ok((before - tats.len()) as u64)
+  }
+}
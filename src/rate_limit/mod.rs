@@ -0,0 +1,215 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::config::{Config, RateLimitBackend, RateLimitConfig, RateLimitRule};
+use crate::db::DbPool;
+use crate::log;
+
+pub mod memory;
+pub mod postgres;
+
+pub use memory::MemoryStore;
+pub use postgres::PostgresStore;
+
+/// How often keys whose limit has fully recovered are removed from the store
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Routes that share a rate limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitGroup {
+  Register,
+  Login,
+  Upload,
+}
+
+impl RateLimitGroup {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      RateLimitGroup::Register => "register",
+      RateLimitGroup::Login => "login",
+      RateLimitGroup::Upload => "upload",
+    }
+  }
+
+  pub fn rule<'a>(&self, config: &'a RateLimitConfig) -> &'a RateLimitRule {
+    match self {
+      RateLimitGroup::Register => &config.register,
+      RateLimitGroup::Login => &config.login,
+      RateLimitGroup::Upload => &config.upload,
+    }
+  }
+}
+
+/// Outcome of counting one request
+#[derive(Clone, Copy, Debug)]
+pub struct Decision {
+  pub allowed: bool,
+  /// Requests per window
+  pub limit: u32,
+  /// Requests that could still be made right now
+  pub remaining: u32,
+  /// Seconds until the full limit is available again
+  pub reset_secs: u64,
+  /// Seconds until the next request is allowed, 0 when this one was
+  pub retry_after_secs: u64,
+}
+
+#[derive(Debug)]
+pub struct RateLimitError(pub String);
+
+impl fmt::Display for RateLimitError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Rate limit store error: {}", self.0)
+  }
+}
+
+/// Keeps the state of every rate limited key. Limits use the generic cell rate algorithm, a
+/// token bucket that only needs to store the theoretical arrival time (TAT) of the next request
+/// per key, in milliseconds since the epoch.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+  /// Counts a request for `key` if the rule allows it
+  async fn acquire(&self, key: &str, rule: &RateLimitRule) -> Result<Decision, RateLimitError>;
+
+  /// Forgets keys that are back to their full limit. Returns how many were removed.
+  async fn purge(&self) -> Result<u64, RateLimitError>;
+}
+
+/// Creates the backend selected by `rate_limit.backend`
+pub fn from_config(config: &Config, pool: &DbPool) -> Arc<dyn RateLimitStore> {
+  match config.rate_limit.backend {
+    RateLimitBackend::Memory => Arc::new(MemoryStore::default()),
+    RateLimitBackend::Postgres => Arc::new(PostgresStore::new(pool.clone())),
+  }
+}
+
+/// Purges the store every minute for as long as the server runs
+pub fn purge_periodically(store: Arc<dyn RateLimitStore>) {
+  actix_web::rt::spawn(async move {
+    let mut interval = actix_web::rt::time::interval(PURGE_INTERVAL);
+    loop {
+      interval.tick().await;
+      match store.purge().await {
+        Ok(0) => {}
+        Ok(count) => log::debug(&format!("Purged {} rate limit key(s)", count)),
+        Err(e) => log::warn(&e.to_string()),
+      }
+    }
+  });
+}
+
+/// Current time in milliseconds since the epoch, the unit TATs are stored in
+fn now_ms() -> i64 {
+  Utc::now().timestamp_millis()
+}
+
+/// Milliseconds one request uses up, and the length of the window
+fn intervals(rule: &RateLimitRule) -> (i64, i64) {
+  let window = (rule.window_secs as i64).saturating_mul(1000);
+  let emission = (window / i64::from(rule.requests.max(1))).max(1);
+  (emission, window)
+}
+
+/// The TAT to store if a request arriving at `now` is allowed, given the stored one
+fn next_tat(stored: Option<i64>, now: i64, rule: &RateLimitRule) -> Option<i64> {
+  let (emission, window) = intervals(rule);
+  let tat = stored.unwrap_or(now).max(now) + emission;
+  (tat - window <= now).then_some(tat)
+}
+
+/// Describes the state after a request at `now`. `tat` is the stored TAT after the request,
+/// updated if it was allowed.
+fn decide(allowed: bool, tat: i64, now: i64, rule: &RateLimitRule) -> Decision {
+  let (emission, window) = intervals(rule);
+  let tat = tat.max(now);
+  let remaining = ((now + window - tat) / emission).clamp(0, i64::from(rule.requests)) as u32;
+  let retry_after = if allowed {
+    0
+  } else {
+    ceil_secs(tat + emission - window - now)
+  };
+  Decision {
+    allowed,
+    limit: rule.requests,
+    remaining,
+    reset_secs: ceil_secs(tat - now),
+    retry_after_secs: retry_after.max(u64::from(!allowed)),
+  }
+}
+
+fn ceil_secs(ms: i64) -> u64 {
+  (ms.max(0) as u64).div_ceil(1000)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::RateLimitKey;
+
+  /// 5 requests per 10 seconds, so one request every 2 seconds
+  fn rule() -> RateLimitRule {
+    RateLimitRule {
+      requests: 5,
+      window_secs: 10,
+      key: RateLimitKey::Ip,
+    }
+  }
+
+  /// Counts a request like the stores do and returns the decision
+  fn acquire(stored: &mut Option<i64>, now: i64) -> Decision {
+    match next_tat(*stored, now, &rule()) {
+      Some(tat) => {
+        *stored = Some(tat);
+        decide(true, tat, now, &rule())
+      }
+      None => decide(false, stored.unwrap_or(now), now, &rule()),
+    }
+  }
+
+  #[test]
+  fn full_window_can_be_used_at_once() {
+    let mut stored = None;
+    for remaining in (0..5).rev() {
+      let decision = acquire(&mut stored, 0);
+      assert!(decision.allowed);
+      assert_eq!(decision.remaining, remaining);
+      assert_eq!(decision.retry_after_secs, 0);
+    }
+    assert_eq!(stored, Some(10_000));
+
+    let denied = acquire(&mut stored, 0);
+    assert!(!denied.allowed);
+    assert_eq!(denied.remaining, 0);
+    assert_eq!(denied.retry_after_secs, 2);
+    assert_eq!(denied.reset_secs, 10);
+    assert_eq!(stored, Some(10_000), "denied requests are not counted");
+  }
+
+  #[test]
+  fn one_request_returns_per_emission_interval() {
+    let mut stored = Some(10_000);
+    let early = acquire(&mut stored, 1_999);
+    assert!(!early.allowed);
+    assert_eq!(early.retry_after_secs, 1);
+
+    let allowed = acquire(&mut stored, 2_000);
+    assert!(allowed.allowed);
+    assert_eq!(allowed.remaining, 0);
+    assert_eq!(stored, Some(12_000));
+    assert!(!acquire(&mut stored, 2_000).allowed);
+  }
+
+  #[test]
+  fn idle_keys_start_over() {
+    let mut stored = Some(10_000);
+    let decision = acquire(&mut stored, 60_000);
+    assert!(decision.allowed);
+    assert_eq!(decision.remaining, 4);
+    assert_eq!(decision.reset_secs, 2);
+    assert_eq!(stored, Some(62_000));
+  }
+}
@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+
+use super::{Decision, RateLimitError, RateLimitStore, decide, intervals, now_ms};
+use crate::config::RateLimitRule;
+use crate::db::DbPool;
+use crate::metrics::QueryTimer;
+
+/// Keeps TATs in the `rate_limits` table so that every instance shares the same limits
+pub struct PostgresStore {
+  pool: DbPool,
+}
+
+impl PostgresStore {
+  pub fn new(pool: DbPool) -> Self {
+    PostgresStore { pool }
+  }
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresStore {
+  async fn acquire(&self, key: &str, rule: &RateLimitRule) -> Result<Decision, RateLimitError> {
+    let _timer = QueryTimer::start("rate_limits.acquire");
+    let client = self.pool.get().await.map_err(store_error)?;
+    let now = now_ms();
+    let (emission, window) = intervals(rule);
+
+    // Only updates the row when the request is allowed, in one statement so that concurrent
+    // requests cannot both take the last slot
+    let allowed = client
+      .query_opt(
+        "INSERT INTO rate_limits (key, tat_ms) VALUES ($1, $2::BIGINT + $3::BIGINT)
+            ON CONFLICT (key) DO UPDATE SET tat_ms = GREATEST(rate_limits.tat_ms, $2) + $3
+              WHERE GREATEST(rate_limits.tat_ms, $2) + $3 - $4::BIGINT <= $2
+            RETURNING tat_ms",
+        &[&key, &now, &emission, &window],
+      )
+      .await
+      .map_err(store_error)?;
+    if let Some(row) = allowed {
+      return Ok(decide(true, row.get("tat_ms"), now, rule));
+    }
+
+    let row = client
+      .query_opt("SELECT tat_ms FROM rate_limits WHERE key = $1", &[&key])
+      .await
+      .map_err(store_error)?;
+    let tat = row.map(|row| row.get("tat_ms")).unwrap_or(now);
+    Ok(decide(false, tat, now, rule))
+  }
+
+  async fn purge(&self) -> Result<u64, RateLimitError> {
+    let _timer = QueryTimer::start("rate_limits.purge");
+    let client = self.pool.get().await.map_err(store_error)?;
+    client
+      .execute("DELETE FROM rate_limits WHERE tat_ms <= $1", &[&now_ms()])
+      .await
+      .map_err(store_error)
+  }
+}
+
+fn store_error(err: impl std::fmt::Display) -> RateLimitError {
+  RateLimitError(err.to_string())
+}
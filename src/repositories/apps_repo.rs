@@ -1,27 +1,36 @@
-use async_trait::async_trait;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio_postgres::Client;
+use chrono::SecondsFormat;
+use tokio_postgres::Row;
+use tokio_postgres::types::ToSql;
 use uuid::Uuid;
 
+use crate::db::DbPool;
 use crate::dberror::DbError;
 use crate::log;
-use crate::repository::Repository;
-use crate::tables::apps::Apps;
-use crate::tools::table_name_from_statement;
+use crate::media::ImageVariant;
+use crate::metrics::QueryTimer;
+use crate::pagination::{Cursor, Page, SortOrder};
+use crate::requests::list_apps_request::{AppSort, ListAppsRequest};
+use crate::tables::app_image::AppImage;
+use crate::tables::apps::{AppSearchResult, Apps};
+
+/// Control characters marking matches in `ts_headline` output. They are replaced only after
+/// the text is escaped, so app names and descriptions cannot inject markup.
+const HIGHLIGHT_START: char = '\u{2}';
+const HIGHLIGHT_STOP: char = '\u{3}';
 
 #[derive(Clone)]
 pub struct AppsRepo {
-  client: Arc<Mutex<Client>>,
+  pool: DbPool,
 }
 
 impl AppsRepo {
-  pub fn new(client: Arc<Mutex<Client>>) -> Self {
-    Self { client }
+  pub fn new(pool: DbPool) -> Self {
+    Self { pool }
   }
 
   pub async fn get_app_by_id(&self, id: Uuid) -> Result<Apps, DbError> {
-    let client = self.client.lock().await;
+    let _timer = QueryTimer::start("apps.get_app_by_id");
+    let client = self.pool.get().await?;
 
     let rows = client
       .query("SELECT * FROM apps WHERE id = $1", &[&id])
@@ -45,41 +54,184 @@ impl AppsRepo {
     }
   }
 
-  pub async fn get_apps_by_user_id(&self, user_id: Uuid) -> Result<Vec<Apps>, DbError> {
-    let client = self.client.lock().await;
+  /// Lists apps, optionally only those owned by `user_id`, applying the filters and sort of the
+  /// request. Pages continue after `cursor` when given, otherwise they start at `offset`.
+  pub async fn list_apps(
+    &self,
+    user_id: Option<Uuid>,
+    request: &ListAppsRequest,
+    limit: i64,
+    cursor: Option<&Cursor>,
+  ) -> Result<Page<Apps>, DbError> {
+    let _timer = QueryTimer::start("apps.list_apps");
+    let client = self.pool.get().await?;
+
+    let mut conditions = vec!["TRUE".to_string()];
+    let mut params: Vec<Box<dyn ToSql + Sync + Send>> = Vec::new();
+    if let Some(user_id) = user_id {
+      conditions.push(format!("user_id = {}", bind(&mut params, user_id)));
+    }
+    if let Some(is_active) = request.is_active {
+      conditions.push(format!("is_active = {}", bind(&mut params, is_active)));
+    }
+    if let Some(after) = request.created_after {
+      conditions.push(format!("created_at >= {}", bind(&mut params, after)));
+    }
+    if let Some(before) = request.created_before {
+      conditions.push(format!("created_at < {}", bind(&mut params, before)));
+    }
+    let filter_count = params.len();
+    let filters = conditions.join(" AND ");
+
+    let column = request.sort.as_str();
+    let order = request.order.as_sql();
+    let mut page_conditions = filters.clone();
+    if let Some(cursor) = cursor {
+      let value_type = match request.sort {
+        AppSort::Name => "text",
+        AppSort::CreatedAt | AppSort::UpdatedAt => "timestamptz",
+      };
+      let comparison = match request.order {
+        SortOrder::Asc => ">",
+        SortOrder::Desc => "<",
+      };
+      let value = bind(&mut params, cursor.value.clone());
+      let id = bind(&mut params, cursor.id);
+      page_conditions.push_str(&format!(
+        " AND ({}, id) {} ({}::text::{}, {})",
+        column, comparison, value, value_type, id
+      ));
+    }
+    let limit_param = bind(&mut params, limit + 1);
+    let offset_param = bind(&mut params, request.offset.unwrap_or(0));
+
+    let params: Vec<&(dyn ToSql + Sync)> = params
+      .iter()
+      .map(|p| p.as_ref() as &(dyn ToSql + Sync))
+      .collect();
+
+    let total: i64 = client
+      .query_one(
+        &format!("SELECT COUNT(*) FROM apps WHERE {}", filters),
+        &params[..filter_count],
+      )
+      .await?
+      .get(0);
 
     let rows = client
       .query(
-        "
-            SELECT *
-                FROM apps
-                JOIN users ON apps.user_id = users.id
-                WHERE users.id = $1",
-        &[&user_id],
+        &format!(
+          "SELECT * FROM apps WHERE {}
+            ORDER BY {} {}, id {}
+            LIMIT {} OFFSET {}",
+          page_conditions, column, order, order, limit_param, offset_param
+        ),
+        &params,
       )
       .await
       .map_err(|e| {
-        log::error(&format!("Failed to query apps by user_id: {}", e));
+        log::error(&format!("Failed to list apps: {}", e));
         DbError::from(e)
       })?;
 
-    let mut apps = Vec::new();
-    for row in rows {
-      let app = Apps {
-        id: row.get("id"),
-        name: row.get("name"),
-        description: row.get("description"),
-        github_url: row.get("github_url"),
-        image_name: row.get("image_name"),
-        created_at: row.get("created_at"),
-        updated_at: row.get("updated_at"),
-        is_active: row.get("is_active"),
-      };
-      apps.push(app);
-    }
-    Ok(apps)
+    let mut items: Vec<Apps> = rows.iter().map(row_to_app).collect();
+    let next_cursor = if items.len() as i64 > limit {
+      items.truncate(limit as usize);
+      items.last().map(|last| {
+        let value = match request.sort {
+          AppSort::Name => last.name.clone(),
+          AppSort::CreatedAt => last.created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+          AppSort::UpdatedAt => last.updated_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+        };
+        Cursor {
+          sort: column.to_string(),
+          order: request.order,
+          value,
+          id: last.id,
+        }
+        .encode()
+      })
+    } else {
+      None
+    };
+
+    Ok(Page {
+      items,
+      total,
+      limit,
+      offset: request.offset,
+      next_cursor,
+    })
   }
 
+  /// Full-text search over active apps, ranked by relevance. Names within a few typos of the
+  /// query also match through trigram similarity.
+  pub async fn search_apps(
+    &self,
+    query: &str,
+    limit: i64,
+    offset: i64,
+  ) -> Result<Page<AppSearchResult>, DbError> {
+    let _timer = QueryTimer::start("apps.search_apps");
+    let client = self.pool.get().await?;
+
+    let matches = "is_active AND (search_vector @@ query OR name % $1 OR $1 <% name)";
+    let total: i64 = client
+      .query_one(
+        &format!(
+          "SELECT COUNT(*) FROM apps, websearch_to_tsquery('english', $1) AS query WHERE {}",
+          matches
+        ),
+        &[&query],
+      )
+      .await?
+      .get(0);
+
+    let name_options = format!(
+      "StartSel={}, StopSel={}, HighlightAll=true",
+      HIGHLIGHT_START, HIGHLIGHT_STOP
+    );
+    let snippet_options = format!(
+      "StartSel={}, StopSel={}, MaxFragments=2, MaxWords=20, MinWords=8",
+      HIGHLIGHT_START, HIGHLIGHT_STOP
+    );
+    let rows = client
+      .query(
+        &format!(
+          "SELECT apps.*,
+              ts_rank(search_vector, query) + similarity(name, $1) AS rank,
+              ts_headline('english', name, query, $4) AS name_highlight,
+              ts_headline('english', description, query, $5) AS snippet
+            FROM apps, websearch_to_tsquery('english', $1) AS query
+            WHERE {}
+            ORDER BY rank DESC, id
+            LIMIT $2 OFFSET $3",
+          matches
+        ),
+        &[&query, &limit, &offset, &name_options, &snippet_options],
+      )
+      .await?;
+
+    let items = rows
+      .iter()
+      .map(|row| AppSearchResult {
+        app: row_to_app(row),
+        rank: row.get("rank"),
+        name_highlight: render_highlight(row.get("name_highlight")),
+        snippet: render_highlight(row.get("snippet")),
+      })
+      .collect();
+
+    Ok(Page {
+      items,
+      total,
+      limit,
+      offset: Some(offset),
+      next_cursor: None,
+    })
+  }
+
+  /// Inserts the app together with the stored variants of its image
   pub async fn add_app(
     &self,
     name: &str,
@@ -87,45 +239,241 @@ impl AppsRepo {
     github_url: Option<&str>,
     image_url: &str,
     user_id: Uuid,
+    images: &[AppImage],
   ) -> Result<(), DbError> {
-    let client = self.client.lock().await;
+    let _timer = QueryTimer::start("apps.add_app");
+    let mut client = self.pool.get().await?;
+    let transaction = client.transaction().await?;
 
-    client
-            .execute(
-                "INSERT INTO apps (name, description, github_url, image_url, user_id) VALUES ($1, $2, $3, $4, $5)",
+    let row = transaction
+            .query_one(
+                "INSERT INTO apps (name, description, github_url, image_name, user_id) VALUES ($1, $2, $3, $4, $5) RETURNING id",
                 &[&name, &description, &github_url, &image_url, &user_id],
             )
             .await
             .map_err(DbError::from)?;
+    let app_id: Uuid = row.get("id");
+
+    for image in images {
+      transaction
+        .execute(
+          "INSERT INTO app_image_variants (app_id, variant, file_name, width, height, content_type)
+              VALUES ($1, $2, $3, $4, $5, $6)",
+          &[
+            &app_id,
+            &image.variant.as_str(),
+            &image.file_name,
+            &image.width,
+            &image.height,
+            &image.content_type,
+          ],
+        )
+        .await?;
+    }
+    transaction.commit().await?;
     Ok(())
   }
+
+  /// The stored variants of an app's image, smallest first
+  pub async fn get_app_images(&self, id: Uuid) -> Result<Vec<AppImage>, DbError> {
+    let _timer = QueryTimer::start("apps.get_app_images");
+    let client = self.pool.get().await?;
+
+    let rows = client
+      .query(
+        "SELECT * FROM app_image_variants WHERE app_id = $1 ORDER BY width * height",
+        &[&id],
+      )
+      .await?;
+
+    Ok(
+      rows
+        .iter()
+        .filter_map(|row| {
+          Some(AppImage {
+            variant: ImageVariant::from_name(row.get("variant"))?,
+            file_name: row.get("file_name"),
+            width: row.get("width"),
+            height: row.get("height"),
+            content_type: row.get("content_type"),
+          })
+        })
+        .collect(),
+    )
+  }
+
+  pub async fn get_app_owner(&self, id: Uuid) -> Result<Uuid, DbError> {
+    let _timer = QueryTimer::start("apps.get_app_owner");
+    let client = self.pool.get().await?;
+
+    let row = client
+      .query_opt("SELECT user_id FROM apps WHERE id = $1", &[&id])
+      .await?
+      .ok_or(DbError::NotFound)?;
+
+    Ok(row.get("user_id"))
+  }
+
+  pub async fn update_app(
+    &self,
+    id: Uuid,
+    name: &str,
+    description: &str,
+    github_url: Option<&str>,
+    is_active: bool,
+  ) -> Result<Apps, DbError> {
+    let _timer = QueryTimer::start("apps.update_app");
+    let client = self.pool.get().await?;
+
+    let row = client
+      .query_opt(
+        "UPDATE apps
+            SET name = $2, description = $3, github_url = $4, is_active = $5
+            WHERE id = $1
+            RETURNING *",
+        &[&id, &name, &description, &github_url, &is_active],
+      )
+      .await?
+      .ok_or(DbError::NotFound)?;
+
+    Ok(row_to_app(&row))
+  }
+
+  /// Updates only the given fields. `github_url` is `Some(None)` to clear the URL.
+  pub async fn patch_app(
+    &self,
+    id: Uuid,
+    name: Option<&str>,
+    description: Option<&str>,
+    github_url: Option<Option<&str>>,
+    is_active: Option<bool>,
+  ) -> Result<Apps, DbError> {
+    let _timer = QueryTimer::start("apps.patch_app");
+    let client = self.pool.get().await?;
+
+    let row = client
+      .query_opt(
+        "UPDATE apps
+            SET name = COALESCE($2, name),
+                description = COALESCE($3, description),
+                github_url = CASE WHEN $4 THEN $5 ELSE github_url END,
+                is_active = COALESCE($6, is_active)
+            WHERE id = $1
+            RETURNING *",
+        &[
+          &id,
+          &name,
+          &description,
+          &github_url.is_some(),
+          &github_url.flatten(),
+          &is_active,
+        ],
+      )
+      .await?
+      .ok_or(DbError::NotFound)?;
+
+    Ok(row_to_app(&row))
+  }
+
+  pub async fn set_app_active(&self, id: Uuid, is_active: bool) -> Result<Apps, DbError> {
+    self.patch_app(id, None, None, None, Some(is_active)).await
+  }
+
+  /// Deletes the app and returns the names of its stored image files
+  pub async fn delete_app(&self, id: Uuid) -> Result<Vec<String>, DbError> {
+    let _timer = QueryTimer::start("apps.delete_app");
+    let client = self.pool.get().await?;
+
+    // The subquery still sees the variants that the cascade removes
+    let row = client
+      .query_opt(
+        "DELETE FROM apps WHERE id = $1
+            RETURNING image_name,
+              ARRAY(SELECT file_name FROM app_image_variants WHERE app_id = $1) AS variants",
+        &[&id],
+      )
+      .await?
+      .ok_or(DbError::NotFound)?;
+
+    let mut files: Vec<String> = row.get("variants");
+    let image_name: String = row.get("image_name");
+    if !files.contains(&image_name) {
+      files.push(image_name);
+    }
+    Ok(files)
+  }
 }
 
-#[async_trait]
-impl Repository for AppsRepo {
-  async fn create_table(&self) -> Result<(), String> {
-    let client = self.client.lock().await;
-
-    let statement: &str = "CREATE TABLE IF NOT EXISTS apps (
-                    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
-                    name VARCHAR(255) NOT NULL,
-                    description TEXT,
-                    github_url VARCHAR(255),
-                    image_name VARCHAR(255),
-                    user_id UUID NOT NULL REFERENCES users(id),
-                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                    is_active BOOLEAN DEFAULT TRUE
-                )";
-
-    client
-      .execute(statement, &[])
-      .await
-      .map_err(|e| e.to_string())?;
-    log::info(
-      format!("Created table {}", table_name_from_statement(&statement)).as_str(),
-      true,
+fn row_to_app(row: &Row) -> Apps {
+  Apps::new(
+    row.get("id"),
+    row.get("name"),
+    row.get("description"),
+    row.get("created_at"),
+    row.get("updated_at"),
+    row.get("is_active"),
+    row.get("image_name"),
+    row.get("github_url"),
+  )
+}
+
+/// Escapes the text for HTML and turns the `ts_headline` markers into `<mark>` tags
+fn render_highlight(text: &str) -> String {
+  let mut html = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      HIGHLIGHT_START => html.push_str("<mark>"),
+      HIGHLIGHT_STOP => html.push_str("</mark>"),
+      '&' => html.push_str("&amp;"),
+      '<' => html.push_str("&lt;"),
+      '>' => html.push_str("&gt;"),
+      '"' => html.push_str("&quot;"),
+      '\'' => html.push_str("&#39;"),
+      c => html.push(c),
+    }
+  }
+  html
+}
+
+/// Adds a query parameter and returns its placeholder
+fn bind(
+  params: &mut Vec<Box<dyn ToSql + Sync + Send>>,
+  value: impl ToSql + Sync + Send + 'static,
+) -> String {
+  params.push(Box::new(value));
+  format!("${}", params.len())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn marked(text: &str) -> String {
+    format!("{}{}{}", HIGHLIGHT_START, text, HIGHLIGHT_STOP)
+  }
+
+  #[test]
+  fn highlight_wraps_matches_in_mark() {
+    let text = format!("a {} planner", marked("meal"));
+    assert_eq!(render_highlight(&text), "a <mark>meal</mark> planner");
+  }
+
+  #[test]
+  fn highlight_escapes_text_around_and_inside_matches() {
+    let text = format!(
+      "<script>alert(\"x\")</script> {} & 'more'",
+      marked("<b>chess</b>")
     );
-    Ok(())
+    assert_eq!(
+      render_highlight(&text),
+      "&lt;script&gt;alert(&quot;x&quot;)&lt;/script&gt; \
+       <mark>&lt;b&gt;chess&lt;/b&gt;</mark> &amp; &#39;more&#39;"
+    );
+  }
+
+  #[test]
+  fn highlight_does_not_unescape_entities() {
+    assert_eq!(render_highlight("&lt;mark&gt;"), "&amp;lt;mark&amp;gt;");
+    assert_eq!(render_highlight("plain text"), "plain text");
   }
 }
@@ -0,0 +1,83 @@
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use crate::db::DbPool;
+use crate::dberror::DbError;
+use crate::metrics::QueryTimer;
+use crate::tables::login_event::{LoginEvent, LoginOutcome};
+
+#[derive(Clone)]
+pub struct LoginEventRepo {
+  pool: DbPool,
+}
+
+impl LoginEventRepo {
+  pub fn new(pool: DbPool) -> Self {
+    Self { pool }
+  }
+
+  /// Records a login attempt. A successful one also sets the user's `last_login_at`. Events
+  /// older than 90 days are removed from the user's history at the same time.
+  pub async fn record(
+    &self,
+    user_id: Uuid,
+    outcome: LoginOutcome,
+    ip: &str,
+    user_agent: Option<&str>,
+    session_id: Option<Uuid>,
+  ) -> Result<(), DbError> {
+    let _timer = QueryTimer::start("login_events.record");
+    let mut client = self.pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    transaction
+      .execute(
+        "INSERT INTO login_events (user_id, outcome, ip, user_agent, session_id)
+            VALUES ($1, $2, $3, $4, $5)",
+        &[&user_id, &outcome.as_str(), &ip, &user_agent, &session_id],
+      )
+      .await?;
+    if outcome == LoginOutcome::Success {
+      transaction
+        .execute(
+          "UPDATE users SET last_login_at = CURRENT_TIMESTAMP WHERE id = $1",
+          &[&user_id],
+        )
+        .await?;
+    }
+    transaction
+      .execute(
+        "DELETE FROM login_events
+            WHERE user_id = $1 AND created_at < CURRENT_TIMESTAMP - INTERVAL '90 days'",
+        &[&user_id],
+      )
+      .await?;
+    transaction.commit().await?;
+    Ok(())
+  }
+
+  /// The user's latest login attempts, newest first
+  pub async fn recent(&self, user_id: Uuid, limit: i64) -> Result<Vec<LoginEvent>, DbError> {
+    let _timer = QueryTimer::start("login_events.recent");
+    let client = self.pool.get().await?;
+
+    let rows = client
+      .query(
+        "SELECT * FROM login_events WHERE user_id = $1 ORDER BY created_at DESC LIMIT $2",
+        &[&user_id, &limit],
+      )
+      .await?;
+    Ok(rows.iter().filter_map(login_event_from_row).collect())
+  }
+}
+
+fn login_event_from_row(row: &Row) -> Option<LoginEvent> {
+  Some(LoginEvent {
+    id: row.get("id"),
+    outcome: LoginOutcome::from_name(row.get("outcome"))?,
+    ip: row.get("ip"),
+    user_agent: row.get("user_agent"),
+    session_id: row.get("session_id"),
+    created_at: row.get("created_at"),
+  })
+}
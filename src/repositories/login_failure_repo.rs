@@ -0,0 +1,137 @@
+use chrono::{DateTime, Utc};
+use tokio_postgres::Row;
+
+use crate::db::DbPool;
+use crate::dberror::DbError;
+use crate::metrics::QueryTimer;
+use crate::tables::login_failure::{LoginFailure, LoginFailureKind};
+
+#[derive(Clone)]
+pub struct LoginFailureRepo {
+  pool: DbPool,
+}
+
+impl LoginFailureRepo {
+  pub fn new(pool: DbPool) -> Self {
+    Self { pool }
+  }
+
+  /// Latest time until which the account or the IP address is locked, if either still is
+  pub async fn locked_until(
+    &self,
+    account: &str,
+    ip: &str,
+  ) -> Result<Option<DateTime<Utc>>, DbError> {
+    let _timer = QueryTimer::start("login_failures.locked_until");
+    let client = self.pool.get().await?;
+
+    let row = client
+      .query_one(
+        "SELECT MAX(locked_until) AS locked_until FROM login_failures
+            WHERE ((kind = 'account' AND subject = $1) OR (kind = 'ip' AND subject = $2))
+              AND locked_until > CURRENT_TIMESTAMP",
+        &[&account, &ip],
+      )
+      .await?;
+    Ok(row.get("locked_until"))
+  }
+
+  /// Counts a failed login and returns the number of failures since `since`. Older failures are
+  /// forgotten, and so are entries of other subjects that went quiet before then.
+  pub async fn record_failure(
+    &self,
+    kind: LoginFailureKind,
+    subject: &str,
+    since: DateTime<Utc>,
+  ) -> Result<i32, DbError> {
+    let _timer = QueryTimer::start("login_failures.record_failure");
+    let client = self.pool.get().await?;
+
+    client
+      .execute(
+        "DELETE FROM login_failures WHERE last_failure_at < $1
+            AND (locked_until IS NULL OR locked_until < CURRENT_TIMESTAMP)",
+        &[&since],
+      )
+      .await?;
+    let row = client
+      .query_one(
+        "INSERT INTO login_failures (kind, subject) VALUES ($1, $2)
+            ON CONFLICT (kind, subject) DO UPDATE SET
+              failures = CASE WHEN login_failures.last_failure_at < $3 THEN 1
+                ELSE login_failures.failures + 1 END,
+              last_failure_at = CURRENT_TIMESTAMP
+            RETURNING failures",
+        &[&kind.as_str(), &subject, &since],
+      )
+      .await?;
+    Ok(row.get("failures"))
+  }
+
+  /// Refuses logins for the subject until `until`
+  pub async fn lock(
+    &self,
+    kind: LoginFailureKind,
+    subject: &str,
+    until: DateTime<Utc>,
+  ) -> Result<(), DbError> {
+    let _timer = QueryTimer::start("login_failures.lock");
+    let client = self.pool.get().await?;
+
+    client
+      .execute(
+        "UPDATE login_failures SET locked_until = $3 WHERE kind = $1 AND subject = $2",
+        &[&kind.as_str(), &subject, &until],
+      )
+      .await?;
+    Ok(())
+  }
+
+  /// Forgets the failures of the subject, lifting any lockout. Returns `NotFound` when there
+  /// were none.
+  pub async fn clear(&self, kind: LoginFailureKind, subject: &str) -> Result<(), DbError> {
+    let _timer = QueryTimer::start("login_failures.clear");
+    let client = self.pool.get().await?;
+
+    let deleted = client
+      .execute(
+        "DELETE FROM login_failures WHERE kind = $1 AND subject = $2",
+        &[&kind.as_str(), &subject],
+      )
+      .await?;
+    if deleted == 0 {
+      return Err(DbError::NotFound);
+    }
+    Ok(())
+  }
+
+  /// Subjects that are locked or failed to log in since `since`, locked ones first
+  pub async fn list(&self, since: DateTime<Utc>) -> Result<Vec<LoginFailure>, DbError> {
+    let _timer = QueryTimer::start("login_failures.list");
+    let client = self.pool.get().await?;
+
+    let rows = client
+      .query(
+        "SELECT f.*, u.id AS user_id, u.username FROM login_failures f
+            LEFT JOIN users u ON f.kind = 'account' AND u.id::text = f.subject
+            WHERE f.last_failure_at >= $1 OR f.locked_until > CURRENT_TIMESTAMP
+            ORDER BY f.locked_until > CURRENT_TIMESTAMP DESC NULLS LAST,
+              f.last_failure_at DESC",
+        &[&since],
+      )
+      .await?;
+    Ok(rows.iter().filter_map(login_failure_from_row).collect())
+  }
+}
+
+fn login_failure_from_row(row: &Row) -> Option<LoginFailure> {
+  Some(LoginFailure {
+    kind: LoginFailureKind::from_name(row.get("kind"))?,
+    subject: row.get("subject"),
+    user_id: row.get("user_id"),
+    username: row.get("username"),
+    failures: row.get("failures"),
+    last_failure_at: row.get("last_failure_at"),
+    locked_until: row.get("locked_until"),
+  })
+}
@@ -1,2 +1,7 @@
 pub mod apps_repo;
+pub mod login_event_repo;
+pub mod login_failure_repo;
+pub mod password_reset_repo;
+pub mod token_repo;
 pub mod user_repo;
+pub mod verification_repo;
@@ -0,0 +1,100 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::db::DbPool;
+use crate::dberror::DbError;
+use crate::metrics::QueryTimer;
+
+#[derive(Clone)]
+pub struct PasswordResetRepo {
+  pool: DbPool,
+}
+
+impl PasswordResetRepo {
+  pub fn new(pool: DbPool) -> Self {
+    Self { pool }
+  }
+
+  pub async fn add_token(
+    &self,
+    user_id: Uuid,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+  ) -> Result<(), DbError> {
+    let _timer = QueryTimer::start("password_reset.add_token");
+    let client = self.pool.get().await?;
+
+    client
+      .execute(
+        "INSERT INTO password_reset_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+        &[&user_id, &token_hash, &expires_at],
+      )
+      .await?;
+    Ok(())
+  }
+
+  /// Number of reset emails sent to the user in the last 24 hours and when the latest one was
+  /// sent
+  pub async fn recent_tokens(
+    &self,
+    user_id: Uuid,
+  ) -> Result<(i64, Option<DateTime<Utc>>), DbError> {
+    let _timer = QueryTimer::start("password_reset.recent_tokens");
+    let client = self.pool.get().await?;
+
+    let row = client
+      .query_one(
+        "SELECT COUNT(*) AS sent, MAX(created_at) AS latest FROM password_reset_tokens
+            WHERE user_id = $1 AND created_at > CURRENT_TIMESTAMP - INTERVAL '1 day'",
+        &[&user_id],
+      )
+      .await?;
+    Ok((row.get("sent"), row.get("latest")))
+  }
+
+  /// Redeems the token and sets the new password hash. Every other reset token and every login
+  /// session of the user is revoked, which stops their refresh and access tokens, and the email
+  /// address counts as verified since the link reached it. Returns `NotFound` when the token is
+  /// unknown, expired or was already used.
+  pub async fn reset_password(&self, token_hash: &str, password: &str) -> Result<Uuid, DbError> {
+    let _timer = QueryTimer::start("password_reset.reset_password");
+    let mut client = self.pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+      .query_opt(
+        "UPDATE password_reset_tokens SET used_at = CURRENT_TIMESTAMP
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+            RETURNING user_id",
+        &[&token_hash],
+      )
+      .await?
+      .ok_or(DbError::NotFound)?;
+    let user_id: Uuid = row.get("user_id");
+
+    transaction
+      .execute(
+        "UPDATE users SET password = $2,
+            email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP)
+            WHERE id = $1",
+        &[&user_id, &password],
+      )
+      .await?;
+    transaction
+      .execute(
+        "UPDATE password_reset_tokens SET used_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND used_at IS NULL",
+        &[&user_id],
+      )
+      .await?;
+    transaction
+      .execute(
+        "UPDATE refresh_tokens SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND revoked_at IS NULL",
+        &[&user_id],
+      )
+      .await?;
+    transaction.commit().await?;
+    Ok(user_id)
+  }
+}
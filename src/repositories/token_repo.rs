@@ -0,0 +1,183 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::auth::auth_user::SessionRevocations;
+use crate::db::DbPool;
+use crate::dberror::DbError;
+use crate::metrics::QueryTimer;
+use crate::tables::login_event::Session;
+use crate::tables::refresh_token::RefreshTokenRecord;
+
+#[derive(Clone)]
+pub struct TokenRepo {
+  pool: DbPool,
+}
+
+impl TokenRepo {
+  pub fn new(pool: DbPool) -> Self {
+    Self { pool }
+  }
+
+  pub async fn add_refresh_token(
+    &self,
+    user_id: Uuid,
+    family_id: Uuid,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+  ) -> Result<(), DbError> {
+    let _timer = QueryTimer::start("tokens.add_refresh_token");
+    let client = self.pool.get().await?;
+
+    client
+      .execute(
+        "INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4)",
+        &[&user_id, &family_id, &token_hash, &expires_at],
+      )
+      .await?;
+    Ok(())
+  }
+
+  pub async fn get_refresh_token(&self, token_hash: &str) -> Result<RefreshTokenRecord, DbError> {
+    let _timer = QueryTimer::start("tokens.get_refresh_token");
+    let client = self.pool.get().await?;
+
+    let row = client
+      .query_opt(
+        "SELECT * FROM refresh_tokens WHERE token_hash = $1",
+        &[&token_hash],
+      )
+      .await?
+      .ok_or(DbError::NotFound)?;
+
+    Ok(RefreshTokenRecord {
+      id: row.get("id"),
+      user_id: row.get("user_id"),
+      family_id: row.get("family_id"),
+      expires_at: row.get("expires_at"),
+      used_at: row.get("used_at"),
+      revoked_at: row.get("revoked_at"),
+    })
+  }
+
+  /// Marks the token as used. Returns false if it was already used, which means it is being
+  /// replayed.
+  pub async fn mark_used(&self, id: Uuid) -> Result<bool, DbError> {
+    let _timer = QueryTimer::start("tokens.mark_used");
+    let client = self.pool.get().await?;
+
+    let rows = client
+      .execute(
+        "UPDATE refresh_tokens SET used_at = CURRENT_TIMESTAMP WHERE id = $1 AND used_at IS NULL",
+        &[&id],
+      )
+      .await?;
+    Ok(rows == 1)
+  }
+
+  /// Revokes every token of a login session
+  pub async fn revoke_family(&self, family_id: Uuid) -> Result<u64, DbError> {
+    let _timer = QueryTimer::start("tokens.revoke_family");
+    let client = self.pool.get().await?;
+
+    let rows = client
+      .execute(
+        "UPDATE refresh_tokens SET revoked_at = CURRENT_TIMESTAMP
+            WHERE family_id = $1 AND revoked_at IS NULL",
+        &[&family_id],
+      )
+      .await?;
+    Ok(rows)
+  }
+
+  /// The user's login sessions that can still be refreshed, most recently used first
+  pub async fn active_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, DbError> {
+    let _timer = QueryTimer::start("tokens.active_sessions");
+    let client = self.pool.get().await?;
+
+    let rows = client
+      .query(
+        "SELECT s.*, e.ip, e.user_agent FROM (
+              SELECT family_id, MIN(created_at) AS created_at, MAX(created_at) AS last_used_at,
+                MAX(expires_at) AS expires_at
+              FROM refresh_tokens WHERE user_id = $1
+              GROUP BY family_id
+              HAVING bool_or(used_at IS NULL AND revoked_at IS NULL
+                AND expires_at > CURRENT_TIMESTAMP)
+            ) s
+            LEFT JOIN login_events e ON e.session_id = s.family_id AND e.outcome = 'success'
+            ORDER BY s.last_used_at DESC",
+        &[&user_id],
+      )
+      .await?;
+    Ok(
+      rows
+        .iter()
+        .map(|row| Session {
+          id: row.get("family_id"),
+          current: false,
+          ip: row.get("ip"),
+          user_agent: row.get("user_agent"),
+          created_at: row.get("created_at"),
+          last_used_at: row.get("last_used_at"),
+          expires_at: row.get("expires_at"),
+        })
+        .collect(),
+    )
+  }
+
+  /// Revokes one of the user's login sessions. Returns `NotFound` when the user has no such
+  /// session or it was already revoked.
+  pub async fn revoke_session(&self, user_id: Uuid, family_id: Uuid) -> Result<(), DbError> {
+    let _timer = QueryTimer::start("tokens.revoke_session");
+    let client = self.pool.get().await?;
+
+    let rows = client
+      .execute(
+        "UPDATE refresh_tokens SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND family_id = $2 AND revoked_at IS NULL",
+        &[&user_id, &family_id],
+      )
+      .await?;
+    if rows == 0 {
+      return Err(DbError::NotFound);
+    }
+    Ok(())
+  }
+
+  /// Revokes every login session of the user except `keep`. Returns the number of revoked
+  /// refresh tokens.
+  pub async fn revoke_other_sessions(&self, user_id: Uuid, keep: Uuid) -> Result<u64, DbError> {
+    let _timer = QueryTimer::start("tokens.revoke_other_sessions");
+    let client = self.pool.get().await?;
+
+    let rows = client
+      .execute(
+        "UPDATE refresh_tokens SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND family_id <> $2 AND revoked_at IS NULL",
+        &[&user_id, &keep],
+      )
+      .await?;
+    Ok(rows)
+  }
+}
+
+#[async_trait]
+impl SessionRevocations for TokenRepo {
+  /// Revoking a session marks all of its refresh tokens, so one revoked token is enough
+  async fn is_revoked(&self, session_id: Uuid) -> Result<bool, DbError> {
+    let _timer = QueryTimer::start("tokens.is_revoked");
+    let client = self.pool.get().await?;
+
+    let row = client
+      .query_one(
+        "SELECT EXISTS (
+              SELECT 1 FROM refresh_tokens WHERE family_id = $1 AND revoked_at IS NOT NULL
+            ) AS revoked",
+        &[&session_id],
+      )
+      .await?;
+    Ok(row.get("revoked"))
+  }
+}
@@ -1,87 +1,50 @@
-use async_trait::async_trait;
+use tokio_postgres::Row;
 use uuid::Uuid;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio_postgres::Client;
 
-use crate::{log, repository::Repository, tables::user::User, tools::table_name_from_statement};
+use crate::{
+  auth::role::Role, db::DbPool, dberror::DbError, metrics::QueryTimer, tables::user::User,
+};
 
 #[derive(Clone)]
 pub struct UserRepo {
-  client: Arc<Mutex<Client>>,
+  pool: DbPool,
 }
 
 impl UserRepo {
-  pub fn new(client: Arc<Mutex<Client>>) -> Self {
-    Self { client }
+  pub fn new(pool: DbPool) -> Self {
+    Self { pool }
   }
 
-  pub async fn get_user_id(&self, user_id: Uuid) -> Result<User, String> {
-    let client = self.client.lock().await;
+  pub async fn get_user_id(&self, user_id: Uuid) -> Result<User, DbError> {
+    let _timer = QueryTimer::start("users.get_user_id");
+    let client = self.pool.get().await?;
 
     let rows = client
       .query("SELECT * FROM users WHERE id = $1", &[&user_id])
-      .await
-      .map_err(|e| e.to_string())?;
+      .await?;
 
     if let Some(row) = rows.into_iter().next() {
-      let id: Uuid = row.get("id");
-      let username: String = row.get("username");
-      let email: String = row.get("email");
-      let created_at: chrono::DateTime<chrono::Utc> = row.get("created_at");
-      let last_logged_in: Option<chrono::DateTime<chrono::Utc>> = row.get("last_login_at");
-      let terms: bool = row.get("terms");
-      let is_admin: bool = row.get("is_admin");
-      let password: Option<String> = None;
-
-      Ok(User::new(
-        id,
-        username,
-        email,
-        created_at,
-        last_logged_in,
-        terms,
-        is_admin,
-        password,
-      ))
+      Ok(row_to_user(&row, false))
     } else {
-      Err("User not found".to_string())
+      Err(DbError::NotFound)
     }
   }
 
-  pub async fn get_user_username_authentication(&self, username: &str) -> Result<User, String> {
-    let client = self.client.lock().await;
+  pub async fn get_user_username_authentication(&self, username: &str) -> Result<User, DbError> {
+    let _timer = QueryTimer::start("users.get_user_username_authentication");
+    let client = self.pool.get().await?;
 
     let rows = client
       .query(
         "SELECT * FROM users WHERE username ILIKE $1 OR email ILIKE $1",
         &[&username],
       )
-      .await
-      .map_err(|e| e.to_string())?;
+      .await?;
 
     if let Some(row) = rows.into_iter().next() {
-      let id: Uuid = row.get("id");
-      let username: String = row.get("username");
-      let email: String = row.get("email");
-      let created_at: chrono::DateTime<chrono::Utc> = row.get("created_at");
-      let last_logged_in: Option<chrono::DateTime<chrono::Utc>> = row.get("last_login_at");
-      let terms: bool = row.get("terms");
-      let is_admin: bool = row.get("is_admin");
-      let password: Option<String> = row.get("password");
-
-      Ok(User::new(
-        id,
-        username,
-        email,
-        created_at,
-        last_logged_in,
-        terms,
-        is_admin,
-        password,
-      ))
+      Ok(row_to_user(&row, true))
     } else {
-      Err("User not found".to_string())
+      Err(DbError::NotFound)
     }
   }
 
@@ -91,75 +54,146 @@ impl UserRepo {
     email: String,
     password: String,
     terms: bool,
-  ) -> Result<bool, String> {
-    let client = self.client.lock().await;
+  ) -> Result<Uuid, DbError> {
+    let _timer = QueryTimer::start("users.register_user");
+    let client = self.pool.get().await?;
 
-    let rows = client
-      .execute(
-        "INSERT INTO users (username, email, password, terms) VALUES ($1, $2, $3, $4)",
+    let row = client
+      .query_one(
+        "INSERT INTO users (username, email, password, terms) VALUES ($1, $2, $3, $4) RETURNING id",
         &[&username, &email, &password, &terms],
       )
-      .await
-      .map_err(|e| e.to_string())?;
+      .await?;
+    Ok(row.get("id"))
+  }
 
-    if rows == 1 {
-      Ok(true)
-    } else {
-      Err("Failed to insert user".to_string())
+  pub async fn get_user_email(&self, email: &str) -> Result<User, DbError> {
+    let _timer = QueryTimer::start("users.get_user_email");
+    let client = self.pool.get().await?;
+
+    let row = client
+      .query_opt(
+        "SELECT * FROM users WHERE LOWER(email) = LOWER($1)",
+        &[&email],
+      )
+      .await?
+      .ok_or(DbError::NotFound)?;
+    Ok(row_to_user(&row, false))
+  }
+
+  /// The bcrypt hash of the user's password
+  pub async fn get_password_hash(&self, user_id: Uuid) -> Result<String, DbError> {
+    let _timer = QueryTimer::start("users.get_password_hash");
+    let client = self.pool.get().await?;
+
+    let row = client
+      .query_opt("SELECT password FROM users WHERE id = $1", &[&user_id])
+      .await?
+      .ok_or(DbError::NotFound)?;
+    Ok(row.get("password"))
+  }
+
+  /// Replaces the password hash and revokes every login session and pending password reset of
+  /// the user. Revoking the sessions stops their refresh tokens and, through the session check
+  /// in `authenticate`, their access tokens.
+  pub async fn update_password(&self, user_id: Uuid, password: &str) -> Result<(), DbError> {
+    let _timer = QueryTimer::start("users.update_password");
+    let mut client = self.pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let updated = transaction
+      .execute(
+        "UPDATE users SET password = $2 WHERE id = $1",
+        &[&user_id, &password],
+      )
+      .await?;
+    if updated == 0 {
+      return Err(DbError::NotFound);
     }
+    transaction
+      .execute(
+        "UPDATE refresh_tokens SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND revoked_at IS NULL",
+        &[&user_id],
+      )
+      .await?;
+    transaction
+      .execute(
+        "UPDATE password_reset_tokens SET used_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND used_at IS NULL",
+        &[&user_id],
+      )
+      .await?;
+    transaction.commit().await?;
+    Ok(())
   }
 
-  pub async fn user_exists_by_username(&self, username: &str) -> Result<bool, String> {
-    let client = self.client.lock().await;
+  pub async fn user_exists_by_username(&self, username: &str) -> Result<bool, DbError> {
+    let _timer = QueryTimer::start("users.user_exists_by_username");
+    let client = self.pool.get().await?;
     let rows = client
       .query(
         "SELECT 1 FROM users WHERE LOWER(username) = LOWER($1)",
         &[&username],
       )
-      .await
-      .map_err(|e| e.to_string())?;
+      .await?;
     Ok(!rows.is_empty())
   }
 
-  pub async fn user_exists_by_email(&self, email: &str) -> Result<bool, String> {
-    let client = self.client.lock().await;
+  pub async fn user_exists_by_email(&self, email: &str) -> Result<bool, DbError> {
+    let _timer = QueryTimer::start("users.user_exists_by_email");
+    let client = self.pool.get().await?;
     let rows = client
       .query(
         "SELECT 1 FROM users WHERE LOWER(email) = LOWER($1)",
         &[&email],
       )
-      .await
-      .map_err(|e| e.to_string())?;
+      .await?;
     Ok(!rows.is_empty())
   }
-}
 
-#[async_trait]
-impl Repository for UserRepo {
-  async fn create_table(&self) -> Result<(), String> {
-    let client = self.client.lock().await;
-
-    let statement: &str = "CREATE TABLE IF NOT EXISTS users (
-                id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
-                username VARCHAR(50) UNIQUE NOT NULL,
-                email VARCHAR(100) UNIQUE NOT NULL,
-                password VARCHAR(255) NOT NULL,
-                created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
-                last_login_at TIMESTAMP WITH TIME ZONE,
-                terms BOOLEAN DEFAULT FALSE,
-                is_admin BOOLEAN DEFAULT FALSE
-            )";
-
-    client
-      .execute(statement, &[])
-      .await
-      .map_err(|e| e.to_string())?;
-
-    log::info(
-      format!("Created table {}", table_name_from_statement(&statement)).as_str(),
-      true,
-    );
+  pub async fn list_users(&self) -> Result<Vec<User>, DbError> {
+    let _timer = QueryTimer::start("users.list_users");
+    let client = self.pool.get().await?;
 
-    Ok(())
+    let rows = client
+      .query("SELECT * FROM users ORDER BY created_at", &[])
+      .await?;
+
+    Ok(rows.iter().map(|row| row_to_user(row, false)).collect())
   }
+
+  /// Changes the user's role and keeps `is_admin` in line with it
+  pub async fn set_user_role(&self, user_id: Uuid, role: Role) -> Result<User, DbError> {
+    let _timer = QueryTimer::start("users.set_user_role");
+    let client = self.pool.get().await?;
+
+    let rows = client
+      .query(
+        "UPDATE users SET role = $2, is_admin = $3 WHERE id = $1 RETURNING *",
+        &[&user_id, &role.as_str(), &(role == Role::Admin)],
+      )
+      .await?;
+
+    match rows.first() {
+      Some(row) => Ok(row_to_user(row, false)),
+      None => Err(DbError::NotFound),
+    }
+  }
+}
+
+fn row_to_user(row: &Row, with_password: bool) -> User {
+  let role: String = row.get("role");
+  User::new(
+    row.get("id"),
+    row.get("username"),
+    row.get("email"),
+    row.get("created_at"),
+    row.get("last_login_at"),
+    row.get("email_verified_at"),
+    row.get("terms"),
+    row.get("is_admin"),
+    role.parse().unwrap_or_default(),
+    if with_password { row.get("password") } else { None },
+  )
 }
@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::db::DbPool;
+use crate::dberror::DbError;
+use crate::metrics::QueryTimer;
+
+#[derive(Clone)]
+pub struct VerificationRepo {
+  pool: DbPool,
+}
+
+impl VerificationRepo {
+  pub fn new(pool: DbPool) -> Self {
+    Self { pool }
+  }
+
+  pub async fn add_token(
+    &self,
+    user_id: Uuid,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+  ) -> Result<(), DbError> {
+    let _timer = QueryTimer::start("verification.add_token");
+    let client = self.pool.get().await?;
+
+    client
+      .execute(
+        "INSERT INTO email_verification_tokens (user_id, token_hash, expires_at)
+            VALUES ($1, $2, $3)",
+        &[&user_id, &token_hash, &expires_at],
+      )
+      .await?;
+    Ok(())
+  }
+
+  /// Number of verification emails sent to the user in the last 24 hours and when the latest
+  /// one was sent
+  pub async fn recent_tokens(
+    &self,
+    user_id: Uuid,
+  ) -> Result<(i64, Option<DateTime<Utc>>), DbError> {
+    let _timer = QueryTimer::start("verification.recent_tokens");
+    let client = self.pool.get().await?;
+
+    let row = client
+      .query_one(
+        "SELECT COUNT(*) AS sent, MAX(created_at) AS latest FROM email_verification_tokens
+            WHERE user_id = $1 AND created_at > CURRENT_TIMESTAMP - INTERVAL '1 day'",
+        &[&user_id],
+      )
+      .await?;
+    Ok((row.get("sent"), row.get("latest")))
+  }
+
+  /// Marks the token as used and the user's email address as verified. Returns the user the
+  /// token belongs to, or `NotFound` when it is unknown, expired or was already used.
+  pub async fn redeem_token(&self, token_hash: &str) -> Result<Uuid, DbError> {
+    let _timer = QueryTimer::start("verification.redeem_token");
+    let mut client = self.pool.get().await?;
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+      .query_opt(
+        "UPDATE email_verification_tokens SET used_at = CURRENT_TIMESTAMP
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+            RETURNING user_id",
+        &[&token_hash],
+      )
+      .await?
+      .ok_or(DbError::NotFound)?;
+    let user_id: Uuid = row.get("user_id");
+
+    transaction
+      .execute(
+        "UPDATE users SET email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP)
+            WHERE id = $1",
+        &[&user_id],
+      )
+      .await?;
+    transaction.commit().await?;
+    Ok(user_id)
+  }
+}
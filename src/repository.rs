@@ -1,29 +1,33 @@
 use std::sync::Arc;
 
-use async_trait::async_trait;
-
-use crate::{log, repositories::{apps_repo::AppsRepo, user_repo::UserRepo}};
-
-#[async_trait]
-#[allow(dead_code)]
-pub trait Repository: Send + Sync {
-  async fn create_table(&self) -> Result<(), String>;
-}
+use crate::db::DbPool;
+use crate::repositories::{
+  apps_repo::AppsRepo, login_event_repo::LoginEventRepo, login_failure_repo::LoginFailureRepo,
+  password_reset_repo::PasswordResetRepo, token_repo::TokenRepo, user_repo::UserRepo,
+  verification_repo::VerificationRepo,
+};
 
 #[derive(Clone)]
 pub struct Repositories {
   pub apps: Arc<AppsRepo>,
   pub user: Arc<UserRepo>,
+  pub tokens: Arc<TokenRepo>,
+  pub verification: Arc<VerificationRepo>,
+  pub password_reset: Arc<PasswordResetRepo>,
+  pub login_failures: Arc<LoginFailureRepo>,
+  pub login_events: Arc<LoginEventRepo>,
 }
 
 impl Repositories {
-  pub async fn create_tables(&self) -> Result<(), String> {
-    let _ = self.apps.create_table().await.map_err(|e| {
-      log::warn(&format!("Failed to create apps table: {}", e));
-    });
-    let _ = self.user.create_table().await.map_err(|e| {
-      log::warn(&format!("Failed to create user table: {}", e));
-    });
-    Ok(())
+  pub fn new(pool: &DbPool) -> Self {
+    Repositories {
+      apps: Arc::new(AppsRepo::new(pool.clone())),
+      user: Arc::new(UserRepo::new(pool.clone())),
+      tokens: Arc::new(TokenRepo::new(pool.clone())),
+      verification: Arc::new(VerificationRepo::new(pool.clone())),
+      password_reset: Arc::new(PasswordResetRepo::new(pool.clone())),
+      login_failures: Arc::new(LoginFailureRepo::new(pool.clone())),
+      login_events: Arc::new(LoginEventRepo::new(pool.clone())),
+    }
   }
 }
@@ -1,8 +1,19 @@
 use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct CreateAppRequest {
   pub name: String,
   pub description: String,
   pub github_url: String,
 }
+
+/// Multipart body of `POST /api/apps`. Only used to document the upload.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct ImageUpload {
+  /// The app image
+  #[schema(value_type = String, format = Binary)]
+  pub image: Vec<u8>,
+}
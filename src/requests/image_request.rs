@@ -0,0 +1,12 @@
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::media::ImageVariant;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImageRequest {
+  #[serde(default)]
+  #[param(inline)]
+  pub variant: ImageVariant,
+}
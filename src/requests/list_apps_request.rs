@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::pagination::SortOrder;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AppSort {
+  Name,
+  #[default]
+  CreatedAt,
+  UpdatedAt,
+}
+
+impl AppSort {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      AppSort::Name => "name",
+      AppSort::CreatedAt => "created_at",
+      AppSort::UpdatedAt => "updated_at",
+    }
+  }
+}
+
+/// Query string of the app listings. Pages are requested either with `cursor` or with `offset`.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListAppsRequest {
+  /// Page size, 1 to 100 (default 20)
+  pub limit: Option<i64>,
+  /// `next_cursor` of the previous page
+  pub cursor: Option<String>,
+  /// Number of rows to skip, instead of a cursor
+  pub offset: Option<i64>,
+  #[serde(default)]
+  #[param(inline)]
+  pub sort: AppSort,
+  #[serde(default)]
+  #[param(inline)]
+  pub order: SortOrder,
+  /// Only active or only inactive apps. Without it the owner, moderators and admins see both,
+  /// and everyone else sees active apps only.
+  pub is_active: Option<bool>,
+  /// Only apps created at or after this time (RFC 3339)
+  pub created_after: Option<DateTime<Utc>>,
+  /// Only apps created before this time (RFC 3339)
+  pub created_before: Option<DateTime<Utc>>,
+}
@@ -1,3 +1,11 @@
 pub mod create_app_request;
+pub mod image_request;
+pub mod list_apps_request;
 pub mod login_request;
+pub mod password_request;
+pub mod refresh_request;
 pub mod register_request;
+pub mod search_apps_request;
+pub mod set_role_request;
+pub mod update_app_request;
+pub mod verify_email_request;
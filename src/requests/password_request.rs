@@ -0,0 +1,20 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct ChangePasswordRequest {
+  pub current_password: String,
+  pub new_password: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ForgotPasswordRequest {
+  pub email: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResetPasswordRequest {
+  /// Token from the link in the reset email
+  pub token: String,
+  pub new_password: String,
+}
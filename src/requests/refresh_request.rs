@@ -0,0 +1,7 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct RefreshRequest {
+  pub refresh_token: String,
+}
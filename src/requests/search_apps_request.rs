@@ -0,0 +1,13 @@
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchAppsRequest {
+  /// Search words; quoted phrases, `or` and `-word` are supported
+  pub q: String,
+  /// Page size, 1 to 100 (default 20)
+  pub limit: Option<i64>,
+  /// Number of results to skip
+  pub offset: Option<i64>,
+}
@@ -0,0 +1,9 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::auth::role::Role;
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetRoleRequest {
+  pub role: Role,
+}
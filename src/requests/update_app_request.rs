@@ -0,0 +1,20 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateAppRequest {
+  pub name: String,
+  pub description: String,
+  pub github_url: Option<String>,
+  pub is_active: bool,
+}
+
+/// Body for a partial update. Missing fields are left unchanged and an empty
+/// `github_url` clears the stored URL.
+#[derive(Deserialize, ToSchema)]
+pub struct PatchAppRequest {
+  pub name: Option<String>,
+  pub description: Option<String>,
+  pub github_url: Option<String>,
+  pub is_active: Option<bool>,
+}
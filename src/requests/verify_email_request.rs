@@ -0,0 +1,14 @@
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VerifyEmailRequest {
+  /// Token from the link in the verification email
+  pub token: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResendVerificationRequest {
+  pub email: String,
+}
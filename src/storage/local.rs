@@ -0,0 +1,142 @@
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures_util::{StreamExt, stream};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::{ByteStream, ObjectMeta, Storage, StorageError, validate_key};
+use crate::upload::{self, PARTIAL_SUFFIX};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Files in a directory on the local disk, served by the `/media` route
+pub struct LocalStorage {
+  root: PathBuf,
+}
+
+impl LocalStorage {
+  pub fn new(root: PathBuf) -> Self {
+    LocalStorage { root }
+  }
+
+  /// Path of an existing file, making sure it does not resolve outside the root through a
+  /// symlink
+  async fn existing_path(&self, key: &str) -> Result<PathBuf, StorageError> {
+    validate_key(key)?;
+    let root = tokio::fs::canonicalize(&self.root).await?;
+    let path = tokio::fs::canonicalize(self.root.join(key)).await?;
+    if path.starts_with(&root) {
+      Ok(path)
+    } else {
+      Err(StorageError::NotFound)
+    }
+  }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+  async fn put(&self, key: &str, data: Bytes, _content_type: &str) -> Result<(), StorageError> {
+    validate_key(key)?;
+    let path = self.root.join(key);
+    let (Some(directory), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str()))
+    else {
+      return Err(StorageError::InvalidKey(key.to_string()));
+    };
+    tokio::fs::create_dir_all(directory).await?;
+    upload::store_file(directory, name, &data).await?;
+    Ok(())
+  }
+
+  async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError> {
+    let metadata = tokio::fs::metadata(self.existing_path(key).await?).await?;
+    if !metadata.is_file() {
+      return Err(StorageError::NotFound);
+    }
+    Ok(ObjectMeta {
+      size: metadata.len(),
+      last_modified: metadata.modified().ok(),
+    })
+  }
+
+  async fn get(&self, key: &str, range: Option<(u64, u64)>) -> Result<ByteStream, StorageError> {
+    let mut file = tokio::fs::File::open(self.existing_path(key).await?).await?;
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+      return Err(StorageError::NotFound);
+    }
+
+    let (start, end) = range.map_or((0, metadata.len()), |(first, last)| (first, last + 1));
+    if start > 0 {
+      file.seek(SeekFrom::Start(start)).await?;
+    }
+    let body = stream::try_unfold((file, end - start), |(mut file, remaining)| async move {
+      if remaining == 0 {
+        return Ok(None);
+      }
+      let mut buffer = vec![0; CHUNK_SIZE.min(remaining as usize)];
+      let read = file.read(&mut buffer).await?;
+      if read == 0 {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+      }
+      buffer.truncate(read);
+      Ok(Some((Bytes::from(buffer), (file, remaining - read as u64))))
+    });
+
+    Ok(body.boxed())
+  }
+
+  async fn delete(&self, key: &str) -> Result<(), StorageError> {
+    validate_key(key)?;
+    match tokio::fs::remove_file(self.root.join(key)).await {
+      Ok(()) => Ok(()),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+      Err(e) => Err(StorageError::Io(e)),
+    }
+  }
+
+  async fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+    let prefix = prefix.trim_end_matches('/');
+    if !prefix.is_empty() {
+      validate_key(prefix)?;
+    }
+
+    let mut keys = Vec::new();
+    let mut pending = vec![prefix.to_string()];
+    while let Some(directory) = pending.pop() {
+      let mut entries = match tokio::fs::read_dir(self.root.join(&directory)).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+        Err(e) => return Err(StorageError::Io(e)),
+      };
+      while let Some(entry) = entries.next_entry().await? {
+        let Ok(name) = entry.file_name().into_string() else {
+          continue;
+        };
+        if name.starts_with('.') || name.ends_with(PARTIAL_SUFFIX) {
+          continue;
+        }
+        let key = if directory.is_empty() {
+          name
+        } else {
+          format!("{}/{}", directory, name)
+        };
+        if entry.file_type().await?.is_dir() {
+          pending.push(key);
+        } else {
+          keys.push(key);
+        }
+      }
+    }
+    keys.sort();
+    Ok(keys)
+  }
+
+  /// Local files are public, so the link is simply their `/media` path
+  fn presigned_url(&self, key: &str, _expires_in: Duration) -> Result<String, StorageError> {
+    validate_key(key)?;
+    Ok(format!("/media/{}", key))
+  }
+}
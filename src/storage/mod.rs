@@ -0,0 +1,104 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+
+use crate::config::{Config, StorageBackend};
+
+pub mod local;
+pub mod s3;
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+/// Body of a stored object
+pub type ByteStream = BoxStream<'static, std::io::Result<Bytes>>;
+
+pub struct ObjectMeta {
+  pub size: u64,
+  pub last_modified: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+  NotFound,
+  /// Keys are relative paths without empty, `.` or hidden segments
+  InvalidKey(String),
+  Io(std::io::Error),
+  /// The object store rejected the request or could not be reached
+  Backend(String),
+}
+
+impl fmt::Display for StorageError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      StorageError::NotFound => write!(f, "Object not found"),
+      StorageError::InvalidKey(ref key) => write!(f, "Invalid storage key: {:?}", key),
+      StorageError::Io(ref err) => write!(f, "Storage IO error: {}", err),
+      StorageError::Backend(ref msg) => write!(f, "Storage backend error: {}", msg),
+    }
+  }
+}
+
+impl From<std::io::Error> for StorageError {
+  fn from(err: std::io::Error) -> Self {
+    if err.kind() == std::io::ErrorKind::NotFound {
+      StorageError::NotFound
+    } else {
+      StorageError::Io(err)
+    }
+  }
+}
+
+/// Where stored media lives. Keys are `/` separated paths such as `images/app/{file_name}`.
+#[async_trait]
+pub trait Storage: Send + Sync {
+  async fn put(&self, key: &str, data: Bytes, content_type: &str) -> Result<(), StorageError>;
+
+  async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError>;
+
+  /// Reads the object, or only the bytes from `range.0` to `range.1` inclusive
+  async fn get(&self, key: &str, range: Option<(u64, u64)>) -> Result<ByteStream, StorageError>;
+
+  /// Removes the object. A missing object is not an error.
+  async fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+  /// Keys of every object under `prefix`
+  async fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError>;
+
+  /// A URL clients can fetch the object from for at least `expires_in`
+  fn presigned_url(&self, key: &str, expires_in: Duration) -> Result<String, StorageError>;
+}
+
+/// Creates the backend selected by `storage.backend`
+pub fn from_config(config: &Config) -> Arc<dyn Storage> {
+  match config.storage.backend {
+    StorageBackend::Local => Arc::new(LocalStorage::new(config.media.dir.clone())),
+    StorageBackend::S3 => Arc::new(S3Storage::new(config.storage.s3.clone())),
+  }
+}
+
+/// Key of a stored image
+pub fn image_key(image_type: &str, file_name: &str) -> String {
+  format!("images/{}/{}", image_type, file_name)
+}
+
+/// Rejects keys that could leave the storage root or address hidden and partial files
+pub fn validate_key(key: &str) -> Result<(), StorageError> {
+  let valid = !key.is_empty()
+    && !key.ends_with(crate::upload::PARTIAL_SUFFIX)
+    && key.split('/').all(|segment| {
+      !segment.is_empty()
+        && !segment.starts_with('.')
+        && !segment.contains('\\')
+        && !segment.chars().any(char::is_control)
+    });
+  if valid {
+    Ok(())
+  } else {
+    Err(StorageError::InvalidKey(key.to_string()))
+  }
+}
@@ -0,0 +1,14 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::media::ImageVariant;
+
+/// One stored size of an app image
+#[derive(Serialize, ToSchema)]
+pub struct AppImage {
+  pub variant: ImageVariant,
+  pub file_name: String,
+  pub width: i32,
+  pub height: i32,
+  pub content_type: String,
+}
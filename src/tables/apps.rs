@@ -1,7 +1,8 @@
 use serde::Serialize;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct Apps {
   pub id: Uuid,
   pub name: String,
@@ -14,6 +15,7 @@ pub struct Apps {
 }
 
 impl Apps {
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
     id: Uuid,
     name: String,
@@ -49,3 +51,16 @@ impl Apps {
     })
   }
 }
+
+/// An app found by search, with the matched words wrapped in `<mark>` tags. The rest of the
+/// text is HTML escaped.
+#[derive(Serialize, ToSchema)]
+pub struct AppSearchResult {
+  #[serde(flatten)]
+  pub app: Apps,
+  /// Relevance, higher is better
+  pub rank: f32,
+  pub name_highlight: String,
+  /// Fragments of the description around the matches
+  pub snippet: String,
+}
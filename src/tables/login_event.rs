@@ -0,0 +1,65 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// How a login attempt ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginOutcome {
+  Success,
+  WrongPassword,
+  /// Refused because of earlier failed attempts
+  Locked,
+  /// Right password, but unverified accounts may not log in
+  Unverified,
+}
+
+impl LoginOutcome {
+  pub const ALL: [LoginOutcome; 4] = [
+    LoginOutcome::Success,
+    LoginOutcome::WrongPassword,
+    LoginOutcome::Locked,
+    LoginOutcome::Unverified,
+  ];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      LoginOutcome::Success => "success",
+      LoginOutcome::WrongPassword => "wrong_password",
+      LoginOutcome::Locked => "locked",
+      LoginOutcome::Unverified => "unverified",
+    }
+  }
+
+  pub fn from_name(value: &str) -> Option<LoginOutcome> {
+    LoginOutcome::ALL.into_iter().find(|o| o.as_str() == value)
+  }
+}
+
+/// One login attempt against the user's account
+#[derive(Serialize, ToSchema)]
+pub struct LoginEvent {
+  pub id: Uuid,
+  pub outcome: LoginOutcome,
+  pub ip: String,
+  pub user_agent: Option<String>,
+  /// Session started by a successful login
+  pub session_id: Option<Uuid>,
+  pub created_at: DateTime<Utc>,
+}
+
+/// A login session: a refresh token family that can still be refreshed
+#[derive(Serialize, ToSchema)]
+pub struct Session {
+  pub id: Uuid,
+  /// Whether the caller's access token belongs to this session
+  pub current: bool,
+  /// Where the session was logged in from, unknown for sessions older than the login history
+  pub ip: Option<String>,
+  pub user_agent: Option<String>,
+  pub created_at: DateTime<Utc>,
+  /// When the session's tokens were last refreshed
+  pub last_used_at: DateTime<Utc>,
+  pub expires_at: DateTime<Utc>,
+}
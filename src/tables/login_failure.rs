@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// What failed logins are counted against
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LoginFailureKind {
+  Account,
+  Ip,
+}
+
+impl LoginFailureKind {
+  pub const ALL: [LoginFailureKind; 2] = [LoginFailureKind::Account, LoginFailureKind::Ip];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      LoginFailureKind::Account => "account",
+      LoginFailureKind::Ip => "ip",
+    }
+  }
+
+  pub fn from_name(value: &str) -> Option<LoginFailureKind> {
+    LoginFailureKind::ALL
+      .into_iter()
+      .find(|k| k.as_str() == value)
+  }
+}
+
+/// Recent failed logins for one account or IP address
+#[derive(Serialize, ToSchema)]
+pub struct LoginFailure {
+  pub kind: LoginFailureKind,
+  /// User id of an existing account, the lowercased name tried for an unknown one, or the IP
+  /// address
+  pub subject: String,
+  /// Set for existing accounts
+  pub user_id: Option<Uuid>,
+  pub username: Option<String>,
+  /// Failures since the counter was last reset
+  pub failures: i32,
+  pub last_failure_at: DateTime<Utc>,
+  /// Logins are refused until then
+  pub locked_until: Option<DateTime<Utc>>,
+}
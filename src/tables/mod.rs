@@ -1,2 +1,6 @@
+pub mod app_image;
 pub mod apps;
+pub mod login_event;
+pub mod login_failure;
+pub mod refresh_token;
 pub mod user;
@@ -0,0 +1,11 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+pub struct RefreshTokenRecord {
+  pub id: Uuid,
+  pub user_id: Uuid,
+  pub family_id: Uuid,
+  pub expires_at: DateTime<Utc>,
+  pub used_at: Option<DateTime<Utc>>,
+  pub revoked_at: Option<DateTime<Utc>>,
+}
@@ -1,26 +1,37 @@
 use chrono::{DateTime, Utc};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::auth::role::Role;
+
+#[derive(ToSchema)]
 pub struct User {
   pub id: Uuid,
   pub username: String,
   pub email: String,
   pub created_at: DateTime<Utc>,
   pub last_logged_in: Option<DateTime<Utc>>,
+  /// `None` until the user followed the link in the verification email
+  pub email_verified_at: Option<DateTime<Utc>>,
   pub terms: bool,
   pub is_admin: bool,
+  pub role: Role,
+  /// Only loaded for authentication, always `null` in responses
   pub password: Option<String>,
 }
 
 impl User {
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
     id: Uuid,
     username: String,
     email: String,
     created_at: DateTime<Utc>,
     last_logged_in: Option<DateTime<Utc>>,
+    email_verified_at: Option<DateTime<Utc>>,
     terms: bool,
     is_admin: bool,
+    role: Role,
     password: Option<String>,
   ) -> Self {
     User {
@@ -29,12 +40,18 @@ impl User {
       email,
       created_at,
       last_logged_in,
+      email_verified_at,
       terms,
       is_admin,
+      role,
       password,
     }
   }
 
+  pub fn email_verified(&self) -> bool {
+    self.email_verified_at.is_some()
+  }
+
   pub fn to_json(&self) -> serde_json::Value {
     serde_json::json!({
         "id": self.id,
@@ -42,8 +59,11 @@ impl User {
         "email": self.email,
         "created_at": self.created_at.to_rfc3339(),
         "last_logged_in": self.last_logged_in.map(|l| l.to_rfc3339()),
+        "email_verified": self.email_verified(),
+        "email_verified_at": self.email_verified_at.map(|v| v.to_rfc3339()),
         "terms": self.terms,
         "is_admin": self.is_admin,
+        "role": self.role,
         "password": self.password
     })
   }
@@ -0,0 +1,71 @@
+//! Helpers for tests that need PostgreSQL. Such tests are `#[ignore]`d; run them with
+//! `cargo test -- --ignored` and the `DATABASE_*` variables pointing at a server where the user
+//! may create databases.
+
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::migrations::Migrator;
+use crate::repository::Repositories;
+
+/// A migrated database of its own, so tests can run in parallel and leave nothing behind
+pub struct TestDatabase {
+  pub pool: DbPool,
+  /// Connects to the configured database, which the test database is created from and dropped in
+  admin: DbPool,
+  name: String,
+}
+
+impl TestDatabase {
+  pub async fn create() -> TestDatabase {
+    let mut config = Config::default();
+    for (var, key) in [
+      ("DATABASE_USERNAME", "database.username"),
+      ("DATABASE_PASSWORD", "database.password"),
+      ("DATABASE_HOST", "database.host"),
+      ("DATABASE_PORT", "database.port"),
+      ("DATABASE_NAME", "database.name"),
+    ] {
+      if let Ok(value) = std::env::var(var) {
+        config.set(key, &value).expect("valid database setting");
+      }
+    }
+    if config.database.name.is_empty() {
+      config.database.name = "postgres".to_string();
+    }
+
+    let admin = DbPool::new(&config.database)
+      .await
+      .expect("DATABASE_* must point at a PostgreSQL server");
+    let name = format!("test_{}", Uuid::new_v4().simple());
+    admin
+      .get()
+      .await
+      .unwrap()
+      .batch_execute(&format!("CREATE DATABASE {}", name))
+      .await
+      .expect("permission to create a test database");
+
+    config.database.name = name.clone();
+    let pool = DbPool::new(&config.database).await.unwrap();
+    Migrator::new(pool.clone()).up().await.unwrap();
+    TestDatabase { pool, admin, name }
+  }
+
+  pub fn repositories(&self) -> Repositories {
+    Repositories::new(&self.pool)
+  }
+
+  pub async fn remove(self) {
+    self.pool.close();
+    self
+      .admin
+      .get()
+      .await
+      .unwrap()
+      .batch_execute(&format!("DROP DATABASE {} WITH (FORCE)", self.name))
+      .await
+      .unwrap();
+  }
+}
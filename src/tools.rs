@@ -1,45 +1,32 @@
-use actix_multipart::Multipart;
-use futures_util::StreamExt;
-use mime_guess::get_mime_extensions_str;
-use std::io::Write;
-use uuid::Uuid;
+use actix_web::HttpRequest;
+use sha2::{Digest, Sha256};
+
+/// Hex encoded SHA-256 digest, used for checksums and for storing tokens
+pub fn sha256_hex(value: &str) -> String {
+  Sha256::digest(value.as_bytes())
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
 
-pub fn table_name_from_statement(statement: &str) -> &str {
-  let protected_statement_words = [
-    "CREATE",
-    "TABLE",
-    "INDEX",
-    "IF",
-    "NOT",
-    "EXISTS",
-    "TEMPORARY",
-    "TEMP",
-    "UNIQUE",
-    "PRIMARY",
-    "KEY",
-    "FOREIGN",
-    "REFERENCES",
-    "ON",
-    "CONSTRAINT",
-    "CHECK",
-  ];
-  let mut parts = statement.split_whitespace();
-  while let Some(part) = parts.next() {
-    if protected_statement_words.contains(&part.to_uppercase().as_str()) {
-      continue;
-    }
-    if part.contains('(') {
-      return part.split('(').next().unwrap_or("Unknown");
-    }
-    return part;
-  }
-  "Unknown"
+/// IP address of the client. Forwarding headers are only honoured when `trust_forwarded` is set,
+/// because clients can put anything in them.
+pub fn client_ip(req: &HttpRequest, trust_forwarded: bool) -> String {
+  let addr = if trust_forwarded {
+    req
+      .connection_info()
+      .realip_remote_addr()
+      .map(str::to_string)
+  } else {
+    req.peer_addr().map(|addr| addr.ip().to_string())
+  };
+  addr.unwrap_or_else(|| "unknown".to_string())
 }
 
 pub fn is_valid_username(username: &str) -> bool {
   let len = username.len();
 
-  if len < 3 || len > 20 {
+  if !(3..=20).contains(&len) {
     return false;
   }
 
@@ -53,7 +40,7 @@ pub fn is_valid_username(username: &str) -> bool {
 pub fn is_valid_email(email: &str) -> bool {
   let len = email.len();
 
-  if len < 5 || len > 254 {
+  if !(5..=254).contains(&len) {
     return false;
   }
 
@@ -78,43 +65,3 @@ pub fn is_valid_email(email: &str) -> bool {
 
   true
 }
-
-pub async fn save_image(image: &mut Multipart, image_type: &str) -> Result<String, String> {
-  let mut image_name = String::new();
-
-  while let Some(item) = image.next().await {
-    let mut field = match item {
-      Ok(f) => f,
-      Err(_) => return Err("Error reading multipart field".to_string()),
-    };
-
-    let content_disposition = field.content_disposition();
-    let field_name = content_disposition.unwrap().get_name().unwrap_or("");
-
-    if field_name == "image" {
-      let original_filename = content_disposition
-        .unwrap()
-        .get_filename()
-        .unwrap_or("file");
-      let content_type = field.content_type().unwrap().to_string();
-
-      let extension = get_mime_extensions_str(&content_type)
-        .and_then(|exts| exts.first().map(|e| format!(".{}", e)))
-        .unwrap_or_default();
-
-      let new_filename = format!("{}{}", Uuid::new_v4(), extension);
-      println!("Original filename: {original_filename}, New: {new_filename}, Type: {content_type}");
-
-      // Save file
-      let filepath = format!("./media/images/{}/{}", image_type, new_filename);
-      let mut f = std::fs::File::create(&filepath).unwrap();
-      while let Some(chunk) = field.next().await {
-        let data = chunk.unwrap();
-        f.write_all(&data).unwrap();
-      }
-
-      image_name = new_filename;
-    }
-  }
-  Ok(image_name)
-}
@@ -0,0 +1,491 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use actix_multipart::{Field, Multipart, MultipartError};
+use actix_web::web::Bytes;
+use futures_util::StreamExt;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::config::MediaConfig;
+use crate::storage::StorageError;
+use crate::{log, metrics};
+
+/// Name of the multipart field that carries the image
+const IMAGE_FIELD: &str = "image";
+
+/// Suffix of uploads that are still being written
+pub const PARTIAL_SUFFIX: &str = ".part";
+
+/// Bytes needed to tell the supported formats apart
+const SNIFF_LEN: usize = 12;
+
+/// Most bytes accepted in all fields other than `image` together. They are read and discarded.
+const MAX_OTHER_FIELDS_BYTES: u64 = 64 * 1024;
+
+#[derive(Debug)]
+pub enum UploadError {
+  /// The request has no `image` field
+  MissingImage,
+  /// More than one `image` field was sent
+  MultipleImages,
+  /// The image is larger than `media.max_upload_bytes`
+  TooLarge { limit: u64 },
+  /// The fields other than `image` are larger than `MAX_OTHER_FIELDS_BYTES`
+  FieldsTooLarge { limit: u64 },
+  /// The content is not a PNG, JPEG, WebP or GIF image, whatever the client claims
+  UnsupportedType,
+  /// The content looks like a supported format but cannot be decoded
+  InvalidImage(String),
+  /// The multipart body could not be read
+  Multipart(MultipartError),
+  /// The file could not be written
+  Io(std::io::Error),
+  /// The processed image could not be stored
+  Storage(StorageError),
+}
+
+impl fmt::Display for UploadError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      UploadError::MissingImage => write!(f, "The '{}' field is required", IMAGE_FIELD),
+      UploadError::MultipleImages => write!(f, "Only one '{}' field is allowed", IMAGE_FIELD),
+      UploadError::TooLarge { limit } => write!(f, "Image is larger than {} bytes", limit),
+      UploadError::FieldsTooLarge { limit } => write!(
+        f,
+        "Fields other than '{}' are larger than {} bytes",
+        IMAGE_FIELD, limit
+      ),
+      UploadError::UnsupportedType => write!(f, "Image must be a PNG, JPEG, WebP or GIF"),
+      UploadError::InvalidImage(ref msg) => write!(f, "Image could not be read: {}", msg),
+      UploadError::Multipart(ref err) => write!(f, "Invalid multipart body: {}", err),
+      UploadError::Io(ref err) => write!(f, "Failed to store image: {}", err),
+      UploadError::Storage(ref err) => write!(f, "Failed to store image: {}", err),
+    }
+  }
+}
+
+impl From<MultipartError> for UploadError {
+  fn from(err: MultipartError) -> Self {
+    UploadError::Multipart(err)
+  }
+}
+
+impl From<std::io::Error> for UploadError {
+  fn from(err: std::io::Error) -> Self {
+    UploadError::Io(err)
+  }
+}
+
+/// Image formats accepted for upload, recognised by their magic bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+  Png,
+  Jpeg,
+  WebP,
+  Gif,
+}
+
+impl ImageFormat {
+  /// Detects the format from the first bytes of a file
+  pub fn sniff(head: &[u8]) -> Option<ImageFormat> {
+    if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+      Some(ImageFormat::Png)
+    } else if head.starts_with(b"\xff\xd8\xff") {
+      Some(ImageFormat::Jpeg)
+    } else if head.len() >= SNIFF_LEN && &head[0..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+      Some(ImageFormat::WebP)
+    } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+      Some(ImageFormat::Gif)
+    } else {
+      None
+    }
+  }
+
+  pub fn extension(&self) -> &'static str {
+    match self {
+      ImageFormat::Png => "png",
+      ImageFormat::Jpeg => "jpg",
+      ImageFormat::WebP => "webp",
+      ImageFormat::Gif => "gif",
+    }
+  }
+
+  pub fn mime_type(&self) -> &'static str {
+    match self {
+      ImageFormat::Png => "image/png",
+      ImageFormat::Jpeg => "image/jpeg",
+      ImageFormat::WebP => "image/webp",
+      ImageFormat::Gif => "image/gif",
+    }
+  }
+}
+
+/// An upload that is written under a temporary name and deleted again unless it is completed,
+/// so a failed or cancelled request does not leave a half-written image behind
+struct PartialUpload {
+  path: PathBuf,
+  completed: bool,
+}
+
+impl PartialUpload {
+  async fn complete(mut self, destination: &Path) -> std::io::Result<()> {
+    tokio::fs::rename(&self.path, destination).await?;
+    self.completed = true;
+    Ok(())
+  }
+}
+
+impl Drop for PartialUpload {
+  fn drop(&mut self) {
+    if !self.completed {
+      let _ = std::fs::remove_file(&self.path);
+    }
+  }
+}
+
+/// Stores the `image` field of a multipart body under `{media.dir}/images/{image_type}` and
+/// returns the new file name. The file type is taken from the content, not from the name or
+/// content type sent by the client. Other fields are discarded, up to `MAX_OTHER_FIELDS_BYTES`
+/// in total. On any error the image is not kept.
+pub async fn save_image(
+  payload: &mut Multipart,
+  media: &MediaConfig,
+  image_type: &str,
+) -> Result<String, UploadError> {
+  let directory = media.dir.join("images").join(image_type);
+  let mut stored: Option<String> = None;
+
+  let result = async {
+    let mut other_bytes = 0;
+    while let Some(item) = payload.next().await {
+      let mut field = item?;
+      let is_image = field
+        .content_disposition()
+        .and_then(|cd| cd.get_name())
+        .is_some_and(|name| name == IMAGE_FIELD);
+
+      if !is_image {
+        // Drain the field so the next one can be read
+        while let Some(chunk) = field.next().await {
+          other_bytes += chunk?.len() as u64;
+          if other_bytes > MAX_OTHER_FIELDS_BYTES {
+            return Err(UploadError::FieldsTooLarge {
+              limit: MAX_OTHER_FIELDS_BYTES,
+            });
+          }
+        }
+        continue;
+      }
+
+      if stored.is_some() {
+        return Err(UploadError::MultipleImages);
+      }
+      stored = Some(write_field(&mut field, &directory, media.max_upload_bytes).await?);
+    }
+    Ok(())
+  }
+  .await;
+
+  match (result, stored) {
+    (Ok(()), Some(name)) => Ok(name),
+    (Ok(()), None) => Err(UploadError::MissingImage),
+    (Err(e), stored) => {
+      // The image may already be stored when a later field fails
+      if let Some(name) = stored {
+        delete_image(&media.dir, &name, image_type).await.ok();
+      }
+      Err(e)
+    }
+  }
+}
+
+/// Streams one field to a temporary file, checking its type and size on the way, and moves it
+/// into place once it is complete
+async fn write_field(
+  field: &mut Field,
+  directory: &Path,
+  limit: u64,
+) -> Result<String, UploadError> {
+  // Collect enough of the start of the file to recognise the format before writing anything
+  let mut head = Vec::with_capacity(SNIFF_LEN);
+  let mut rest = None;
+  while head.len() < SNIFF_LEN {
+    match field.next().await {
+      Some(chunk) => {
+        let chunk = chunk?;
+        let take = (SNIFF_LEN - head.len()).min(chunk.len());
+        head.extend_from_slice(&chunk[..take]);
+        if take < chunk.len() {
+          rest = Some(chunk.slice(take..));
+        }
+      }
+      None => break,
+    }
+  }
+  let format = ImageFormat::sniff(&head).ok_or(UploadError::UnsupportedType)?;
+
+  let name = format!("{}.{}", Uuid::new_v4(), format.extension());
+  let upload = PartialUpload {
+    path: directory.join(format!("{}{}", name, PARTIAL_SUFFIX)),
+    completed: false,
+  };
+  // The directory may not exist yet, e.g. for the first upload to a fresh media directory
+  tokio::fs::create_dir_all(directory).await?;
+  let mut file = tokio::fs::File::create(&upload.path).await?;
+
+  let mut size = 0;
+  let mut chunk = Some(Bytes::from(head));
+  while let Some(data) = chunk {
+    size += data.len() as u64;
+    if size > limit {
+      return Err(UploadError::TooLarge { limit });
+    }
+    file.write_all(&data).await?;
+    chunk = match rest.take() {
+      Some(data) => Some(data),
+      None => field.next().await.transpose()?,
+    };
+  }
+  file.sync_all().await?;
+  drop(file);
+
+  upload.complete(&directory.join(&name)).await?;
+  metrics::record_upload(size);
+  log::log(
+    log::Level::Debug,
+    "Image stored",
+    &[
+      ("file", json!(name)),
+      ("type", json!(format.mime_type())),
+      ("bytes", json!(size)),
+    ],
+  );
+  Ok(name)
+}
+
+/// Writes a complete file under a temporary name and renames it into place
+pub async fn store_file(directory: &Path, name: &str, data: &[u8]) -> std::io::Result<()> {
+  let upload = PartialUpload {
+    path: directory.join(format!("{}{}", name, PARTIAL_SUFFIX)),
+    completed: false,
+  };
+  tokio::fs::create_dir_all(directory).await?;
+  let mut file = tokio::fs::File::create(&upload.path).await?;
+  file.write_all(data).await?;
+  file.sync_all().await?;
+  drop(file);
+  upload.complete(&directory.join(name)).await
+}
+
+/// Removes a stored image. A file that is already gone is not treated as an error.
+pub async fn delete_image(
+  media_dir: &Path,
+  image_name: &str,
+  image_type: &str,
+) -> Result<(), String> {
+  if image_name.is_empty() || image_name.contains('/') || image_name.contains('\\') {
+    return Ok(());
+  }
+
+  let filepath = media_dir.join("images").join(image_type).join(image_name);
+  match tokio::fs::remove_file(&filepath).await {
+    Ok(()) => Ok(()),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+    Err(e) => Err(format!("Failed to delete {}: {}", filepath.display(), e)),
+  }
+}
+
+/// Deletes uploads that were left half-written, e.g. by requests cut off during shutdown.
+/// Returns how many files were removed.
+pub fn remove_partial_uploads(media_dir: &Path) -> usize {
+  let Ok(types) = std::fs::read_dir(media_dir.join("images")) else {
+    return 0;
+  };
+
+  let mut removed = 0;
+  for directory in types.flatten().map(|entry| entry.path()) {
+    let Ok(files) = std::fs::read_dir(&directory) else {
+      continue;
+    };
+    for path in files.flatten().map(|entry| entry.path()) {
+      let partial = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(PARTIAL_SUFFIX));
+      if partial && std::fs::remove_file(&path).is_ok() {
+        removed += 1;
+      }
+    }
+  }
+  removed
+}
+
+#[cfg(test)]
+mod tests {
+  use actix_web::http::header::{CONTENT_TYPE, HeaderMap, HeaderValue};
+  use futures_util::stream;
+
+  use super::*;
+
+  const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+  #[test]
+  fn sniff_recognises_magic_bytes() {
+    assert_eq!(ImageFormat::sniff(PNG), Some(ImageFormat::Png));
+    assert_eq!(
+      ImageFormat::sniff(b"\xff\xd8\xff\xe0\0\x10JFIF"),
+      Some(ImageFormat::Jpeg)
+    );
+    assert_eq!(
+      ImageFormat::sniff(b"RIFF\x24\0\0\0WEBPVP8 "),
+      Some(ImageFormat::WebP)
+    );
+    assert_eq!(ImageFormat::sniff(b"GIF87a\x01\0"), Some(ImageFormat::Gif));
+    assert_eq!(ImageFormat::sniff(b"GIF89a\x01\0"), Some(ImageFormat::Gif));
+  }
+
+  #[test]
+  fn sniff_rejects_other_content() {
+    assert_eq!(ImageFormat::sniff(b""), None);
+    assert_eq!(ImageFormat::sniff(b"<svg xmlns="), None);
+    assert_eq!(ImageFormat::sniff(b"%PDF-1.7\n"), None);
+    // A RIFF container that is not WebP, and one too short to tell
+    assert_eq!(ImageFormat::sniff(b"RIFF\x24\0\0\0WAVEfmt "), None);
+    assert_eq!(ImageFormat::sniff(b"RIFF\x24\0\0\0WEB"), None);
+    // Truncated signatures
+    assert_eq!(ImageFormat::sniff(b"\x89PNG\r\n"), None);
+    assert_eq!(ImageFormat::sniff(b"GIF8"), None);
+  }
+
+  const BOUNDARY: &str = "upload-test-boundary";
+
+  fn part(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut body = format!(
+      "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"x\"\r\n\
+       Content-Type: application/octet-stream\r\n\r\n",
+      BOUNDARY, name
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(b"\r\n");
+    body
+  }
+
+  /// A multipart body with the given fields, delivered in small chunks
+  fn multipart(fields: &[(&str, &[u8])]) -> Multipart {
+    let mut body: Vec<u8> = fields
+      .iter()
+      .flat_map(|(name, data)| part(name, data))
+      .collect();
+    body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+    let chunks: Vec<Result<Bytes, actix_web::error::PayloadError>> = body
+      .chunks(1000)
+      .map(|c| Ok(Bytes::copy_from_slice(c)))
+      .collect();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      CONTENT_TYPE,
+      HeaderValue::from_str(&format!("multipart/form-data; boundary={}", BOUNDARY)).unwrap(),
+    );
+    Multipart::new(&headers, stream::iter(chunks))
+  }
+
+  fn media(limit: u64) -> MediaConfig {
+    let dir = std::env::temp_dir().join(format!("upload-test-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join("images").join("app")).unwrap();
+    MediaConfig {
+      dir,
+      max_upload_bytes: limit,
+      ..Default::default()
+    }
+  }
+
+  fn stored_files(media: &MediaConfig) -> Vec<String> {
+    std::fs::read_dir(media.dir.join("images").join("app"))
+      .unwrap()
+      .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+      .collect()
+  }
+
+  fn png(size: usize) -> Vec<u8> {
+    let mut data = PNG.to_vec();
+    data.resize(size, 0);
+    data
+  }
+
+  #[actix_web::test]
+  async fn stores_an_image_up_to_the_limit() {
+    let media = media(4096);
+    let image = png(4096);
+    let mut payload = multipart(&[("name", b"ignored"), (IMAGE_FIELD, &image)]);
+
+    let name = save_image(&mut payload, &media, "app").await.unwrap();
+    assert!(name.ends_with(".png"));
+    assert_eq!(stored_files(&media), vec![name.clone()]);
+    let stored = std::fs::read(media.dir.join("images").join("app").join(&name)).unwrap();
+    assert_eq!(stored, image);
+    std::fs::remove_dir_all(&media.dir).ok();
+  }
+
+  #[actix_web::test]
+  async fn creates_a_missing_image_directory() {
+    let media = media(4096);
+    std::fs::remove_dir_all(media.dir.join("images")).unwrap();
+    let mut payload = multipart(&[(IMAGE_FIELD, &png(64))]);
+
+    let name = save_image(&mut payload, &media, "app").await.unwrap();
+    assert_eq!(stored_files(&media), vec![name]);
+    std::fs::remove_dir_all(&media.dir).ok();
+  }
+
+  #[actix_web::test]
+  async fn rejects_an_image_over_the_limit() {
+    let media = media(4096);
+    let mut payload = multipart(&[(IMAGE_FIELD, &png(4097))]);
+
+    let result = save_image(&mut payload, &media, "app").await;
+    assert!(matches!(result, Err(UploadError::TooLarge { limit: 4096 })));
+    assert!(stored_files(&media).is_empty());
+    std::fs::remove_dir_all(&media.dir).ok();
+  }
+
+  #[actix_web::test]
+  async fn rejects_content_that_is_not_an_image() {
+    let media = media(4096);
+    let mut payload = multipart(&[(IMAGE_FIELD, b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>")]);
+
+    let result = save_image(&mut payload, &media, "app").await;
+    assert!(matches!(result, Err(UploadError::UnsupportedType)));
+    assert!(stored_files(&media).is_empty());
+    std::fs::remove_dir_all(&media.dir).ok();
+  }
+
+  #[actix_web::test]
+  async fn deletes_the_image_when_a_later_field_fails() {
+    let media = media(4096);
+    let image = png(100);
+    let mut payload = multipart(&[(IMAGE_FIELD, &image), (IMAGE_FIELD, &image)]);
+    let result = save_image(&mut payload, &media, "app").await;
+    assert!(matches!(result, Err(UploadError::MultipleImages)));
+    assert!(stored_files(&media).is_empty());
+
+    let padding = vec![b'x'; MAX_OTHER_FIELDS_BYTES as usize + 1];
+    let mut payload = multipart(&[(IMAGE_FIELD, &image), ("padding", &padding)]);
+    let result = save_image(&mut payload, &media, "app").await;
+    assert!(matches!(result, Err(UploadError::FieldsTooLarge { .. })));
+    assert!(stored_files(&media).is_empty());
+    std::fs::remove_dir_all(&media.dir).ok();
+  }
+
+  #[actix_web::test]
+  async fn requires_an_image_field() {
+    let media = media(4096);
+    let mut payload = multipart(&[("name", b"no image")]);
+    let result = save_image(&mut payload, &media, "app").await;
+    assert!(matches!(result, Err(UploadError::MissingImage)));
+    std::fs::remove_dir_all(&media.dir).ok();
+  }
+}